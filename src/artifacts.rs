@@ -0,0 +1,497 @@
+use crate::checksum::file_checksum;
+use crate::durable::{durable_rename, read_json, write_json};
+use crate::types::{Artifact, ArtifactInstallType, OtaConfig};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::Permissions;
+use std::io::{BufRead, BufReader};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info, warn};
+
+/// Magic bytes at the start of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// An artifact that has been downloaded and verified, ready to install
+#[derive(Debug, Clone, PartialEq)]
+pub struct StagedArtifact {
+    pub artifact: Artifact,
+    pub path: PathBuf,
+}
+
+/// One installed artifact and where its previous content was moved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledArtifact {
+    pub name: String,
+    pub destination: String,
+    /// `None` when nothing existed at the destination before
+    pub backup: Option<String>,
+}
+
+/// Persisted record of the artifacts installed with the current kernel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactState {
+    pub version: String,
+    pub artifacts: Vec<InstalledArtifact>,
+}
+
+/// Path of the artifact state record for a configuration
+pub fn artifact_state_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("artifacts.json")
+}
+
+/// Load the artifact state record, if one has been written
+pub async fn load_artifact_state(config: &OtaConfig) -> Result<Option<ArtifactState>> {
+    read_json(&artifact_state_path(config), "artifact state").await
+}
+
+/// Persist the artifact state record
+pub async fn save_artifact_state(config: &OtaConfig, state: &ArtifactState) -> Result<()> {
+    write_json(&artifact_state_path(config), state, "artifact state").await?;
+
+    debug!(
+        "Saved artifact state for {} ({} artifacts)",
+        state.version,
+        state.artifacts.len()
+    );
+    Ok(())
+}
+
+/// Remove the artifact state record
+pub async fn clear_artifact_state(config: &OtaConfig) -> Result<()> {
+    let path = artifact_state_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove artifact state: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Reject artifact names that would escape the download directory
+pub fn validate_artifact_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        anyhow::bail!("Invalid artifact name: {:?}", name);
+    }
+    Ok(())
+}
+
+/// Parse an octal mode string such as "0644" or "0o755"
+pub fn parse_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim().trim_start_matches("0o");
+    let value = u32::from_str_radix(digits, 8)
+        .with_context(|| format!("Invalid artifact mode: {:?}", mode))?;
+    if value > 0o7777 {
+        anyhow::bail!("Invalid artifact mode: {:?}", mode);
+    }
+    Ok(value)
+}
+
+/// Check a downloaded artifact against its size and checksum
+pub async fn verify_artifact_file(path: &Path, artifact: &Artifact) -> Result<()> {
+    let size = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to read artifact: {}", path.display()))?
+        .len();
+
+    if size != artifact.file_size {
+        anyhow::bail!(
+            "Artifact {} size mismatch: expected {}, got {}",
+            artifact.name,
+            artifact.file_size,
+            size
+        );
+    }
+
+    let checksum = file_checksum(path).await?;
+    if checksum != artifact.checksum {
+        anyhow::bail!(
+            "Artifact {} checksum mismatch: expected {}, got {}",
+            artifact.name,
+            artifact.checksum,
+            checksum
+        );
+    }
+
+    Ok(())
+}
+
+/// Path the previous content of a destination is moved to
+fn backup_path_for(destination: &Path) -> PathBuf {
+    let mut path = destination.as_os_str().to_owned();
+    path.push(".ota_backup");
+    PathBuf::from(path)
+}
+
+/// Path new content is assembled at before it is moved into place
+fn staging_path_for(destination: &Path) -> PathBuf {
+    let mut path = destination.as_os_str().to_owned();
+    path.push(".ota_new");
+    PathBuf::from(path)
+}
+
+/// Remove a file or directory tree, ignoring paths that don't exist
+async fn remove_path(path: &Path) -> Result<()> {
+    let metadata = match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to stat {}", path.display())),
+    };
+
+    if metadata.is_dir() {
+        tokio::fs::remove_dir_all(path).await
+    } else {
+        tokio::fs::remove_file(path).await
+    }
+    .with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// Extract a tar archive, transparently decompressing zstd
+pub fn extract_archive(archive: &Path, target: &Path) -> Result<()> {
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("Failed to open archive: {}", archive.display()))?;
+    let mut reader = BufReader::new(file);
+
+    let compressed = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+    let result = if compressed {
+        let decoder = zstd::stream::read::Decoder::with_buffer(reader)
+            .context("Failed to initialize archive decoder")?;
+        tar::Archive::new(decoder).unpack(target)
+    } else {
+        tar::Archive::new(reader).unpack(target)
+    };
+
+    result.with_context(|| format!("Failed to extract archive: {}", archive.display()))
+}
+
+/// Flush a staged file, or every entry of a staged directory, to stable storage
+fn sync_tree(path: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    if metadata.file_type().is_symlink() {
+        return Ok(());
+    }
+
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory {}", path.display()))?
+        {
+            sync_tree(&entry?.path())?;
+        }
+    }
+
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {} for sync", path.display()))?;
+    match file.sync_all() {
+        // Some filesystems can't sync a directory handle
+        Err(e) if metadata.is_dir() && e.raw_os_error() == Some(libc::EINVAL) => Ok(()),
+        result => result.with_context(|| format!("Failed to sync {}", path.display())),
+    }
+}
+
+/// Install all artifacts as one unit, undoing the ones already placed on failure
+pub async fn install_artifacts(
+    config: &OtaConfig,
+    version: &str,
+    staged: &[StagedArtifact],
+) -> Result<Vec<InstalledArtifact>> {
+    // Check everything before touching the system
+    for item in staged {
+        verify_artifact_file(&item.path, &item.artifact).await?;
+    }
+
+    let mut installed = Vec::new();
+    for item in staged {
+        match install_artifact(item).await {
+            Ok(record) => installed.push(record),
+            Err(e) => {
+                error!("Installing artifact {} failed: {}", item.artifact.name, e);
+                if let Err(restore_err) = restore_artifacts(&installed).await {
+                    return Err(anyhow::anyhow!(
+                        "Artifact installation failed and restore failed: {}. Manual intervention required.",
+                        restore_err
+                    ));
+                }
+                return Err(e.context(format!("Failed to install artifact {}", item.artifact.name)));
+            }
+        }
+    }
+
+    let state = ArtifactState {
+        version: version.to_string(),
+        artifacts: installed.clone(),
+    };
+    if let Err(e) = save_artifact_state(config, &state).await {
+        restore_artifacts(&installed).await?;
+        return Err(e);
+    }
+
+    info!("Installed {} artifacts for {}", installed.len(), version);
+    Ok(installed)
+}
+
+/// Place one artifact at its destination, keeping the old content as a backup
+async fn install_artifact(item: &StagedArtifact) -> Result<InstalledArtifact> {
+    let artifact = &item.artifact;
+    let destination = PathBuf::from(&artifact.destination);
+    let staging = staging_path_for(&destination);
+    let backup = backup_path_for(&destination);
+    let mode = artifact.mode.as_deref().map(parse_mode).transpose()?;
+
+    debug!(
+        "Installing artifact {} to {}",
+        artifact.name,
+        destination.display()
+    );
+
+    if let Some(parent) = destination.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    // Assemble the new content next to the destination first
+    remove_path(&staging).await?;
+    match artifact.install_type {
+        ArtifactInstallType::File => {
+            tokio::fs::copy(&item.path, &staging)
+                .await
+                .with_context(|| format!("Failed to copy artifact to {}", staging.display()))?;
+        }
+        ArtifactInstallType::Archive => {
+            tokio::fs::create_dir_all(&staging).await?;
+            let archive = item.path.clone();
+            let target = staging.clone();
+            tokio::task::spawn_blocking(move || extract_archive(&archive, &target))
+                .await
+                .context("Archive extraction task failed")??;
+        }
+    }
+    if let Some(mode) = mode {
+        tokio::fs::set_permissions(&staging, Permissions::from_mode(mode)).await?;
+    }
+    let synced = staging.clone();
+    tokio::task::spawn_blocking(move || sync_tree(&synced))
+        .await
+        .context("Artifact sync task failed")??;
+
+    // Move the old content aside, then the new content into place
+    let had_previous = tokio::fs::symlink_metadata(&destination).await.is_ok();
+    if had_previous {
+        remove_path(&backup).await?;
+        durable_rename(&destination, &backup)
+            .await
+            .with_context(|| format!("Failed to back up {}", destination.display()))?;
+    }
+
+    if let Err(e) = durable_rename(&staging, &destination).await {
+        if had_previous && let Err(restore_err) = durable_rename(&backup, &destination).await {
+            warn!(
+                "Failed to put back {}: {}",
+                destination.display(),
+                restore_err
+            );
+        }
+        let _ = remove_path(&staging).await;
+        return Err(e).with_context(|| format!("Failed to install {}", destination.display()));
+    }
+
+    Ok(InstalledArtifact {
+        name: artifact.name.clone(),
+        destination: artifact.destination.clone(),
+        backup: had_previous.then(|| backup.to_string_lossy().to_string()),
+    })
+}
+
+/// Undo installed artifacts in reverse order
+pub async fn restore_artifacts(installed: &[InstalledArtifact]) -> Result<()> {
+    for record in installed.iter().rev() {
+        let destination = Path::new(&record.destination);
+        info!("Restoring artifact {}", record.name);
+
+        match &record.backup {
+            Some(backup) if Path::new(backup).exists() => {
+                remove_path(destination).await?;
+                durable_rename(Path::new(backup), destination)
+                    .await
+                    .with_context(|| format!("Failed to restore {}", destination.display()))?;
+            }
+            Some(backup) => {
+                anyhow::bail!("Backup of artifact {} is missing: {}", record.name, backup);
+            }
+            // Nothing was there before the update
+            None => remove_path(destination).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Roll back the artifacts recorded for the current kernel, if any
+pub async fn rollback_artifacts(config: &OtaConfig) -> Result<()> {
+    let Some(state) = load_artifact_state(config).await? else {
+        return Ok(());
+    };
+
+    warn!(
+        "Rolling back {} artifacts installed with {}",
+        state.artifacts.len(),
+        state.version
+    );
+    restore_artifacts(&state.artifacts).await?;
+    clear_artifact_state(config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::checksum_of;
+    use tempfile::TempDir;
+
+    fn create_test_config(temp_dir: &TempDir) -> OtaConfig {
+        OtaConfig {
+            state_path: temp_dir.path().join("state").to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        }
+    }
+
+    fn stage_file(
+        temp_dir: &TempDir,
+        name: &str,
+        content: &[u8],
+        destination: &Path,
+    ) -> StagedArtifact {
+        let path = temp_dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+
+        StagedArtifact {
+            artifact: Artifact {
+                name: name.to_string(),
+                download_url: format!("/artifacts/{}", name),
+                destination: destination.to_string_lossy().to_string(),
+                file_size: content.len() as u64,
+                checksum: checksum_of(content),
+                mode: Some("0640".to_string()),
+                install_type: ArtifactInstallType::File,
+            },
+            path,
+        }
+    }
+
+    fn stage_archive(temp_dir: &TempDir, name: &str, destination: &Path) -> StagedArtifact {
+        let mut builder = tar::Builder::new(Vec::new());
+        let content = b"module data";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "kernel/drivers/test.ko", &content[..])
+            .unwrap();
+        let tarball = builder.into_inner().unwrap();
+        let compressed = zstd::encode_all(&tarball[..], 3).unwrap();
+
+        let mut staged = stage_file(temp_dir, name, &compressed, destination);
+        staged.artifact.install_type = ArtifactInstallType::Archive;
+        staged.artifact.mode = Some("0755".to_string());
+        staged
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_mode("0o755").unwrap(), 0o755);
+        assert!(parse_mode("0999").is_err());
+        assert!(parse_mode("77777").is_err());
+    }
+
+    #[test]
+    fn test_artifact_names() {
+        assert!(validate_artifact_name("bcm2711-rpi-4-b.dtb").is_ok());
+        assert!(validate_artifact_name("../etc/shadow").is_err());
+        assert!(validate_artifact_name("..").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_install_and_rollback_artifacts() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        let dtb = temp_dir.path().join("boot/bcm2711.dtb");
+        std::fs::create_dir_all(dtb.parent().unwrap()).unwrap();
+        std::fs::write(&dtb, b"old dtb").unwrap();
+        let modules = temp_dir.path().join("lib/modules/6.1.0");
+
+        let staged = vec![
+            stage_file(&temp_dir, "bcm2711.dtb", b"new dtb", &dtb),
+            stage_archive(&temp_dir, "modules.tar.zst", &modules),
+        ];
+        for item in &staged {
+            verify_artifact_file(&item.path, &item.artifact)
+                .await
+                .unwrap();
+        }
+
+        install_artifacts(&config, "1.0.0", &staged).await.unwrap();
+
+        assert_eq!(std::fs::read(&dtb).unwrap(), b"new dtb");
+        let mode = std::fs::metadata(&dtb).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+        assert_eq!(
+            std::fs::read(modules.join("kernel/drivers/test.ko")).unwrap(),
+            b"module data"
+        );
+
+        rollback_artifacts(&config).await.unwrap();
+
+        assert_eq!(std::fs::read(&dtb).unwrap(), b"old dtb");
+        assert!(!modules.exists());
+        assert!(load_artifact_state(&config).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_artifact_restores_earlier_ones() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        let dtb = temp_dir.path().join("boot/bcm2711.dtb");
+        std::fs::create_dir_all(dtb.parent().unwrap()).unwrap();
+        std::fs::write(&dtb, b"old dtb").unwrap();
+
+        let mut broken = stage_file(
+            &temp_dir,
+            "overlays.tar",
+            &[b'x'; 1024],
+            &temp_dir.path().join("boot/overlays"),
+        );
+        broken.artifact.install_type = ArtifactInstallType::Archive;
+
+        let staged = vec![
+            stage_file(&temp_dir, "bcm2711.dtb", b"new dtb", &dtb),
+            broken,
+        ];
+
+        assert!(install_artifacts(&config, "1.0.0", &staged).await.is_err());
+        assert_eq!(std::fs::read(&dtb).unwrap(), b"old dtb");
+        assert!(!temp_dir.path().join("boot/overlays").exists());
+        assert!(load_artifact_state(&config).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_corrupt_artifact() {
+        let temp_dir = TempDir::new().unwrap();
+        let item = stage_file(
+            &temp_dir,
+            "bcm2711.dtb",
+            b"new dtb",
+            &temp_dir.path().join("boot/bcm2711.dtb"),
+        );
+        std::fs::write(&item.path, b"bad dtb").unwrap();
+
+        assert!(
+            verify_artifact_file(&item.path, &item.artifact)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use crate::durable::{read_json, sync_parent, write_json};
+use crate::types::OtaConfig;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// A kernel image kept in the backup store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupEntry {
+    /// Version of the backed up kernel, when it was recorded
+    pub version: Option<String>,
+    /// Anti-rollback security version the kernel was installed with
+    #[serde(default)]
+    pub security_version: u64,
+    pub checksum: String,
+    pub size: u64,
+    /// Path of the backup image
+    pub file: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Index of the backup store, oldest entry first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BackupIndex {
+    pub entries: Vec<BackupEntry>,
+}
+
+impl BackupIndex {
+    /// Newest backup of `version`
+    pub fn find(&self, version: &str) -> Option<&BackupEntry> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.version.as_deref() == Some(version))
+    }
+}
+
+/// Directory the versioned backups are written to
+pub fn backup_dir(config: &OtaConfig) -> PathBuf {
+    match &config.backups.directory {
+        Some(directory) => PathBuf::from(directory),
+        None => Path::new(&config.backup_path)
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("kernel_backups"),
+    }
+}
+
+/// Path of the backup index for a configuration
+pub fn backup_index_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("backup_index.json")
+}
+
+/// File name for a backup of `version` taken at `created_at`
+pub fn backup_file_name(version: Option<&str>, created_at: DateTime<Utc>) -> String {
+    // Versions come from the server, so keep only characters safe in a file name
+    let version: String = version
+        .unwrap_or("unknown")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!(
+        "kernel_{}_{}.img",
+        version,
+        created_at.format("%Y%m%d_%H%M%S")
+    )
+}
+
+/// Load the backup index; an empty index when none has been written
+pub async fn load_backup_index(config: &OtaConfig) -> Result<BackupIndex> {
+    let index: BackupIndex = read_json(&backup_index_path(config), "backup index")
+        .await?
+        .unwrap_or_default();
+
+    debug!("Loaded backup index with {} entries", index.entries.len());
+    Ok(index)
+}
+
+/// Persist the backup index, synced before returning
+pub async fn save_backup_index(config: &OtaConfig, index: &BackupIndex) -> Result<()> {
+    write_json(&backup_index_path(config), index, "backup index").await
+}
+
+/// Drop the oldest backups beyond `keep`, and entries whose file is gone
+pub async fn prune_backups(index: &mut BackupIndex, keep: usize) -> Result<()> {
+    index.entries.retain(|entry| {
+        let exists = Path::new(&entry.file).exists();
+        if !exists {
+            warn!(
+                "Backup {} is missing, dropping it from the index",
+                entry.file
+            );
+        }
+        exists
+    });
+
+    let excess = index.entries.len().saturating_sub(keep);
+    for entry in index.entries.drain(..excess) {
+        let path = Path::new(&entry.file);
+        tokio::fs::remove_file(path)
+            .await
+            .with_context(|| format!("Failed to remove old backup: {}", path.display()))?;
+        sync_parent(path).await?;
+        info!(
+            "Removed old backup of {}: {}",
+            entry.version.as_deref().unwrap_or("unknown version"),
+            entry.file
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    fn entry(dir: &Path, version: &str, checksum: &str) -> BackupEntry {
+        let file = dir.join(format!("kernel_{}.img", version));
+        std::fs::write(&file, version).unwrap();
+        BackupEntry {
+            version: Some(version.to_string()),
+            security_version: 0,
+            checksum: checksum.to_string(),
+            size: version.len() as u64,
+            file: file.to_string_lossy().to_string(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_backup_file_name() {
+        let at = Utc.with_ymd_and_hms(2025, 6, 16, 10, 30, 0).unwrap();
+        assert_eq!(
+            backup_file_name(Some("6.1.0-rpi+1"), at),
+            "kernel_6.1.0-rpi+1_20250616_103000.img"
+        );
+        assert_eq!(
+            backup_file_name(Some("../../etc"), at),
+            "kernel_.._.._etc_20250616_103000.img"
+        );
+        assert_eq!(
+            backup_file_name(None, at),
+            "kernel_unknown_20250616_103000.img"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_roundtrip_and_lookup() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+        assert_eq!(
+            load_backup_index(&config).await.unwrap(),
+            BackupIndex::default()
+        );
+
+        let index = BackupIndex {
+            entries: vec![
+                entry(temp_dir.path(), "1.0.0", "sha256:a"),
+                entry(temp_dir.path(), "1.1.0", "sha256:b"),
+            ],
+        };
+        save_backup_index(&config, &index).await.unwrap();
+
+        let loaded = load_backup_index(&config).await.unwrap();
+        assert_eq!(loaded, index);
+        assert_eq!(loaded.find("1.0.0").unwrap().checksum, "sha256:a");
+        assert!(loaded.find("2.0.0").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_newest() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut index = BackupIndex {
+            entries: vec![
+                entry(temp_dir.path(), "1.0.0", "sha256:a"),
+                entry(temp_dir.path(), "1.1.0", "sha256:b"),
+                entry(temp_dir.path(), "1.2.0", "sha256:c"),
+                entry(temp_dir.path(), "1.3.0", "sha256:d"),
+            ],
+        };
+        let oldest = index.entries[0].file.clone();
+
+        // A backup deleted by hand is dropped rather than counted
+        std::fs::remove_file(&index.entries[1].file).unwrap();
+
+        prune_backups(&mut index, 1).await.unwrap();
+        let versions: Vec<_> = index
+            .entries
+            .iter()
+            .map(|entry| entry.version.as_deref().unwrap())
+            .collect();
+        assert_eq!(versions, ["1.3.0"]);
+        assert!(!Path::new(&oldest).exists());
+        assert!(Path::new(&index.entries[0].file).exists());
+    }
+}
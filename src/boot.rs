@@ -0,0 +1,359 @@
+use crate::durable::{read_json, write_json};
+use crate::types::{BootConfirmationConfig, KernelMetadata, OtaConfig};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Marker written at install time and cleared once the new kernel is confirmed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingConfirmation {
+    pub version: String,
+    pub checksum: String,
+    pub installed_at: chrono::DateTime<chrono::Utc>,
+    /// Boot the install happened in; confirmation waits for a different one
+    pub install_boot_id: Option<String>,
+    /// Last boot the daemon counted as an attempt
+    pub last_boot_id: Option<String>,
+    pub boot_attempts: u32,
+}
+
+/// Outcome of checking a pending confirmation at startup
+#[derive(Debug, Clone, PartialEq)]
+pub enum BootCheck {
+    /// No update is waiting for confirmation
+    NothingPending,
+    /// The device has not rebooted into the new kernel yet
+    AwaitingReboot,
+    /// The new kernel booted and passed its health checks
+    Confirmed(String),
+    /// The new kernel must be reverted for the given reason
+    Revert { version: String, reason: String },
+}
+
+/// Path of the pending confirmation marker for a configuration
+pub fn pending_confirmation_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("pending_confirmation.json")
+}
+
+/// Identifier of the current boot, if the kernel exposes one
+pub async fn current_boot_id() -> Option<String> {
+    tokio::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .await
+        .ok()
+        .map(|id| id.trim().to_string())
+}
+
+/// Release string of the running kernel (`uname -r`)
+pub fn running_kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().to_string())
+}
+
+/// Load the pending confirmation marker, if present
+pub async fn load_pending_confirmation(config: &OtaConfig) -> Result<Option<PendingConfirmation>> {
+    read_json(&pending_confirmation_path(config), "confirmation marker").await
+}
+
+/// Persist the pending confirmation marker
+pub async fn save_pending_confirmation(
+    config: &OtaConfig,
+    marker: &PendingConfirmation,
+) -> Result<()> {
+    write_json(
+        &pending_confirmation_path(config),
+        marker,
+        "confirmation marker",
+    )
+    .await?;
+
+    debug!(
+        "Saved confirmation marker for {} ({} boot attempts)",
+        marker.version, marker.boot_attempts
+    );
+    Ok(())
+}
+
+/// Remove the pending confirmation marker
+pub async fn clear_pending_confirmation(config: &OtaConfig) -> Result<()> {
+    let path = pending_confirmation_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove confirmation marker: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Mark a freshly installed kernel as waiting for boot confirmation
+pub async fn mark_pending_confirmation(
+    config: &OtaConfig,
+    metadata: &KernelMetadata,
+) -> Result<()> {
+    if !config.boot_confirmation.enabled {
+        return Ok(());
+    }
+
+    let boot_id = current_boot_id().await;
+    let marker = PendingConfirmation {
+        version: metadata.latest_version.clone(),
+        checksum: metadata.checksum.clone(),
+        installed_at: chrono::Utc::now(),
+        install_boot_id: boot_id.clone(),
+        last_boot_id: boot_id,
+        boot_attempts: 0,
+    };
+
+    save_pending_confirmation(config, &marker).await?;
+    info!(
+        "Version {} will be confirmed after the next boot",
+        metadata.latest_version
+    );
+    Ok(())
+}
+
+/// Check a pending confirmation against the current boot
+///
+/// `active_kernel_checksum` is the checksum of the kernel image the boot
+/// loader is configured to load.
+pub async fn check_pending_confirmation(
+    config: &OtaConfig,
+    boot_id: Option<String>,
+    kernel_release: Option<String>,
+    active_kernel_checksum: &str,
+) -> Result<BootCheck> {
+    let Some(mut marker) = load_pending_confirmation(config).await? else {
+        return Ok(BootCheck::NothingPending);
+    };
+    let settings = &config.boot_confirmation;
+
+    // Daemon restarted without a reboot; the old kernel is still running
+    if boot_id.is_some() && boot_id == marker.install_boot_id {
+        info!(
+            "Version {} installed, waiting for reboot before confirming",
+            marker.version
+        );
+        return Ok(BootCheck::AwaitingReboot);
+    }
+
+    // Count each new boot once, even if the daemon restarts within it
+    if boot_id.is_none() || boot_id != marker.last_boot_id {
+        marker.boot_attempts += 1;
+        marker.last_boot_id = boot_id;
+        save_pending_confirmation(config, &marker).await?;
+    }
+
+    info!(
+        "Confirming boot of version {} (attempt {}/{})",
+        marker.version, marker.boot_attempts, settings.max_boot_attempts
+    );
+
+    if marker.boot_attempts > settings.max_boot_attempts {
+        return Ok(BootCheck::Revert {
+            version: marker.version,
+            reason: format!(
+                "Boot not confirmed after {} attempts",
+                settings.max_boot_attempts
+            ),
+        });
+    }
+
+    if active_kernel_checksum != marker.checksum {
+        return Ok(BootCheck::Revert {
+            version: marker.version,
+            reason: format!(
+                "Active kernel checksum {} does not match installed {}",
+                active_kernel_checksum, marker.checksum
+            ),
+        });
+    }
+
+    if settings.verify_kernel_release {
+        let release = kernel_release.unwrap_or_default();
+        if !release.contains(&marker.version) {
+            return Ok(BootCheck::Revert {
+                version: marker.version.clone(),
+                reason: format!(
+                    "Running kernel release {} does not match version {}",
+                    release, marker.version
+                ),
+            });
+        }
+    }
+
+    if let Err(e) = run_health_checks(settings).await {
+        return Ok(BootCheck::Revert {
+            version: marker.version,
+            reason: format!("Health checks failed: {}", e),
+        });
+    }
+
+    Ok(BootCheck::Confirmed(marker.version))
+}
+
+/// Run the health check commands until they all pass or the deadline expires
+async fn run_health_checks(settings: &BootConfirmationConfig) -> Result<()> {
+    if settings.health_checks.is_empty() {
+        return Ok(());
+    }
+
+    let deadline = Duration::from_secs(settings.health_check_deadline_secs);
+    let attempt_all = async {
+        loop {
+            match run_health_checks_once(&settings.health_checks).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Health check not passing yet: {}", e);
+                    tokio::time::sleep(Duration::from_secs(HEALTH_CHECK_RETRY_SECS)).await;
+                }
+            }
+        }
+    };
+
+    match tokio::time::timeout(deadline, attempt_all).await {
+        Ok(result) => result,
+        Err(_) => anyhow::bail!(
+            "not all health checks passed within {} seconds",
+            deadline.as_secs()
+        ),
+    }
+}
+
+/// Delay between health check rounds
+const HEALTH_CHECK_RETRY_SECS: u64 = 5;
+
+async fn run_health_checks_once(commands: &[String]) -> Result<()> {
+    for command in commands {
+        let status = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .kill_on_drop(true)
+            .status()
+            .await
+            .with_context(|| format!("Failed to run health check: {}", command))?;
+
+        if !status.success() {
+            anyhow::bail!("`{}` exited with {}", command, status);
+        }
+        debug!("Health check passed: {}", command);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_test_config(temp_dir: &TempDir) -> OtaConfig {
+        OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        }
+    }
+
+    async fn write_marker(config: &OtaConfig, boot_attempts: u32) {
+        let marker = PendingConfirmation {
+            version: "1.0.0".to_string(),
+            checksum: "sha256:abc".to_string(),
+            installed_at: chrono::Utc::now(),
+            install_boot_id: Some("boot-1".to_string()),
+            last_boot_id: Some("boot-1".to_string()),
+            boot_attempts,
+        };
+        save_pending_confirmation(config, &marker).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_nothing_pending() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        let result = check_pending_confirmation(&config, None, None, "sha256:abc")
+            .await
+            .unwrap();
+        assert_eq!(result, BootCheck::NothingPending);
+    }
+
+    #[tokio::test]
+    async fn test_awaiting_reboot() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+        write_marker(&config, 0).await;
+
+        let result =
+            check_pending_confirmation(&config, Some("boot-1".to_string()), None, "sha256:abc")
+                .await
+                .unwrap();
+        assert_eq!(result, BootCheck::AwaitingReboot);
+    }
+
+    #[tokio::test]
+    async fn test_confirmed_after_reboot() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = create_test_config(&temp_dir);
+        config.boot_confirmation.health_checks = vec!["true".to_string()];
+        write_marker(&config, 0).await;
+
+        let result =
+            check_pending_confirmation(&config, Some("boot-2".to_string()), None, "sha256:abc")
+                .await
+                .unwrap();
+        assert_eq!(result, BootCheck::Confirmed("1.0.0".to_string()));
+
+        // The same boot is only counted once
+        check_pending_confirmation(&config, Some("boot-2".to_string()), None, "sha256:abc")
+            .await
+            .unwrap();
+        let marker = load_pending_confirmation(&config).await.unwrap().unwrap();
+        assert_eq!(marker.boot_attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_revert_after_too_many_attempts() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+        write_marker(&config, config.boot_confirmation.max_boot_attempts).await;
+
+        let result =
+            check_pending_confirmation(&config, Some("boot-9".to_string()), None, "sha256:abc")
+                .await
+                .unwrap();
+        assert!(matches!(result, BootCheck::Revert { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_revert_on_failed_health_check() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = create_test_config(&temp_dir);
+        config.boot_confirmation.health_checks = vec!["false".to_string()];
+        config.boot_confirmation.health_check_deadline_secs = 1;
+        write_marker(&config, 0).await;
+
+        let result =
+            check_pending_confirmation(&config, Some("boot-2".to_string()), None, "sha256:abc")
+                .await
+                .unwrap();
+        assert!(matches!(result, BootCheck::Revert { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_revert_on_kernel_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+        write_marker(&config, 0).await;
+
+        let result =
+            check_pending_confirmation(&config, Some("boot-2".to_string()), None, "sha256:other")
+                .await
+                .unwrap();
+        assert!(matches!(result, BootCheck::Revert { .. }));
+    }
+}
@@ -0,0 +1,378 @@
+use crate::artifacts::{
+    StagedArtifact, extract_archive, validate_artifact_name, verify_artifact_file,
+};
+use crate::channel::load_pin;
+use crate::signature::{SignatureError, verify_metadata_signature};
+use crate::types::{KernelMetadata, OtaConfig};
+use crate::version::{is_newer, load_installed_version};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Name of the metadata file at the root of a bundle
+pub const BUNDLE_METADATA_FILE: &str = "metadata.json";
+
+/// Where a local install reads its kernel and metadata from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LocalSource {
+    /// Directory or tar archive holding `metadata.json`, the kernel and its artifacts
+    Bundle { path: String },
+    /// Kernel image and metadata file given separately; artifacts sit next to the metadata
+    Files { image: String, metadata: String },
+}
+
+/// Kernel, metadata and artifacts of a local install, ready for the installer
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalUpdate {
+    pub metadata: KernelMetadata,
+    pub kernel_path: PathBuf,
+    pub artifacts: Vec<StagedArtifact>,
+    /// Directory an archive was extracted to, removed by `cleanup`
+    pub staging_dir: Option<PathBuf>,
+}
+
+impl LocalUpdate {
+    /// Remove files extracted from an archive bundle; the source is left alone
+    pub async fn cleanup(&self) {
+        if let Some(dir) = &self.staging_dir
+            && let Err(e) = tokio::fs::remove_dir_all(dir).await
+        {
+            warn!("Failed to remove bundle staging directory: {}", e);
+        }
+    }
+}
+
+/// Read kernel metadata in the server's `/version` format or as bare metadata
+pub async fn load_metadata_file(path: &Path) -> Result<KernelMetadata> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read metadata file: {}", path.display()))?;
+
+    let value: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse metadata file: {}", path.display()))?;
+    let value = match value.get("kernel_info") {
+        Some(kernel_info) => kernel_info.clone(),
+        None => value,
+    };
+
+    serde_json::from_value(value)
+        .with_context(|| format!("Failed to parse kernel metadata: {}", path.display()))
+}
+
+/// Open a local source, extracting archive bundles under the download path
+pub async fn open_local_update(config: &OtaConfig, source: &LocalSource) -> Result<LocalUpdate> {
+    match source {
+        LocalSource::Files { image, metadata } => {
+            let metadata_path = Path::new(metadata);
+            let artifact_dir = metadata_path.parent().unwrap_or_else(|| Path::new("."));
+            let metadata = load_metadata_file(metadata_path).await?;
+            let artifacts = find_artifacts(&metadata, artifact_dir).await?;
+
+            Ok(LocalUpdate {
+                metadata,
+                kernel_path: PathBuf::from(image),
+                artifacts,
+                staging_dir: None,
+            })
+        }
+        LocalSource::Bundle { path } => {
+            let path = Path::new(path);
+            if path.is_dir() {
+                return open_bundle_dir(path, None).await;
+            }
+
+            let staging_dir = Path::new(&config.download_path).join("bundle");
+            if staging_dir.exists() {
+                tokio::fs::remove_dir_all(&staging_dir)
+                    .await
+                    .context("Failed to clear bundle staging directory")?;
+            }
+            tokio::fs::create_dir_all(&staging_dir)
+                .await
+                .context("Failed to create bundle staging directory")?;
+
+            info!("Extracting bundle {}", path.display());
+            let archive = path.to_path_buf();
+            let target = staging_dir.clone();
+            let extracted = tokio::task::spawn_blocking(move || extract_archive(&archive, &target))
+                .await
+                .context("Bundle extraction task failed")?;
+
+            let opened = match extracted {
+                Ok(()) => open_bundle_dir(&staging_dir, Some(staging_dir.clone())).await,
+                Err(e) => Err(e),
+            };
+            if opened.is_err() {
+                let _ = tokio::fs::remove_dir_all(&staging_dir).await;
+            }
+            opened
+        }
+    }
+}
+
+/// Open a bundle laid out in a directory
+async fn open_bundle_dir(dir: &Path, staging_dir: Option<PathBuf>) -> Result<LocalUpdate> {
+    let metadata = load_metadata_file(&dir.join(BUNDLE_METADATA_FILE)).await?;
+
+    // The file name comes from the metadata, so keep it inside the bundle
+    validate_artifact_name(&metadata.kernel_file)
+        .context("Invalid kernel file name in bundle metadata")?;
+    let kernel_path = dir.join(&metadata.kernel_file);
+    if !kernel_path.is_file() {
+        anyhow::bail!("Bundle has no kernel image {}", kernel_path.display());
+    }
+
+    let artifacts = find_artifacts(&metadata, dir).await?;
+    debug!(
+        "Opened bundle {} for version {}",
+        dir.display(),
+        metadata.latest_version
+    );
+
+    Ok(LocalUpdate {
+        metadata,
+        kernel_path,
+        artifacts,
+        staging_dir,
+    })
+}
+
+/// Locate and verify each artifact of the metadata in `dir`
+async fn find_artifacts(metadata: &KernelMetadata, dir: &Path) -> Result<Vec<StagedArtifact>> {
+    let mut staged = Vec::new();
+    for artifact in &metadata.artifacts {
+        validate_artifact_name(&artifact.name)?;
+        let path = dir.join(&artifact.name);
+        verify_artifact_file(&path, artifact)
+            .await
+            .with_context(|| format!("Bundle artifact {} is not usable", artifact.name))?;
+        staged.push(StagedArtifact {
+            artifact: artifact.clone(),
+            path,
+        });
+    }
+    Ok(staged)
+}
+
+/// Decide whether a bundle found by the mount watcher should be installed
+///
+/// Unattended installs need a valid signature even when `allow_unsigned` is
+/// set, and are skipped unless the bundle is newer than the installed kernel
+/// and allowed by the update pin.
+pub async fn should_install_watched(config: &OtaConfig, metadata: &KernelMetadata) -> Result<bool> {
+    if metadata.signature.is_none() {
+        return Err(SignatureError::Missing).context("Refusing unsigned bundle");
+    }
+    verify_metadata_signature(config, metadata).context("Bundle signature verification failed")?;
+
+    if let Some(record) = load_pin(config).await?
+        && !record.pin.allows(&metadata.latest_version)
+    {
+        info!(
+            "Skipping bundle version {}: updates are {}",
+            metadata.latest_version, record.pin
+        );
+        return Ok(false);
+    }
+
+    let Some(installed) = load_installed_version(config).await? else {
+        return Ok(true);
+    };
+    Ok(installed.checksum != metadata.checksum
+        && is_newer(&metadata.latest_version, &installed.version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{PinRecord, UpdatePin, save_pin};
+    use crate::delta::checksum_of;
+    use crate::types::Artifact;
+    use crate::version::{InstalledVersion, save_installed_version};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use chrono::Utc;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use tempfile::TempDir;
+
+    fn create_test_metadata(kernel: &[u8]) -> KernelMetadata {
+        KernelMetadata {
+            latest_version: "2.0.0".to_string(),
+            kernel_file: "kernel-v2.0.0.img".to_string(),
+            file_size: kernel.len() as u64,
+            checksum: checksum_of(kernel),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v2.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        }
+    }
+
+    fn test_artifact(content: &[u8]) -> Artifact {
+        Artifact {
+            name: "bcm2711-rpi-4-b.dtb".to_string(),
+            download_url: "/artifacts/bcm2711-rpi-4-b.dtb".to_string(),
+            destination: "/boot/bcm2711-rpi-4-b.dtb".to_string(),
+            file_size: content.len() as u64,
+            checksum: checksum_of(content),
+            mode: None,
+            install_type: Default::default(),
+        }
+    }
+
+    /// Lay out a bundle with one artifact in `dir`
+    fn write_bundle(dir: &Path) -> KernelMetadata {
+        let mut metadata = create_test_metadata(b"new kernel");
+        metadata.artifacts.push(test_artifact(b"dtb"));
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(&metadata.kernel_file), b"new kernel").unwrap();
+        std::fs::write(dir.join("bcm2711-rpi-4-b.dtb"), b"dtb").unwrap();
+        std::fs::write(
+            dir.join(BUNDLE_METADATA_FILE),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+        metadata
+    }
+
+    fn test_config(temp_dir: &TempDir) -> OtaConfig {
+        OtaConfig {
+            download_path: temp_dir
+                .path()
+                .join("downloads")
+                .to_string_lossy()
+                .to_string(),
+            state_path: temp_dir.path().join("state").to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_directory_bundle() {
+        let temp_dir = TempDir::new().unwrap();
+        let bundle_dir = temp_dir.path().join("usb");
+        let metadata = write_bundle(&bundle_dir);
+
+        let source = LocalSource::Bundle {
+            path: bundle_dir.to_string_lossy().to_string(),
+        };
+        let update = open_local_update(&test_config(&temp_dir), &source)
+            .await
+            .unwrap();
+        assert_eq!(update.metadata, metadata);
+        assert_eq!(update.kernel_path, bundle_dir.join("kernel-v2.0.0.img"));
+        assert_eq!(update.artifacts.len(), 1);
+        assert!(update.staging_dir.is_none());
+
+        // A corrupted artifact fails before anything is installed
+        std::fs::write(bundle_dir.join("bcm2711-rpi-4-b.dtb"), b"bad").unwrap();
+        assert!(
+            open_local_update(&test_config(&temp_dir), &source)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_archive_bundle() {
+        let temp_dir = TempDir::new().unwrap();
+        let bundle_dir = temp_dir.path().join("bundle-src");
+        let metadata = write_bundle(&bundle_dir);
+
+        let archive = temp_dir.path().join("update.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        builder.append_dir_all(".", &bundle_dir).unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let config = test_config(&temp_dir);
+        let source = LocalSource::Bundle {
+            path: archive.to_string_lossy().to_string(),
+        };
+        let update = open_local_update(&config, &source).await.unwrap();
+        assert_eq!(update.metadata, metadata);
+        let staging_dir = update.staging_dir.clone().unwrap();
+        assert!(update.kernel_path.starts_with(&staging_dir));
+        assert_eq!(std::fs::read(&update.kernel_path).unwrap(), b"new kernel");
+
+        update.cleanup().await;
+        assert!(!staging_dir.exists());
+        assert!(archive.exists());
+    }
+
+    #[tokio::test]
+    async fn test_open_separate_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let metadata = create_test_metadata(b"new kernel");
+        let image = temp_dir.path().join("kernel.img");
+        let metadata_path = temp_dir.path().join("version.json");
+        std::fs::write(&image, b"new kernel").unwrap();
+
+        // The server's /version response works as a metadata file
+        let response = serde_json::json!({ "kernel_info": metadata });
+        std::fs::write(&metadata_path, response.to_string()).unwrap();
+
+        let source = LocalSource::Files {
+            image: image.to_string_lossy().to_string(),
+            metadata: metadata_path.to_string_lossy().to_string(),
+        };
+        let update = open_local_update(&test_config(&temp_dir), &source)
+            .await
+            .unwrap();
+        assert_eq!(update.metadata, metadata);
+        assert_eq!(update.kernel_path, image);
+        assert!(update.artifacts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_watched_bundle_must_be_signed_and_newer() {
+        let temp_dir = TempDir::new().unwrap();
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let config = OtaConfig {
+            trusted_public_keys: vec![BASE64.encode(key_pair.public_key().as_ref())],
+            allow_unsigned: true,
+            ..test_config(&temp_dir)
+        };
+
+        // allow_unsigned does not apply to unattended installs
+        let mut metadata = create_test_metadata(b"new kernel");
+        assert!(should_install_watched(&config, &metadata).await.is_err());
+
+        let message = crate::signature::signed_message(&metadata);
+        metadata.signature = Some(BASE64.encode(key_pair.sign(&message).as_ref()));
+        assert!(should_install_watched(&config, &metadata).await.unwrap());
+
+        // A hold or a pin to another version keeps it out
+        let pin_to = |pin| PinRecord {
+            pin,
+            set_at: Utc::now(),
+        };
+        save_pin(&config, &pin_to(UpdatePin::Hold)).await.unwrap();
+        assert!(!should_install_watched(&config, &metadata).await.unwrap());
+        let pinned = UpdatePin::Version("2.1.0".to_string());
+        save_pin(&config, &pin_to(pinned)).await.unwrap();
+        assert!(!should_install_watched(&config, &metadata).await.unwrap());
+        let pinned = UpdatePin::Version("2.0.0".to_string());
+        save_pin(&config, &pin_to(pinned)).await.unwrap();
+        assert!(should_install_watched(&config, &metadata).await.unwrap());
+
+        // Already installed: left alone until a newer bundle is plugged in
+        let installed = InstalledVersion {
+            version: "2.0.0".to_string(),
+            checksum: metadata.checksum.clone(),
+            installed_at: Utc::now(),
+            previous_version: None,
+        };
+        save_installed_version(&config, &installed).await.unwrap();
+        assert!(!should_install_watched(&config, &metadata).await.unwrap());
+    }
+}
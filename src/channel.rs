@@ -0,0 +1,273 @@
+use crate::durable::{read_json, sync_parent, write_json};
+use crate::types::OtaConfig;
+use crate::version::{InstalledVersion, compare_versions};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Device tree node naming the board
+const DEVICE_MODEL_PATH: &str = "/proc/device-tree/model";
+/// Holds the board revision code on Raspberry Pi kernels
+const CPUINFO_PATH: &str = "/proc/cpuinfo";
+
+/// Operator override of which updates the device takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdatePin {
+    /// Only this exact version may be installed
+    Version(String),
+    /// No updates are installed
+    Hold,
+}
+
+impl UpdatePin {
+    /// Whether an update to `version` may be installed under this pin
+    pub fn allows(&self, version: &str) -> bool {
+        match self {
+            UpdatePin::Version(pinned) => compare_versions(version, pinned) == Ordering::Equal,
+            UpdatePin::Hold => false,
+        }
+    }
+}
+
+impl fmt::Display for UpdatePin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdatePin::Version(version) => write!(f, "pinned to {}", version),
+            UpdatePin::Hold => f.write_str("held"),
+        }
+    }
+}
+
+/// An install refused because the update pin doesn't allow its version
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinnedError {
+    pub version: String,
+    pub pin: UpdatePin,
+}
+
+impl fmt::Display for PinnedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "version {} refused: updates are {}",
+            self.version, self.pin
+        )
+    }
+}
+
+impl std::error::Error for PinnedError {}
+
+/// Persisted pin, kept in the state directory rather than the config file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinRecord {
+    pub pin: UpdatePin,
+    pub set_at: DateTime<Utc>,
+}
+
+/// Path of the update pin for a configuration
+pub fn pin_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("update_pin.json")
+}
+
+/// Load the update pin, if an operator set one
+pub async fn load_pin(config: &OtaConfig) -> Result<Option<PinRecord>> {
+    read_json(&pin_path(config), "update pin").await
+}
+
+/// Persist the update pin, synced before returning
+pub async fn save_pin(config: &OtaConfig, record: &PinRecord) -> Result<()> {
+    write_json(&pin_path(config), record, "update pin").await?;
+
+    info!("Updates {}", record.pin);
+    Ok(())
+}
+
+/// Refuse to install `version` unless the update pin, if any, allows it
+pub async fn check_pin(config: &OtaConfig, version: &str) -> Result<()> {
+    match load_pin(config).await? {
+        Some(record) if !record.pin.allows(version) => Err(PinnedError {
+            version: version.to_string(),
+            pin: record.pin,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Remove the update pin, returning the device to its channel
+pub async fn clear_pin(config: &OtaConfig) -> Result<()> {
+    let path = pin_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove update pin: {}", path.display()))?;
+        sync_parent(&path).await?;
+    }
+    Ok(())
+}
+
+/// Board details the server picks images by
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DeviceInfo {
+    pub model: Option<String>,
+    pub hardware_revision: Option<String>,
+}
+
+/// Board model and revision, from the config or else read from the system
+pub async fn device_info(config: &OtaConfig) -> DeviceInfo {
+    let model = match &config.device_model {
+        Some(model) => Some(model.clone()),
+        None => tokio::fs::read_to_string(DEVICE_MODEL_PATH)
+            .await
+            .ok()
+            .and_then(|content| parse_device_model(&content)),
+    };
+    let hardware_revision = match &config.hardware_revision {
+        Some(revision) => Some(revision.clone()),
+        None => tokio::fs::read_to_string(CPUINFO_PATH)
+            .await
+            .ok()
+            .and_then(|content| parse_cpuinfo_revision(&content)),
+    };
+
+    debug!("Device model {:?}, revision {:?}", model, hardware_revision);
+    DeviceInfo {
+        model,
+        hardware_revision,
+    }
+}
+
+/// Board name from the device tree, which ends in a NUL
+fn parse_device_model(content: &str) -> Option<String> {
+    let model = content.trim_end_matches('\0').trim();
+    (!model.is_empty()).then(|| model.to_string())
+}
+
+/// Value of the `Revision` line in `/proc/cpuinfo`
+fn parse_cpuinfo_revision(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Revision" && !value.trim().is_empty()).then(|| value.trim().to_string())
+    })
+}
+
+/// Query parameters of a `/version` request
+///
+/// The installed version and checksum also let the server offer a delta; a
+/// pinned version is passed so the server can serve it instead of the latest.
+pub fn version_query(
+    config: &OtaConfig,
+    installed: Option<&InstalledVersion>,
+    pin: Option<&UpdatePin>,
+    device: &DeviceInfo,
+) -> Vec<(&'static str, String)> {
+    let mut query = vec![("channel", config.channel.name().to_string())];
+    if let Some(installed) = installed {
+        query.push(("installed_version", installed.version.clone()));
+        query.push(("installed_checksum", installed.checksum.clone()));
+    }
+    if let Some(model) = &device.model {
+        query.push(("device_model", model.clone()));
+    }
+    if let Some(revision) = &device.hardware_revision {
+        query.push(("hardware_revision", revision.clone()));
+    }
+    if let Some(UpdatePin::Version(version)) = pin {
+        query.push(("pinned_version", version.clone()));
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::UpdateChannel;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pin_allows_only_its_version() {
+        let pin = UpdatePin::Version("1.2.0".to_string());
+        assert!(pin.allows("1.2.0"));
+        assert!(pin.allows("v1.2.0"));
+        assert!(!pin.allows("1.3.0"));
+        assert!(!UpdatePin::Hold.allows("1.2.0"));
+    }
+
+    #[test]
+    fn test_device_info_parsing() {
+        assert_eq!(
+            parse_device_model("Raspberry Pi 4 Model B Rev 1.4\0").as_deref(),
+            Some("Raspberry Pi 4 Model B Rev 1.4")
+        );
+        assert_eq!(parse_device_model("\0"), None);
+
+        let cpuinfo = "processor\t: 0\nBogoMIPS\t: 108.00\n\nHardware\t: BCM2835\nRevision\t: c03114\nSerial\t\t: 100000001234abcd\n";
+        assert_eq!(parse_cpuinfo_revision(cpuinfo).as_deref(), Some("c03114"));
+        assert_eq!(parse_cpuinfo_revision("processor\t: 0\n"), None);
+    }
+
+    #[test]
+    fn test_version_query() {
+        let config = OtaConfig {
+            channel: UpdateChannel::Beta,
+            ..OtaConfig::default()
+        };
+        let installed = InstalledVersion {
+            version: "1.0.0".to_string(),
+            checksum: "sha256:abc".to_string(),
+            installed_at: Utc::now(),
+            previous_version: None,
+        };
+        let device = DeviceInfo {
+            model: Some("Raspberry Pi 4 Model B".to_string()),
+            hardware_revision: None,
+        };
+        let pin = UpdatePin::Version("1.1.0".to_string());
+
+        let query = version_query(&config, Some(&installed), Some(&pin), &device);
+        assert_eq!(
+            query,
+            vec![
+                ("channel", "beta".to_string()),
+                ("installed_version", "1.0.0".to_string()),
+                ("installed_checksum", "sha256:abc".to_string()),
+                ("device_model", "Raspberry Pi 4 Model B".to_string()),
+                ("pinned_version", "1.1.0".to_string()),
+            ]
+        );
+
+        let query = version_query(
+            &config,
+            None,
+            Some(&UpdatePin::Hold),
+            &DeviceInfo::default(),
+        );
+        assert_eq!(query, vec![("channel", "beta".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_pin_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+
+        assert!(load_pin(&config).await.unwrap().is_none());
+
+        let record = PinRecord {
+            pin: UpdatePin::Hold,
+            set_at: Utc::now(),
+        };
+        save_pin(&config, &record).await.unwrap();
+        assert_eq!(load_pin(&config).await.unwrap(), Some(record));
+
+        clear_pin(&config).await.unwrap();
+        assert!(load_pin(&config).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Bytes read per chunk when hashing or copying, so memory use stays bounded
+pub const HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+fn format_digest(hasher: Sha256) -> String {
+    format!("sha256:{:x}", hasher.finalize())
+}
+
+/// `sha256:<hex>` digest of a file, read in bounded chunks
+pub async fn file_checksum(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(format_digest(hasher))
+}
+
+/// Copy a file while hashing it, so each source byte is read only once
+///
+/// Returns the size and `sha256:<hex>` digest of the bytes written. The
+/// destination is flushed but not synced.
+pub async fn copy_and_hash(src: &Path, dst: &Path) -> Result<(u64, String)> {
+    let mut reader = tokio::fs::File::open(src)
+        .await
+        .with_context(|| format!("Failed to open {}", src.display()))?;
+    let mut writer = tokio::fs::File::create(dst)
+        .await
+        .with_context(|| format!("Failed to create {}", dst.display()))?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .await
+            .with_context(|| format!("Failed to read {}", src.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer
+            .write_all(&buffer[..read])
+            .await
+            .with_context(|| format!("Failed to write {}", dst.display()))?;
+        copied += read as u64;
+    }
+
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("Failed to write {}", dst.display()))?;
+
+    Ok((copied, format_digest(hasher)))
+}
+
+/// First `len` bytes of a file, or fewer if it is shorter
+pub async fn read_prefix(path: &Path, len: usize) -> Result<Vec<u8>> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+
+    let mut prefix = Vec::with_capacity(len);
+    file.take(len as u64)
+        .read_to_end(&mut prefix)
+        .await
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delta::checksum_of;
+    use tempfile::TempDir;
+
+    /// Larger than one chunk and not a multiple of it
+    fn create_content() -> Vec<u8> {
+        (0..HASH_CHUNK_SIZE * 3 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_file_checksum_matches_in_memory_digest() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("image");
+        let content = create_content();
+        std::fs::write(&path, &content).unwrap();
+
+        assert_eq!(file_checksum(&path).await.unwrap(), checksum_of(&content));
+
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(file_checksum(&path).await.unwrap(), checksum_of(b""));
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_hash() {
+        let temp_dir = TempDir::new().unwrap();
+        let src = temp_dir.path().join("src");
+        let dst = temp_dir.path().join("dst");
+        let content = create_content();
+        std::fs::write(&src, &content).unwrap();
+
+        let (size, checksum) = copy_and_hash(&src, &dst).await.unwrap();
+        assert_eq!(size, content.len() as u64);
+        assert_eq!(checksum, checksum_of(&content));
+        assert_eq!(std::fs::read(&dst).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_read_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("image");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        assert_eq!(read_prefix(&path, 4).await.unwrap(), b"0123");
+        assert_eq!(read_prefix(&path, 64).await.unwrap(), b"0123456789");
+        assert!(
+            read_prefix(&temp_dir.path().join("missing"), 4)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::schedule::validate_windows;
+use crate::tls::{parse_pin, validate_client_identity, validate_scheme};
 use crate::types::OtaConfig;
 use anyhow::{Context, Result};
 use std::path::Path;
@@ -61,14 +63,38 @@ async fn validate_config(config: &OtaConfig) -> Result<()> {
         anyhow::bail!("download_timeout_secs must be greater than 0");
     }
 
+    if config.backups.keep == 0 {
+        anyhow::bail!("backups.keep must be greater than 0");
+    }
+
+    if config.bundle_watch.enabled && config.bundle_watch.scan_interval_secs == 0 {
+        anyhow::bail!("bundle_watch.scan_interval_secs must be greater than 0");
+    }
+
+    if config.hooks.timeout_secs == 0 {
+        anyhow::bail!("hooks.timeout_secs must be greater than 0");
+    }
+
+    if config.reboot.inhibitor_retry_secs == 0 {
+        anyhow::bail!("reboot.inhibitor_retry_secs must be greater than 0");
+    }
+
+    validate_windows(&config.schedule.maintenance_windows).context("Invalid maintenance window")?;
+
+    validate_scheme(&config.server_scheme).context("Invalid server_scheme")?;
+    for pin in &config.tls.spki_pins {
+        parse_pin(pin).context("Invalid SPKI pin")?;
+    }
+    validate_client_identity(&config.tls).context("Invalid client certificate settings")?;
+
     // Validate paths exist or can be created
     let download_path = Path::new(&config.download_path);
-    if let Some(parent) = download_path.parent() {
-        if !parent.exists() {
-            tokio::fs::create_dir_all(parent)
-                .await
-                .with_context(|| format!("Cannot create download directory: {:?}", parent))?;
-        }
+    if let Some(parent) = download_path.parent()
+        && !parent.exists()
+    {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Cannot create download directory: {:?}", parent))?;
     }
 
     Ok(())
@@ -140,8 +166,10 @@ download_timeout_secs = 600
     #[tokio::test]
     async fn test_validate_config_success() {
         // Use a config with download path that doesn't require creating system directories
-        let mut config = OtaConfig::default();
-        config.download_path = "/tmp".to_string(); // Use existing /tmp directory
+        let config = OtaConfig {
+            download_path: "/tmp".to_string(), // Use existing /tmp directory
+            ..OtaConfig::default()
+        };
 
         let result = validate_config(&config).await;
         assert!(result.is_ok());
@@ -149,20 +177,44 @@ download_timeout_secs = 600
 
     #[tokio::test]
     async fn test_validate_config_failure() {
-        let mut config = OtaConfig::default();
-
         // Test zero check interval
-        config.check_interval_minutes = 0;
+        let config = OtaConfig {
+            check_interval_minutes: 0,
+            ..OtaConfig::default()
+        };
         assert!(validate_config(&config).await.is_err());
 
         // Reset and test zero retries
-        config = OtaConfig::default();
-        config.max_retries = 0;
+        let config = OtaConfig {
+            max_retries: 0,
+            ..OtaConfig::default()
+        };
         assert!(validate_config(&config).await.is_err());
 
         // Reset and test zero timeout
-        config = OtaConfig::default();
-        config.download_timeout_secs = 0;
+        let config = OtaConfig {
+            download_timeout_secs: 0,
+            ..OtaConfig::default()
+        };
+        assert!(validate_config(&config).await.is_err());
+
+        // Malformed maintenance window
+        let mut config = OtaConfig::default();
+        config
+            .schedule
+            .maintenance_windows
+            .push(crate::types::MaintenanceWindow {
+                days: vec!["someday".to_string()],
+                start: "02:00".to_string(),
+                end: "04:00".to_string(),
+            });
+        assert!(validate_config(&config).await.is_err());
+
+        // Unknown server scheme
+        let config = OtaConfig {
+            server_scheme: "ftp".to_string(),
+            ..OtaConfig::default()
+        };
         assert!(validate_config(&config).await.is_err());
     }
 
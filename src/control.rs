@@ -0,0 +1,435 @@
+use crate::bundle::LocalSource;
+use crate::daemon::OtaDaemon;
+use crate::installer::InstallPlan;
+use crate::types::{DaemonStatus, DryRun, KernelMetadata, OtaResult};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, error, info, warn};
+
+/// Version of the control protocol spoken over the socket
+pub const CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+/// Request sent by the CLI to the running daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlRequest {
+    Status,
+    Check,
+    Update {
+        /// Plan the update instead of installing it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dry_run: Option<DryRun>,
+    },
+    Rollback {
+        /// Version to restore from the backup store; the previous one when unset
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<String>,
+    },
+    /// Install from a bundle or image on the daemon's file system
+    Install {
+        source: LocalSource,
+    },
+    ReloadConfig,
+    ClearHistory,
+    Shutdown,
+}
+
+/// Daemon reply to a control request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Status { status: DaemonStatus },
+    Check { update: Option<KernelMetadata> },
+    Update { installed: bool },
+    DryRun { plan: InstallPlan },
+    RolledBack,
+    Installed { version: String },
+    ConfigReloaded,
+    HistoryCleared { removed: usize },
+    ShuttingDown,
+    Error { message: String },
+}
+
+/// One line on the wire from client to daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestMessage {
+    pub version: u32,
+    pub request: ControlRequest,
+}
+
+/// One line on the wire from daemon to client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub version: u32,
+    pub response: ControlResponse,
+}
+
+/// Serve control requests on a Unix domain socket
+pub async fn serve(daemon: Arc<OtaDaemon>, socket_path: &str) -> Result<()> {
+    let path = Path::new(socket_path);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create control socket directory: {:?}", parent))?;
+    }
+
+    // A stale socket from a previous run would make bind fail, but one a
+    // running daemon still answers on must not be taken over
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!("Control socket {} is in use by another daemon", socket_path);
+        }
+        tokio::fs::remove_file(path)
+            .await
+            .with_context(|| format!("Failed to remove stale control socket: {}", socket_path))?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind control socket: {}", socket_path))?;
+
+    // Only root may drive the daemon
+    tokio::fs::set_permissions(path, Permissions::from_mode(0o600)).await?;
+
+    info!("Control socket listening on {}", socket_path);
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept control connection")?;
+
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(daemon, stream).await {
+                warn!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Handle a single request/response exchange
+async fn handle_connection(daemon: Arc<OtaDaemon>, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let Some(line) = lines.next_line().await? else {
+        return Ok(());
+    };
+
+    let response = match serde_json::from_str::<RequestMessage>(&line) {
+        Ok(message) if message.version != CONTROL_PROTOCOL_VERSION => ControlResponse::Error {
+            message: format!(
+                "Unsupported protocol version {} (daemon speaks {})",
+                message.version, CONTROL_PROTOCOL_VERSION
+            ),
+        },
+        Ok(message) => {
+            debug!("Control request: {:?}", message.request);
+            dispatch(&daemon, message.request).await
+        }
+        Err(e) => ControlResponse::Error {
+            message: format!("Malformed request: {}", e),
+        },
+    };
+
+    let reply = ResponseMessage {
+        version: CONTROL_PROTOCOL_VERSION,
+        response,
+    };
+    let mut payload = serde_json::to_string(&reply).context("Failed to serialize response")?;
+    payload.push('\n');
+
+    writer.write_all(payload.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Run a control request against the daemon
+async fn dispatch(daemon: &OtaDaemon, request: ControlRequest) -> ControlResponse {
+    let result = match request {
+        ControlRequest::Status => Ok(ControlResponse::Status {
+            status: daemon.get_status().await,
+        }),
+        ControlRequest::Check => daemon
+            .check_for_update()
+            .await
+            .map(|update| ControlResponse::Check { update }),
+        ControlRequest::Update { dry_run } => {
+            daemon
+                .force_update_check(dry_run)
+                .await
+                .map(|result| match result {
+                    OtaResult::DryRun(plan) => ControlResponse::DryRun { plan: *plan },
+                    result => ControlResponse::Update {
+                        installed: result == OtaResult::UpdateInstalled,
+                    },
+                })
+        }
+        ControlRequest::Rollback { version } => daemon
+            .manual_rollback(version)
+            .await
+            .map(|_| ControlResponse::RolledBack),
+        ControlRequest::Install { source } => daemon
+            .install_local_source(&source)
+            .await
+            .map(|version| ControlResponse::Installed { version }),
+        ControlRequest::ReloadConfig => daemon
+            .reload_config(daemon.config_path())
+            .await
+            .map(|_| ControlResponse::ConfigReloaded),
+        ControlRequest::ClearHistory => daemon
+            .clear_history()
+            .await
+            .map(|removed| ControlResponse::HistoryCleared { removed }),
+        ControlRequest::Shutdown => {
+            daemon.request_shutdown().await;
+            Ok(ControlResponse::ShuttingDown)
+        }
+    };
+
+    result.unwrap_or_else(|e| {
+        error!("Control request failed: {:#}", e);
+        ControlResponse::Error {
+            message: format!("{:#}", e),
+        }
+    })
+}
+
+/// Client side of the control socket
+pub struct ControlClient {
+    stream: UnixStream,
+}
+
+impl ControlClient {
+    /// Connect to a running daemon, or return `None` if none is listening
+    pub async fn connect(socket_path: &str) -> Option<Self> {
+        match UnixStream::connect(socket_path).await {
+            Ok(stream) => Some(Self { stream }),
+            Err(e) => {
+                debug!("No daemon on control socket {}: {}", socket_path, e);
+                None
+            }
+        }
+    }
+
+    /// Send one request and wait for the daemon's reply
+    pub async fn request(self, request: ControlRequest) -> Result<ControlResponse> {
+        let (reader, mut writer) = self.stream.into_split();
+
+        let message = RequestMessage {
+            version: CONTROL_PROTOCOL_VERSION,
+            request,
+        };
+        let mut payload = serde_json::to_string(&message).context("Failed to serialize request")?;
+        payload.push('\n');
+        writer
+            .write_all(payload.as_bytes())
+            .await
+            .context("Failed to send control request")?;
+
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await
+            .context("Failed to read control response")?
+            .context("Daemon closed the control connection without replying")?;
+
+        let reply: ResponseMessage =
+            serde_json::from_str(&line).context("Failed to parse control response")?;
+        if reply.version != CONTROL_PROTOCOL_VERSION {
+            anyhow::bail!(
+                "Daemon speaks protocol version {}, expected {}",
+                reply.version,
+                CONTROL_PROTOCOL_VERSION
+            );
+        }
+
+        Ok(reply.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_wire_format() {
+        let message = RequestMessage {
+            version: CONTROL_PROTOCOL_VERSION,
+            request: ControlRequest::ReloadConfig,
+        };
+
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(json, r#"{"version":1,"request":{"type":"reload_config"}}"#);
+
+        let parsed: RequestMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.request, ControlRequest::ReloadConfig);
+
+        // A plain rollback keeps the wire format it always had
+        let rollback = serde_json::to_string(&ControlRequest::Rollback { version: None }).unwrap();
+        assert_eq!(rollback, r#"{"type":"rollback"}"#);
+        let parsed: ControlRequest =
+            serde_json::from_str(r#"{"type":"rollback","version":"1.0.0"}"#).unwrap();
+        assert_eq!(
+            parsed,
+            ControlRequest::Rollback {
+                version: Some("1.0.0".to_string())
+            }
+        );
+
+        let install = ControlRequest::Install {
+            source: LocalSource::Bundle {
+                path: "/media/ota".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&install).unwrap(),
+            r#"{"type":"install","source":{"kind":"bundle","path":"/media/ota"}}"#
+        );
+
+        // A plain update keeps its wire format; a dry run asks for a plan
+        let update = serde_json::to_string(&ControlRequest::Update { dry_run: None }).unwrap();
+        assert_eq!(update, r#"{"type":"update"}"#);
+        let parsed: ControlRequest =
+            serde_json::from_str(r#"{"type":"update","dry_run":{"download":true}}"#).unwrap();
+        assert_eq!(
+            parsed,
+            ControlRequest::Update {
+                dry_run: Some(DryRun { download: true })
+            }
+        );
+    }
+
+    #[test]
+    fn test_response_wire_format() {
+        let message = ResponseMessage {
+            version: CONTROL_PROTOCOL_VERSION,
+            response: ControlResponse::Update { installed: false },
+        };
+
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"response":{"type":"update","installed":false}}"#
+        );
+    }
+
+    async fn start_test_daemon(temp_dir: &tempfile::TempDir) -> (Arc<OtaDaemon>, String) {
+        let socket_path = temp_dir
+            .path()
+            .join("control.sock")
+            .to_string_lossy()
+            .to_string();
+        let config = crate::types::OtaConfig {
+            download_path: temp_dir.path().to_string_lossy().to_string(),
+            state_path: temp_dir.path().join("state").to_string_lossy().to_string(),
+            control_socket_path: socket_path.clone(),
+            ..crate::types::OtaConfig::default()
+        };
+
+        let config_path = temp_dir.path().join("config.toml");
+        std::fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+
+        let daemon = Arc::new(OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap());
+        let server_daemon = Arc::clone(&daemon);
+        let server_path = socket_path.clone();
+        tokio::spawn(async move { serve(server_daemon, &server_path).await });
+
+        // Wait for the listener to come up
+        for _ in 0..50 {
+            if Path::new(&socket_path).exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        (daemon, socket_path)
+    }
+
+    #[tokio::test]
+    async fn test_status_over_socket() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (_daemon, socket_path) = start_test_daemon(&temp_dir).await;
+
+        let client = ControlClient::connect(&socket_path).await.unwrap();
+        let response = client.request(ControlRequest::Status).await.unwrap();
+
+        match response {
+            ControlResponse::Status { status } => {
+                assert_eq!(status.current_state, crate::types::DaemonState::Starting);
+                assert_eq!(status.update_count, 0);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_over_socket() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (_daemon, socket_path) = start_test_daemon(&temp_dir).await;
+
+        let client = ControlClient::connect(&socket_path).await.unwrap();
+        let response = client.request(ControlRequest::Shutdown).await.unwrap();
+
+        assert!(matches!(response, ControlResponse::ShuttingDown));
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_protocol_version() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (_daemon, socket_path) = start_test_daemon(&temp_dir).await;
+
+        let mut stream = UnixStream::connect(&socket_path).await.unwrap();
+        stream
+            .write_all(b"{\"version\":99,\"request\":{\"type\":\"status\"}}\n")
+            .await
+            .unwrap();
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        let reply: ResponseMessage = serde_json::from_str(&line).unwrap();
+        assert!(matches!(reply.response, ControlResponse::Error { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_live_socket_not_taken_over() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (daemon, socket_path) = start_test_daemon(&temp_dir).await;
+
+        let error = serve(Arc::clone(&daemon), &socket_path).await.unwrap_err();
+        assert!(error.to_string().contains("in use"));
+        assert!(ControlClient::connect(&socket_path).await.is_some());
+
+        // A socket nobody listens on is stale and replaced
+        let stale_path = temp_dir.path().join("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale_path).unwrap());
+        let stale_path = stale_path.to_string_lossy().to_string();
+        let server_path = stale_path.clone();
+        tokio::spawn(async move { serve(daemon, &server_path).await });
+        for _ in 0..50 {
+            if ControlClient::connect(&stale_path).await.is_some() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("stale control socket was not replaced");
+    }
+
+    #[tokio::test]
+    async fn test_connect_without_daemon() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let socket_path = temp_dir.path().join("control.sock");
+
+        assert!(
+            ControlClient::connect(socket_path.to_str().unwrap())
+                .await
+                .is_none()
+        );
+    }
+}
@@ -1,16 +1,54 @@
+use crate::boot::{
+    BootCheck, check_pending_confirmation, clear_pending_confirmation, current_boot_id,
+    mark_pending_confirmation, running_kernel_release,
+};
+use crate::bundle::{
+    BUNDLE_METADATA_FILE, LocalSource, LocalUpdate, load_metadata_file, open_local_update,
+    should_install_watched,
+};
+use crate::channel::PinnedError;
 use crate::config::load_config;
-use crate::downloader::Downloader;
-use crate::installer::Installer;
+use crate::control;
+use crate::disk_space::InsufficientSpaceError;
+use crate::downloader::{Downloader, remove_downloads};
+use crate::history::{MAX_HISTORY_RECORDS, history_path, load_history, save_history};
+use crate::hooks::HookError;
+use crate::installer::{InstallPlan, InstallationStatus, Installer, RecoveryOutcome};
+use crate::journal::UndoFailedError;
+use crate::metrics::{self, MetricsSnapshot};
+use crate::reboot::{
+    PendingReboot, RebootExecutor, SystemReboot, active_inhibitor, load_pending_reboot,
+    mark_pending_reboot, reboot_due_at, save_pending_reboot, settle_pending_reboot,
+};
+use crate::schedule::{self, in_maintenance_window, local_now};
+use crate::security_version::AntiRollbackError;
+use crate::signature::SignatureError;
+use crate::tls::{client_certificate, device_identity};
 use crate::types::*;
+use crate::version::load_installed_version;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use std::path::Path;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::{Mutex, RwLock};
-use tokio::time::{interval, sleep, timeout};
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio::time::{sleep, timeout};
 use tracing::{debug, error, info, warn};
 
+/// Where a single update cycle attempt ended up
+enum CycleOutcome {
+    /// Already running the latest version
+    UpToDate,
+    /// An update is available but must wait for a maintenance window
+    Deferred(Box<KernelMetadata>),
+    /// The update was installed
+    Installed(UpdateRecord),
+    /// A dry run planned the install
+    DryRun(UpdateRecord, Box<InstallPlan>),
+}
+
 /// Main daemon service orchestrating OTA updates
 pub struct OtaDaemon {
     config: Arc<RwLock<OtaConfig>>,
@@ -20,28 +58,55 @@ pub struct OtaDaemon {
     update_history: Arc<Mutex<Vec<UpdateRecord>>>,
     start_time: Instant,
     last_check: Arc<RwLock<Option<DateTime<Utc>>>>,
+    /// When the main loop will next wake up to check or apply an update
+    next_action_at: Arc<RwLock<Option<DateTime<Utc>>>>,
     shutdown_requested: Arc<RwLock<bool>>,
-    log_file_path: String,
+    shutdown_notify: Arc<Notify>,
+    /// Held for update cycles, local installs and rollbacks so they never overlap
+    cycle_lock: Mutex<()>,
+    /// Bytes downloaded since startup; survives downloader replacement on reload
+    bytes_downloaded: Arc<AtomicU64>,
+    log_file_path: PathBuf,
     config_path: String,
+    /// Plan updates instead of installing them
+    dry_run: Option<DryRun>,
+    reboot_executor: Arc<dyn RebootExecutor>,
+    /// Wakes the reboot task when a reboot has been scheduled
+    reboot_notify: Arc<Notify>,
 }
 
 impl OtaDaemon {
     /// Create new daemon instance
     pub async fn new(config_path: &str) -> Result<Self> {
+        Self::with_dry_run(config_path, None).await
+    }
+
+    /// Create a daemon that, with `dry_run` set, plans updates but never installs
+    ///
+    /// A dry-run daemon leaves the boot partition alone entirely: it does not
+    /// recover interrupted installs, confirm boots or install watched bundles.
+    pub async fn with_dry_run(config_path: &str, dry_run: Option<DryRun>) -> Result<Self> {
         let config = load_config(config_path)
             .await
             .context("Failed to load configuration")?;
 
-        let downloader = Downloader::new(config.clone());
+        let bytes_downloaded = Arc::new(AtomicU64::new(0));
+        let mut downloader =
+            Downloader::new(config.clone()).context("Failed to initialize downloader")?;
+        downloader.set_byte_counter(Arc::clone(&bytes_downloaded));
         let installer = Installer::new(config.clone()).context("Failed to initialize installer")?;
 
         // Create log file path
-        let log_file_path = format!("{}/ota_update_history.json", config.download_path);
+        let log_file_path = history_path(&config);
 
         // Load existing update history
-        let update_history = Self::load_update_history(&log_file_path).await?;
+        let update_history = load_history(&log_file_path).await?;
+        info!(
+            "Loaded {} update records from history",
+            update_history.len()
+        );
 
-        Ok(Self {
+        let daemon = Self {
             config: Arc::new(RwLock::new(config)),
             downloader: Arc::new(Mutex::new(downloader)),
             installer: Arc::new(Mutex::new(installer)),
@@ -49,10 +114,78 @@ impl OtaDaemon {
             update_history: Arc::new(Mutex::new(update_history)),
             start_time: Instant::now(),
             last_check: Arc::new(RwLock::new(None)),
+            next_action_at: Arc::new(RwLock::new(None)),
             shutdown_requested: Arc::new(RwLock::new(false)),
+            shutdown_notify: Arc::new(Notify::new()),
+            cycle_lock: Mutex::new(()),
+            bytes_downloaded,
             log_file_path,
             config_path: config_path.to_string(),
-        })
+            dry_run,
+            reboot_executor: Arc::new(SystemReboot),
+            reboot_notify: Arc::new(Notify::new()),
+        };
+
+        // Settle an install the last run didn't finish before anything else
+        if dry_run.is_some() {
+            info!("Dry run: updates are planned, not installed");
+        } else {
+            daemon.recover_interrupted_install().await;
+        }
+
+        Ok(daemon)
+    }
+
+    /// Replace the executor that carries out reboots
+    pub fn with_reboot_executor(mut self, executor: Arc<dyn RebootExecutor>) -> Self {
+        self.reboot_executor = executor;
+        self
+    }
+
+    /// Finish or undo an install the last run didn't live to complete
+    async fn recover_interrupted_install(&self) {
+        let outcome = match self
+            .installer
+            .lock()
+            .await
+            .recover_interrupted_install()
+            .await
+        {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => return,
+            Err(e) => {
+                // Installs retry the recovery before they start, so keep running
+                error!("Recovery of interrupted installation failed: {:#}", e);
+                return;
+            }
+        };
+
+        let (metadata, status, error_message) = match outcome {
+            RecoveryOutcome::Completed(metadata) => {
+                let config = self.config.read().await.clone();
+                if let Err(e) = mark_pending_confirmation(&config, &metadata).await {
+                    error!("Failed to write boot confirmation marker: {}", e);
+                }
+                (metadata, UpdateStatus::Success, None)
+            }
+            RecoveryOutcome::RolledBack(metadata) => (
+                metadata,
+                UpdateStatus::RolledBack,
+                Some("Installation was interrupted and has been undone".to_string()),
+            ),
+        };
+
+        let record = UpdateRecord {
+            timestamp: Utc::now(),
+            version: metadata.latest_version,
+            status,
+            error_message,
+            duration_seconds: 0,
+            device_id: None,
+        };
+        if let Err(e) = self.save_update_record(record).await {
+            error!("Failed to record recovered installation: {}", e);
+        }
     }
 
     /// Start the daemon main loop
@@ -62,32 +195,77 @@ impl OtaDaemon {
         // Setup signal handlers
         self.setup_signal_handlers().await?;
 
+        // Serve the control API so CLI commands talk to this process
+        let socket_path = self.config.read().await.control_socket_path.clone();
+        let control_daemon = Arc::clone(&self);
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(control_daemon, &socket_path).await {
+                error!("Control socket stopped: {:#}", e);
+            }
+        });
+
+        let metrics_config = self.config.read().await.metrics.clone();
+        if metrics_config.enabled {
+            let metrics_daemon = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(e) = metrics::serve(metrics_daemon, &metrics_config.listen_address).await
+                {
+                    error!("Metrics endpoint stopped: {:#}", e);
+                }
+            });
+        }
+
+        // Confirm or revert a kernel installed before the last reboot, before
+        // a watched bundle can install over it
+        if self.dry_run.is_none() {
+            if let Err(e) = self.confirm_pending_boot().await {
+                error!("Boot confirmation check failed: {}", e);
+            }
+            tokio::spawn(Arc::clone(&self).reboot_when_due());
+        }
+
+        if self.dry_run.is_none() && self.config.read().await.bundle_watch.enabled {
+            tokio::spawn(Arc::clone(&self).watch_bundles());
+        }
+
         // Transition to idle state
         self.set_state(DaemonState::Idle).await;
+        self.check_client_certificate().await;
 
-        // Main service loop
-        let config = self.config.read().await;
-        let check_interval = Duration::from_secs(config.check_interval_minutes * 60);
-        drop(config);
-
-        let mut check_timer = interval(check_interval);
+        // Main service loop; the first check is jittered so a fleet booted
+        // together doesn't hit the server at once
+        let mut delay = schedule::jitter(self.config.read().await.schedule.jitter_secs);
 
         loop {
+            self.set_next_action(delay).await;
+
             tokio::select! {
-                _ = check_timer.tick() => {
+                _ = sleep(delay) => {
                     if *self.shutdown_requested.read().await {
                         break;
                     }
 
                     info!("Periodic update check triggered");
-                    if let Err(e) = self.perform_update_cycle().await {
-                        error!("Update cycle failed: {}", e);
-                        self.set_state(DaemonState::Error(e.to_string())).await;
+                    self.check_client_certificate().await;
+                    delay = match self.perform_update_cycle(true, self.dry_run).await {
+                        Ok(OtaResult::UpdateAvailable(metadata)) => {
+                            self.delay_until_window(&metadata).await
+                        }
+                        Ok(_) => self.next_check_delay().await,
+                        Err(e) => {
+                            error!("Update cycle failed: {}", e);
+                            self.set_state(DaemonState::Error(e.to_string())).await;
+
+                            // Wait before next attempt
+                            sleep(Duration::from_secs(300)).await; // 5 minutes
+                            self.set_state(DaemonState::Idle).await;
+                            self.next_check_delay().await
+                        }
+                    };
+                }
 
-                        // Wait before next attempt
-                        sleep(Duration::from_secs(300)).await; // 5 minutes
-                        self.set_state(DaemonState::Idle).await;
-                    }
+                _ = self.shutdown_notify.notified() => {
+                    break;
                 }
 
                 _ = tokio::signal::ctrl_c() => {
@@ -100,19 +278,97 @@ impl OtaDaemon {
         self.shutdown().await
     }
 
+    /// Warn ahead of time when the mutual TLS certificate is about to expire
+    async fn check_client_certificate(&self) {
+        let tls = self.config.read().await.tls.clone();
+        match client_certificate(&tls) {
+            Ok(Some(cert)) => {
+                if let Some(warning) = cert.expiry_warning(tls.cert_expiry_warning_days, Utc::now())
+                {
+                    warn!("{}", warning);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read client certificate: {:#}", e),
+        }
+    }
+
+    /// Delay before the next periodic check, with jitter
+    async fn next_check_delay(&self) -> Duration {
+        let config = self.config.read().await;
+        let check_interval = Duration::from_secs(config.check_interval_minutes * 60);
+        schedule::next_check_delay(check_interval, &config.schedule)
+    }
+
+    /// Delay before retrying a deferred update
+    ///
+    /// Wakes at the next window opening, or at the next check if that comes first.
+    async fn delay_until_window(&self, metadata: &KernelMetadata) -> Duration {
+        let check_delay = self.next_check_delay().await;
+        let schedule = self.config.read().await.schedule.clone();
+        let delay = match schedule::delay_until_window(&schedule, local_now()) {
+            Some(window_delay) => window_delay.min(check_delay),
+            None => check_delay,
+        };
+
+        info!(
+            "Update {} deferred until the maintenance window, next attempt in {}s",
+            metadata.latest_version,
+            delay.as_secs()
+        );
+        delay
+    }
+
+    async fn set_next_action(&self, delay: Duration) {
+        let next = chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().checked_add_signed(delay));
+        *self.next_action_at.write().await = next;
+    }
+
     /// Perform complete update cycle with retry logic
-    async fn perform_update_cycle(&self) -> Result<()> {
+    ///
+    /// With `respect_windows`, an update found outside the maintenance windows
+    /// is not downloaded and `OtaResult::UpdateAvailable` is returned instead.
+    /// With `dry_run`, the install is planned and `OtaResult::DryRun` returned.
+    async fn perform_update_cycle(
+        &self,
+        respect_windows: bool,
+        dry_run: Option<DryRun>,
+    ) -> Result<OtaResult> {
+        let _cycle = self.cycle_lock.lock().await;
         let start_time = Instant::now();
         let mut last_error = None;
 
         // Try up to 3 times
         for attempt in 1..=3 {
-            match self.try_update_cycle(attempt).await {
-                Ok(update_record) => {
+            match self
+                .try_update_cycle(attempt, respect_windows, dry_run)
+                .await
+            {
+                Ok(CycleOutcome::Installed(update_record)) => {
                     // Success - save to history
                     self.save_update_record(update_record).await?;
                     *self.last_check.write().await = Some(Utc::now());
-                    return Ok(());
+                    return Ok(OtaResult::UpdateInstalled);
+                }
+                Ok(CycleOutcome::DryRun(update_record, plan)) => {
+                    self.save_update_record(update_record).await?;
+                    self.set_state(DaemonState::Idle).await;
+                    *self.last_check.write().await = Some(Utc::now());
+                    return Ok(OtaResult::DryRun(plan));
+                }
+                Ok(CycleOutcome::Deferred(metadata)) => {
+                    self.set_state(DaemonState::Idle).await;
+                    *self.last_check.write().await = Some(Utc::now());
+                    return Ok(OtaResult::UpdateAvailable(metadata));
+                }
+                Ok(CycleOutcome::UpToDate) => {
+                    // Nothing to do - don't clutter the history
+                    info!("System is up to date");
+                    self.set_state(DaemonState::Idle).await;
+                    *self.last_check.write().await = Some(Utc::now());
+                    return Ok(OtaResult::NoUpdate);
                 }
                 Err(e) => {
                     warn!("Update attempt {} failed: {}", attempt, e);
@@ -132,12 +388,9 @@ impl OtaDaemon {
         let error = last_error.unwrap();
         error!("All update attempts failed: {}", error);
 
-        // Check if we need to rollback
-        if self.should_rollback(&error).await {
-            warn!("Performing automatic rollback");
-            if let Err(rollback_err) = self.perform_rollback().await {
-                error!("Rollback failed: {}", rollback_err);
-            }
+        // Check if we need to rollback; a dry run changed nothing to undo
+        if dry_run.is_none() {
+            self.rollback_if_needed(&error).await;
         }
 
         // Record the failure
@@ -147,6 +400,7 @@ impl OtaDaemon {
             status: UpdateStatus::Failed,
             error_message: Some(error.to_string()),
             duration_seconds: start_time.elapsed().as_secs(),
+            device_id: None,
         };
 
         self.save_update_record(failure_record).await?;
@@ -156,15 +410,24 @@ impl OtaDaemon {
     }
 
     /// Single update cycle attempt
-    async fn try_update_cycle(&self, attempt: u8) -> Result<UpdateRecord> {
+    async fn try_update_cycle(
+        &self,
+        attempt: u8,
+        respect_windows: bool,
+        dry_run: Option<DryRun>,
+    ) -> Result<CycleOutcome> {
         let start_time = Instant::now();
         info!("Starting update cycle (attempt {})", attempt);
 
         // Get timeout from config
         let config = self.config.read().await;
         let download_timeout = Duration::from_secs(config.download_timeout_secs);
+        let maintenance_windows = config.schedule.maintenance_windows.clone();
         drop(config);
 
+        // Image a delta update would be applied to
+        let base_image = self.installer.lock().await.active_kernel_path().await?;
+
         // Wrap download operations with timeout
         let download_result = timeout(download_timeout, async {
             // 1. Server Discovery
@@ -188,10 +451,23 @@ impl OtaDaemon {
                 }
                 None => {
                     info!("No updates available");
-                    return Ok((None, String::new()));
+                    return Ok(ControlFlow::Break(CycleOutcome::UpToDate));
                 }
             };
 
+            // Checks run at any time, but downloads and installs wait for a window
+            if respect_windows && !in_maintenance_window(&maintenance_windows, local_now()) {
+                info!("Outside maintenance window, not downloading yet");
+                return Ok(ControlFlow::Break(CycleOutcome::Deferred(Box::new(
+                    metadata,
+                ))));
+            }
+
+            // A dry run stops at the check unless it also verifies the image
+            if dry_run.is_some_and(|dry_run| !dry_run.download) {
+                return Ok(ControlFlow::Continue((metadata, None, Vec::new())));
+            }
+
             // 3. Download Update
             let downloaded_path = {
                 let progress_callback = {
@@ -208,27 +484,30 @@ impl OtaDaemon {
                 };
 
                 downloader
-                    .download_with_retries(&metadata, Some(progress_callback))
+                    .download_update(&metadata, &base_image, Some(progress_callback))
                     .await
                     .context("Failed to download kernel")?
             };
 
-            Ok((Some(metadata), downloaded_path))
+            let artifacts = downloader
+                .download_artifacts(&metadata)
+                .await
+                .context("Failed to download artifacts")?;
+
+            Ok(ControlFlow::Continue((
+                metadata,
+                Some(downloaded_path),
+                artifacts,
+            )))
         })
         .await;
 
         // Handle timeout or download results
-        let (metadata, downloaded_path) = match download_result {
-            Ok(Ok((Some(metadata), downloaded_path))) => (metadata, downloaded_path),
-            Ok(Ok((None, _))) => {
-                // No updates available
-                return Ok(UpdateRecord {
-                    timestamp: Utc::now(),
-                    version: "no-update".to_string(),
-                    status: UpdateStatus::Success,
-                    error_message: None,
-                    duration_seconds: start_time.elapsed().as_secs(),
-                });
+        let (metadata, downloaded_path, artifacts) = match download_result {
+            Ok(Ok(ControlFlow::Continue(downloaded))) => downloaded,
+            Ok(Ok(ControlFlow::Break(outcome))) => {
+                // Nothing to install this time
+                return Ok(outcome);
             }
             Ok(Err(e)) => {
                 return Err(e);
@@ -241,76 +520,458 @@ impl OtaDaemon {
             }
         };
 
-        info!("Kernel downloaded to: {}", downloaded_path);
+        if dry_run.is_some() {
+            let plan = self
+                .installer
+                .lock()
+                .await
+                .plan_install(downloaded_path.as_deref(), &artifacts, &metadata)
+                .await;
+            remove_downloads(downloaded_path.as_deref(), &artifacts).await;
+            let plan = plan.context("Dry run failed")?;
 
-        // 4. Install Update
-        let installation_callback = {
-            let state = Arc::clone(&self.state);
-            move |status: crate::installer::InstallationStatus| {
-                tokio::spawn({
-                    let state = Arc::clone(&state);
-                    async move {
-                        let mut state_guard = state.write().await;
-                        *state_guard = DaemonState::Installing(status);
-                    }
-                });
+            for operation in &plan.operations {
+                info!("Dry run: would {}", operation);
             }
-        };
+            let record = UpdateRecord {
+                timestamp: Utc::now(),
+                version: metadata.latest_version,
+                status: UpdateStatus::DryRun,
+                error_message: None,
+                duration_seconds: start_time.elapsed().as_secs(),
+                device_id: None,
+            };
+            return Ok(CycleOutcome::DryRun(record, Box::new(plan)));
+        }
+        let downloaded_path = downloaded_path.context("Kernel was not downloaded")?;
+
+        info!("Kernel downloaded to: {}", downloaded_path);
 
+        // 4. Install Update
+        let installation_callback = self.installation_callback();
         let mut installer = self.installer.lock().await;
         installer
-            .install_kernel(&downloaded_path, &metadata, Some(&installation_callback))
+            .install_update(
+                &downloaded_path,
+                &artifacts,
+                &metadata,
+                Some(&installation_callback),
+            )
             .await
             .context("Failed to install kernel")?;
 
         info!("Kernel installation completed successfully");
+        drop(installer);
 
-        // 5. Cleanup
-        if let Err(e) = tokio::fs::remove_file(&downloaded_path).await {
-            warn!("Failed to cleanup downloaded file: {}", e);
+        // The install itself succeeded, so don't fail the cycle over the marker
+        let config = self.config.read().await.clone();
+        if let Err(e) = mark_pending_confirmation(&config, &metadata).await {
+            error!("Failed to write boot confirmation marker: {}", e);
         }
 
+        // 5. Cleanup
+        remove_downloads(Some(&downloaded_path), &artifacts).await;
+
         // 6. Schedule reboot (if needed)
         self.set_state(DaemonState::Rebooting).await;
-        info!("Kernel update completed. System reboot may be required.");
+        self.schedule_reboot(&metadata.latest_version).await;
 
-        Ok(UpdateRecord {
+        Ok(CycleOutcome::Installed(UpdateRecord {
             timestamp: Utc::now(),
             version: metadata.latest_version,
             status: UpdateStatus::Success,
             error_message: None,
             duration_seconds: start_time.elapsed().as_secs(),
+            device_id: None,
+        }))
+    }
+
+    /// Record that the device must reboot into `version`, and when it will
+    async fn schedule_reboot(&self, version: &str) {
+        let config = self.config.read().await.clone();
+        let marker = match mark_pending_reboot(&config, version).await {
+            Ok(marker) => marker,
+            Err(e) => {
+                error!("Failed to write reboot marker: {}", e);
+                return;
+            }
+        };
+
+        let Some(reboot_at) = marker.reboot_at else {
+            info!("Kernel update completed. System reboot may be required.");
+            return;
+        };
+        info!(
+            "Kernel update completed. Reboot into version {} scheduled for {}",
+            version,
+            reboot_at.format("%Y-%m-%d %H:%M:%S UTC")
+        );
+
+        if config.reboot.policy == RebootPolicy::Delayed {
+            let message = format!(
+                "Kernel {} was installed. The system will reboot in {} seconds.",
+                version, config.reboot.delay_secs
+            );
+            if let Err(e) = self.reboot_executor.broadcast(&message).await {
+                warn!("Failed to broadcast reboot warning: {:#}", e);
+            }
+        }
+        self.reboot_notify.notify_one();
+    }
+
+    /// Reboot into a newly installed kernel once its reboot is due and nothing inhibits it
+    ///
+    /// The schedule lives in the reboot marker, so a reboot still pending when
+    /// the daemon restarts is picked up again.
+    async fn reboot_when_due(self: Arc<Self>) {
+        let config = self.config.read().await.clone();
+        match settle_pending_reboot(&config, current_boot_id().await.as_deref()).await {
+            Ok(Some(marker)) => debug!("Reboot into {} completed", marker.version),
+            Ok(None) => {}
+            Err(e) => error!("Failed to check reboot marker: {:#}", e),
+        }
+
+        loop {
+            let config = self.config.read().await.clone();
+            let marker = match load_pending_reboot(&config).await {
+                Ok(marker) => marker,
+                Err(e) => {
+                    error!("Failed to read reboot marker: {:#}", e);
+                    None
+                }
+            };
+
+            let Some((marker, reboot_at)) =
+                marker.and_then(|marker| marker.reboot_at.map(|at| (marker, at)))
+            else {
+                self.reboot_notify.notified().await;
+                continue;
+            };
+
+            let wait = (reboot_at - Utc::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = sleep(wait) => {}
+                // A newer install rescheduled the reboot
+                _ = self.reboot_notify.notified() => continue,
+            }
+
+            if let Err(e) = self.attempt_reboot(&config, marker).await {
+                error!("Reboot failed: {:#}", e);
+            }
+        }
+    }
+
+    /// Reboot now, or move the reboot back while an inhibitor holds it
+    async fn attempt_reboot(&self, config: &OtaConfig, mut marker: PendingReboot) -> Result<()> {
+        if let Some(inhibitor) = active_inhibitor(&config.reboot).await {
+            let retry_at = if config.reboot.policy == RebootPolicy::NextWindow
+                && !in_maintenance_window(&config.schedule.maintenance_windows, local_now())
+            {
+                // The window closed while the reboot was held back
+                reboot_due_at(&config.reboot, &config.schedule, Utc::now(), local_now())
+            } else {
+                Some(Utc::now() + retry_delay(&config.reboot))
+            };
+            warn!(
+                "Reboot into {} held back: {}; retrying at {}",
+                marker.version,
+                inhibitor,
+                retry_at.map_or_else(|| "-".to_string(), |at| at.to_rfc3339())
+            );
+            marker.reboot_at = retry_at;
+            return save_pending_reboot(config, &marker).await;
+        }
+
+        info!("Rebooting to activate kernel {}", marker.version);
+        self.set_state(DaemonState::Rebooting).await;
+        let message = format!("Rebooting now to activate kernel {}.", marker.version);
+        if let Err(e) = self.reboot_executor.broadcast(&message).await {
+            warn!("Failed to broadcast reboot warning: {:#}", e);
+        }
+
+        let result = self.reboot_executor.reboot().await;
+
+        // Once issued the reboot is not repeated; the new boot clears the marker
+        marker.reboot_at = match result {
+            Ok(()) => None,
+            Err(_) => Some(Utc::now() + retry_delay(&config.reboot)),
+        };
+        save_pending_reboot(config, &marker).await?;
+        result
+    }
+
+    /// Progress callback that mirrors installer progress into the daemon state
+    fn installation_callback(&self) -> impl Fn(InstallationStatus) + Send + Sync + 'static {
+        let state = Arc::clone(&self.state);
+        move |status: InstallationStatus| {
+            tokio::spawn({
+                let state = Arc::clone(&state);
+                async move {
+                    let mut state_guard = state.write().await;
+                    *state_guard = DaemonState::Installing(status);
+                }
+            });
+        }
+    }
+
+    /// Install a kernel from local files and record the outcome in the history
+    pub async fn install_local(&self, update: &LocalUpdate) -> Result<()> {
+        let _cycle = self.cycle_lock.lock().await;
+        let start_time = Instant::now();
+        let version = update.metadata.latest_version.clone();
+        info!(
+            "Installing version {} from {}",
+            version,
+            update.kernel_path.display()
+        );
+
+        let installation_callback = self.installation_callback();
+        let result = self
+            .installer
+            .lock()
+            .await
+            .install_update(
+                &update.kernel_path.to_string_lossy(),
+                &update.artifacts,
+                &update.metadata,
+                Some(&installation_callback),
+            )
+            .await
+            .context("Failed to install kernel");
+
+        let error_message = match &result {
+            Ok(()) => {
+                let config = self.config.read().await.clone();
+                if let Err(e) = mark_pending_confirmation(&config, &update.metadata).await {
+                    error!("Failed to write boot confirmation marker: {}", e);
+                }
+                self.set_state(DaemonState::Rebooting).await;
+                self.schedule_reboot(&version).await;
+                None
+            }
+            Err(e) => {
+                error!("Local installation of {} failed: {:#}", version, e);
+                self.set_state(DaemonState::Idle).await;
+                Some(e.to_string())
+            }
+        };
+
+        self.save_update_record(UpdateRecord {
+            timestamp: Utc::now(),
+            version,
+            status: if result.is_ok() {
+                UpdateStatus::Success
+            } else {
+                UpdateStatus::Failed
+            },
+            error_message,
+            duration_seconds: start_time.elapsed().as_secs(),
+            device_id: None,
         })
+        .await?;
+
+        result
+    }
+
+    /// Open a local source and install it, returning the installed version
+    pub async fn install_local_source(&self, source: &LocalSource) -> Result<String> {
+        let config = self.config.read().await.clone();
+        let update = open_local_update(&config, source).await?;
+
+        let result = self.install_local(&update).await;
+        update.cleanup().await;
+        result.map(|_| update.metadata.latest_version)
+    }
+
+    /// Look for signed bundles at the watched mount point until shutdown
+    async fn watch_bundles(self: Arc<Self>) {
+        let mut last_seen = None;
+        loop {
+            let watch = self.config.read().await.bundle_watch.clone();
+            if *self.shutdown_requested.read().await {
+                break;
+            }
+            if watch.enabled
+                && let Err(e) = self.scan_bundle_mount(&watch.path, &mut last_seen).await
+            {
+                warn!("Bundle at {} not installed: {:#}", watch.path, e);
+            }
+            sleep(Duration::from_secs(watch.scan_interval_secs.max(1))).await;
+        }
+    }
+
+    /// Install the bundle at `path` if it is new, signed and newer than the kernel
+    ///
+    /// `last_seen` holds the checksum of the bundle already handled, so one left
+    /// plugged in is not retried every scan; it resets once the bundle is gone.
+    async fn scan_bundle_mount(&self, path: &str, last_seen: &mut Option<String>) -> Result<()> {
+        let metadata_path = Path::new(path).join(BUNDLE_METADATA_FILE);
+        if !metadata_path.exists() {
+            *last_seen = None;
+            return Ok(());
+        }
+
+        let metadata = load_metadata_file(&metadata_path).await?;
+        if last_seen.as_deref() == Some(metadata.checksum.as_str()) {
+            return Ok(());
+        }
+        *last_seen = Some(metadata.checksum.clone());
+
+        // Check the same read of the bundle that gets installed, so media
+        // rewritten after the check can't slip in unverified metadata
+        let config = self.config.read().await.clone();
+        let update = open_local_update(
+            &config,
+            &LocalSource::Bundle {
+                path: path.to_string(),
+            },
+        )
+        .await?;
+        let result = self.install_watched_bundle(&config, path, &update).await;
+        update.cleanup().await;
+        result
+    }
+
+    /// Install an opened bundle from the watched mount if it passes the unattended checks
+    async fn install_watched_bundle(
+        &self,
+        config: &OtaConfig,
+        path: &str,
+        update: &LocalUpdate,
+    ) -> Result<()> {
+        let metadata = &update.metadata;
+        if !should_install_watched(config, metadata).await? {
+            info!(
+                "Bundle at {} holds version {}, not newer than the installed kernel",
+                path, metadata.latest_version
+            );
+            return Ok(());
+        }
+
+        info!(
+            "Found bundle for version {} at {}",
+            metadata.latest_version, path
+        );
+        self.install_local(update).await
+    }
+
+    /// Confirm a kernel installed before this boot, or revert it
+    async fn confirm_pending_boot(&self) -> Result<()> {
+        let config = self.config.read().await.clone();
+        if !config.boot_confirmation.enabled {
+            return Ok(());
+        }
+        // A revert must not interleave with an install from the control socket
+        let _cycle = self.cycle_lock.lock().await;
+
+        let installer = self.installer.lock().await;
+        let active_kernel = installer.active_kernel_path().await?;
+        let active_checksum = installer.calculate_file_checksum(&active_kernel).await?;
+        drop(installer);
+
+        let check = check_pending_confirmation(
+            &config,
+            current_boot_id().await,
+            running_kernel_release(),
+            &active_checksum,
+        )
+        .await?;
+
+        match check {
+            BootCheck::NothingPending | BootCheck::AwaitingReboot => Ok(()),
+            BootCheck::Confirmed(version) => {
+                clear_pending_confirmation(&config).await?;
+                info!("Boot of version {} confirmed", version);
+                Ok(())
+            }
+            BootCheck::Revert { version, reason } => {
+                error!("Reverting version {}: {}", version, reason);
+
+                // The kernel never proved it boots, so a security version it
+                // raised must not keep the device on it
+                let installer = self.installer.lock().await;
+                installer
+                    .rollback(true)
+                    .await
+                    .context("Rollback of unconfirmed boot failed")?;
+                drop(installer);
+
+                self.save_update_record(UpdateRecord {
+                    timestamp: Utc::now(),
+                    version,
+                    status: UpdateStatus::RolledBack,
+                    error_message: Some(reason),
+                    duration_seconds: 0,
+                    device_id: None,
+                })
+                .await?;
+                clear_pending_confirmation(&config).await?;
+
+                warn!("Previous kernel restored. System reboot is required to activate it.");
+                Ok(())
+            }
+        }
     }
 
     /// Determine if rollback is needed based on error type
     async fn should_rollback(&self, error: &anyhow::Error) -> bool {
-        let error_str = error.to_string().to_lowercase();
+        // A rejected signature, security version or pin, a full disk, or a
+        // pre-install hook stopping the install means nothing was installed
+        if error.chain().any(|cause| {
+            cause.is::<SignatureError>()
+                || cause.is::<AntiRollbackError>()
+                || cause.is::<PinnedError>()
+                || cause.is::<InsufficientSpaceError>()
+                || cause.is::<HookError>()
+        }) {
+            return false;
+        }
+
+        // A failed install undoes its own changes, and the previous kernel
+        // is older still; only an install whose undo failed is left half-done
+        error.downcast_ref::<UndoFailedError>().is_some()
+    }
+
+    /// Roll back after a failed update cycle if the failure left the system half-changed
+    async fn rollback_if_needed(&self, error: &anyhow::Error) {
+        if !self.should_rollback(error).await {
+            return;
+        }
 
-        // Rollback for installation failures but not for network/discovery issues
-        error_str.contains("install")
-            || error_str.contains("backup")
-            || error_str.contains("kernel")
-            || error_str.contains("checksum")
+        warn!("Performing automatic rollback");
+        if let Err(rollback_err) = self.perform_rollback(false).await {
+            error!("Rollback failed: {}", rollback_err);
+        }
     }
 
-    /// Perform automatic rollback
-    async fn perform_rollback(&self) -> Result<()> {
-        info!("Performing automatic rollback");
+    /// Roll back to the previous kernel
+    ///
+    /// Only a manual rollback may go below the anti-rollback counter.
+    async fn perform_rollback(&self, manual: bool) -> Result<()> {
+        info!(
+            "Performing {} rollback",
+            if manual { "manual" } else { "automatic" }
+        );
 
         let installer = self.installer.lock().await;
         installer
-            .rollback()
+            .rollback(manual)
             .await
             .context("Rollback operation failed")?;
 
         // Record rollback
+        let reason = if manual {
+            "Manual rollback to the previous kernel"
+        } else {
+            "Automatic rollback after failed update"
+        };
         let rollback_record = UpdateRecord {
             timestamp: Utc::now(),
             version: "rollback".to_string(),
             status: UpdateStatus::RolledBack,
-            error_message: Some("Automatic rollback after failed update".to_string()),
+            error_message: Some(reason.to_string()),
             duration_seconds: 0,
+            device_id: None,
         };
 
         drop(installer);
@@ -333,7 +994,11 @@ impl OtaDaemon {
         let check_interval = Duration::from_secs(config.check_interval_minutes * 60);
         drop(config);
 
-        let next_check_in = if let Some(last) = last_check {
+        let next_action_at = *self.next_action_at.read().await;
+        let next_check_in = if let Some(next) = next_action_at {
+            let remaining = next.signed_duration_since(Utc::now());
+            Duration::from_secs(remaining.num_seconds().max(0) as u64)
+        } else if let Some(last) = last_check {
             let elapsed = Utc::now().signed_duration_since(last);
             let elapsed_duration = Duration::from_secs(elapsed.num_seconds().max(0) as u64);
             check_interval.saturating_sub(elapsed_duration)
@@ -351,6 +1016,30 @@ impl OtaDaemon {
         }
     }
 
+    /// Copy of the retained update history, oldest first
+    pub async fn update_history(&self) -> Vec<UpdateRecord> {
+        self.update_history.lock().await.clone()
+    }
+
+    /// Gather the values exported on the metrics endpoint
+    pub async fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let config = self.config.read().await.clone();
+        let installed_version = match load_installed_version(&config).await {
+            Ok(record) => record.map(|r| r.version),
+            Err(e) => {
+                warn!("Failed to read installed version for metrics: {}", e);
+                None
+            }
+        };
+
+        MetricsSnapshot {
+            status: self.get_status().await,
+            history: self.update_history().await,
+            installed_version,
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+        }
+    }
+
     /// Reload configuration from file
     pub async fn reload_config(&self, config_path: &str) -> Result<()> {
         info!("Reloading configuration");
@@ -359,6 +1048,11 @@ impl OtaDaemon {
             .await
             .context("Failed to reload configuration")?;
 
+        // Build the new downloader first so a bad TLS setup leaves the old config in place
+        let mut new_downloader =
+            Downloader::new(new_config.clone()).context("Failed to reinitialize downloader")?;
+        new_downloader.set_byte_counter(Arc::clone(&self.bytes_downloaded));
+
         // Update components with new config
         let mut config_guard = self.config.write().await;
         *config_guard = new_config.clone();
@@ -366,7 +1060,7 @@ impl OtaDaemon {
 
         // Update downloader
         let mut downloader = self.downloader.lock().await;
-        *downloader = Downloader::new(new_config.clone());
+        *downloader = new_downloader;
         drop(downloader);
 
         // Update installer
@@ -382,24 +1076,73 @@ impl OtaDaemon {
     pub async fn request_shutdown(&self) {
         info!("Shutdown requested");
         *self.shutdown_requested.write().await = true;
+        self.shutdown_notify.notify_one();
+    }
+
+    /// Path of the configuration file the daemon was started with
+    pub fn config_path(&self) -> &str {
+        &self.config_path
     }
 
-    /// Force immediate update check
-    pub async fn force_update_check(&self) -> Result<()> {
+    /// Query the server for a newer kernel without downloading it
+    pub async fn check_for_update(&self) -> Result<Option<KernelMetadata>> {
+        info!("Checking for updates on request");
+        let _cycle = self.cycle_lock.lock().await;
+
+        let mut downloader = self.downloader.lock().await;
+        downloader
+            .discover_server()
+            .await
+            .context("Failed to discover server")?;
+        let update = downloader.check_for_updates().await?;
+        drop(downloader);
+
+        *self.last_check.write().await = Some(Utc::now());
+        Ok(update)
+    }
+
+    /// Force immediate update check; a dry-run daemon always plans instead of installing
+    pub async fn force_update_check(&self, dry_run: Option<DryRun>) -> Result<OtaResult> {
         info!("Forcing immediate update check");
-        self.perform_update_cycle().await
+        self.perform_update_cycle(false, dry_run.or(self.dry_run))
+            .await
     }
 
-    /// Perform manual rollback
-    pub async fn manual_rollback(&self) -> Result<()> {
+    /// Perform manual rollback, to a given version from the backup store if set
+    pub async fn manual_rollback(&self, version: Option<String>) -> Result<()> {
         info!("Manual rollback requested");
-        self.perform_rollback().await
+        // Never interleave with an update cycle or its automatic rollback
+        let _cycle = self.cycle_lock.lock().await;
+        let Some(version) = version else {
+            return self.perform_rollback(true).await;
+        };
+
+        self.installer
+            .lock()
+            .await
+            .rollback_to(&version)
+            .await
+            .with_context(|| format!("Rollback to version {} failed", version))?;
+
+        self.save_update_record(UpdateRecord {
+            timestamp: Utc::now(),
+            version: version.clone(),
+            status: UpdateStatus::RolledBack,
+            error_message: Some(format!("Manual rollback to version {}", version)),
+            duration_seconds: 0,
+            device_id: None,
+        })
+        .await?;
+
+        info!("Rollback to version {} completed", version);
+        Ok(())
     }
 
     /// Setup signal handlers for graceful shutdown and config reload
     async fn setup_signal_handlers(self: &Arc<Self>) -> Result<()> {
         // Setup SIGTERM handler
         let shutdown_flag = Arc::clone(&self.shutdown_requested);
+        let shutdown_notify = Arc::clone(&self.shutdown_notify);
         tokio::spawn(async move {
             if let Ok(()) =
                 tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
@@ -410,6 +1153,7 @@ impl OtaDaemon {
             {
                 info!("Received SIGTERM, requesting shutdown");
                 *shutdown_flag.write().await = true;
+                shutdown_notify.notify_one();
             }
         });
 
@@ -446,15 +1190,19 @@ impl OtaDaemon {
     }
 
     /// Save update record to history and persistent storage
-    async fn save_update_record(&self, record: UpdateRecord) -> Result<()> {
+    async fn save_update_record(&self, mut record: UpdateRecord) -> Result<()> {
+        if record.device_id.is_none() {
+            record.device_id = device_identity(&*self.config.read().await);
+        }
+
         // Add to in-memory history
         let mut history = self.update_history.lock().await;
         history.push(record);
 
-        // Keep only last 100 records
+        // Keep only the most recent records
         let len = history.len();
-        if len > 100 {
-            history.drain(0..len - 100);
+        if len > MAX_HISTORY_RECORDS {
+            history.drain(0..len - MAX_HISTORY_RECORDS);
         }
 
         // Save to file
@@ -463,34 +1211,20 @@ impl OtaDaemon {
         Ok(())
     }
 
-    /// Load update history from file
-    async fn load_update_history(log_file_path: &str) -> Result<Vec<UpdateRecord>> {
-        if !Path::new(log_file_path).exists() {
-            return Ok(Vec::new());
-        }
-
-        let content = tokio::fs::read_to_string(log_file_path)
-            .await
-            .context("Failed to read update history file")?;
-
-        let history: Vec<UpdateRecord> =
-            serde_json::from_str(&content).context("Failed to parse update history")?;
-
-        info!("Loaded {} update records from history", history.len());
-        Ok(history)
-    }
-
     /// Save update history to file
     async fn save_update_history(&self, history: &[UpdateRecord]) -> Result<()> {
-        let content =
-            serde_json::to_string_pretty(history).context("Failed to serialize update history")?;
+        save_history(&self.log_file_path, history).await
+    }
 
-        tokio::fs::write(&self.log_file_path, content)
-            .await
-            .context("Failed to write update history file")?;
+    /// Drop every update record, returning how many were removed
+    pub async fn clear_history(&self) -> Result<usize> {
+        let mut history = self.update_history.lock().await;
+        let removed = history.len();
+        history.clear();
+        self.save_update_history(&history).await?;
 
-        debug!("Saved {} update records to history", history.len());
-        Ok(())
+        info!("Cleared {} update records from history", removed);
+        Ok(removed)
     }
 
     /// Graceful shutdown
@@ -499,6 +1233,11 @@ impl OtaDaemon {
 
         self.set_state(DaemonState::Shutdown).await;
 
+        let socket_path = self.config.read().await.control_socket_path.clone();
+        if let Err(e) = tokio::fs::remove_file(&socket_path).await {
+            debug!("Control socket not removed: {}", e);
+        }
+
         // Save final state
         let history = self.update_history.lock().await;
         if let Err(e) = self.save_update_history(&history).await {
@@ -510,13 +1249,21 @@ impl OtaDaemon {
     }
 }
 
+/// Time to wait before trying an inhibited or failed reboot again
+fn retry_delay(settings: &RebootConfig) -> chrono::Duration {
+    chrono::Duration::seconds(settings.inhibitor_retry_secs.try_into().unwrap_or(i64::MAX))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::reboot::{RebootAction, RecordingReboot};
     use std::fs;
+    use std::path::PathBuf;
     use tempfile::TempDir;
 
-    async fn create_test_daemon() -> (TempDir, OtaDaemon) {
+    /// Write a test config into a fresh directory, returning its path
+    fn write_test_config() -> (TempDir, PathBuf, OtaConfig) {
         let temp_dir = TempDir::new().unwrap();
         let config_path = temp_dir.path().join("config.toml");
 
@@ -537,11 +1284,38 @@ mod tests {
             mdns_service: "_ota._tcp.local".to_string(),
             fallback_server: Some("http://localhost:8080".to_string()),
             download_timeout_secs: 30,
+            state_path: temp_dir.path().join("state").to_string_lossy().to_string(),
+            trusted_public_keys: Vec::new(),
+            allow_unsigned: false,
+            ab_slots: None,
+            boot_confirmation: BootConfirmationConfig::default(),
+            control_socket_path: temp_dir
+                .path()
+                .join("control.sock")
+                .to_string_lossy()
+                .to_string(),
+            metrics: MetricsConfig::default(),
+            schedule: ScheduleConfig::default(),
+            server_scheme: "http".to_string(),
+            tls: TlsConfig::default(),
+            device_id: None,
+            channel: UpdateChannel::default(),
+            device_model: None,
+            hardware_revision: None,
+            backups: BackupConfig::default(),
+            bundle_watch: BundleWatchConfig::default(),
+            reboot: RebootConfig::default(),
+            hooks: HooksConfig::default(),
         };
 
         let config_content = toml::to_string(&config).unwrap();
         fs::write(&config_path, config_content).unwrap();
 
+        (temp_dir, config_path, config)
+    }
+
+    async fn create_test_daemon() -> (TempDir, OtaDaemon) {
+        let (temp_dir, config_path, _config) = write_test_config();
         let daemon = OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap();
         (temp_dir, daemon)
     }
@@ -579,6 +1353,7 @@ mod tests {
             status: UpdateStatus::Success,
             error_message: None,
             duration_seconds: 120,
+            device_id: None,
         };
 
         daemon.save_update_record(record.clone()).await.unwrap();
@@ -586,6 +1361,22 @@ mod tests {
         let status = daemon.get_status().await;
         assert_eq!(status.update_count, 1);
         assert_eq!(status.last_update.as_ref().unwrap().version, "1.0.0");
+
+        // Records are stamped with the device identity
+        daemon.config.write().await.device_id = Some("pi-0042".to_string());
+        daemon.save_update_record(record).await.unwrap();
+        let last_update = daemon.get_status().await.last_update.unwrap();
+        assert_eq!(last_update.device_id.as_deref(), Some("pi-0042"));
+
+        // Clearing empties both the in-memory and the persisted history
+        assert_eq!(daemon.clear_history().await.unwrap(), 2);
+        assert_eq!(daemon.get_status().await.update_count, 0);
+        assert!(
+            load_history(&daemon.log_file_path)
+                .await
+                .unwrap()
+                .is_empty()
+        );
     }
 
     #[tokio::test]
@@ -594,9 +1385,11 @@ mod tests {
         let config_path = temp_dir.path().join("config.toml");
 
         // Modify config
-        let mut new_config = OtaConfig::default();
-        new_config.check_interval_minutes = 5;
-        new_config.download_path = temp_dir.path().to_string_lossy().to_string();
+        let new_config = OtaConfig {
+            check_interval_minutes: 5,
+            download_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
 
         let config_content = toml::to_string(&new_config).unwrap();
         fs::write(&config_path, config_content).unwrap();
@@ -621,15 +1414,431 @@ mod tests {
         assert!(*daemon.shutdown_requested.read().await);
     }
 
+    #[tokio::test]
+    async fn test_unconfirmed_boot_is_reverted() {
+        let (temp_dir, daemon) = create_test_daemon().await;
+        let config = daemon.config.read().await.clone();
+
+        fs::write(temp_dir.path().join("kernel.img"), b"new kernel").unwrap();
+        fs::write(temp_dir.path().join("kernel.img.backup"), b"old kernel").unwrap();
+
+        // Installed in an earlier boot, and the device has rebooted too often since
+        let marker = crate::boot::PendingConfirmation {
+            version: "2.0.0".to_string(),
+            checksum: "sha256:unused".to_string(),
+            installed_at: Utc::now(),
+            install_boot_id: Some("previous-boot".to_string()),
+            last_boot_id: Some("previous-boot".to_string()),
+            boot_attempts: config.boot_confirmation.max_boot_attempts,
+        };
+        crate::boot::save_pending_confirmation(&config, &marker)
+            .await
+            .unwrap();
+
+        daemon.confirm_pending_boot().await.unwrap();
+
+        let restored = fs::read(temp_dir.path().join("kernel.img")).unwrap();
+        assert_eq!(restored, b"old kernel");
+
+        let status = daemon.get_status().await;
+        let last_update = status.last_update.unwrap();
+        assert_eq!(last_update.version, "2.0.0");
+        assert_eq!(last_update.status, UpdateStatus::RolledBack);
+        assert!(
+            crate::boot::load_pending_confirmation(&config)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_boot_revert_waits_for_running_install() {
+        let (temp_dir, daemon) = create_test_daemon().await;
+        let config = daemon.config.read().await.clone();
+
+        fs::write(temp_dir.path().join("kernel.img"), b"new kernel").unwrap();
+        fs::write(temp_dir.path().join("kernel.img.backup"), b"old kernel").unwrap();
+        let marker = crate::boot::PendingConfirmation {
+            version: "2.0.0".to_string(),
+            checksum: "sha256:unused".to_string(),
+            installed_at: Utc::now(),
+            install_boot_id: Some("previous-boot".to_string()),
+            last_boot_id: Some("previous-boot".to_string()),
+            boot_attempts: config.boot_confirmation.max_boot_attempts,
+        };
+        crate::boot::save_pending_confirmation(&config, &marker)
+            .await
+            .unwrap();
+
+        // An install from the control socket is still running
+        let cycle = daemon.cycle_lock.lock().await;
+        let waited = timeout(Duration::from_millis(100), daemon.confirm_pending_boot()).await;
+        assert!(waited.is_err());
+        assert_eq!(
+            fs::read(temp_dir.path().join("kernel.img")).unwrap(),
+            b"new kernel"
+        );
+
+        drop(cycle);
+        daemon.confirm_pending_boot().await.unwrap();
+        assert_eq!(
+            fs::read(temp_dir.path().join("kernel.img")).unwrap(),
+            b"old kernel"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unconfirmed_boot_reverted_below_security_version() {
+        let (temp_dir, daemon) = create_test_daemon().await;
+        let config = daemon.config.read().await.clone();
+
+        fs::write(temp_dir.path().join("kernel.img"), b"new kernel").unwrap();
+        fs::write(temp_dir.path().join("kernel.img.backup"), b"old kernel").unwrap();
+
+        // The new kernel raised the counter from 1 to 2, then never came up
+        crate::security_version::record_installed_security_version(&config, 1)
+            .await
+            .unwrap();
+        crate::security_version::record_installed_security_version(&config, 2)
+            .await
+            .unwrap();
+        let marker = crate::boot::PendingConfirmation {
+            version: "2.0.0".to_string(),
+            checksum: "sha256:unused".to_string(),
+            installed_at: Utc::now(),
+            install_boot_id: Some("previous-boot".to_string()),
+            last_boot_id: Some("previous-boot".to_string()),
+            boot_attempts: config.boot_confirmation.max_boot_attempts,
+        };
+        crate::boot::save_pending_confirmation(&config, &marker)
+            .await
+            .unwrap();
+
+        daemon.confirm_pending_boot().await.unwrap();
+
+        let restored = fs::read(temp_dir.path().join("kernel.img")).unwrap();
+        assert_eq!(restored, b"old kernel");
+        let state = crate::security_version::load_security_version(&config)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.installed, 1);
+        assert_eq!(state.highest, 2);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_install_undone_at_startup() {
+        let (temp_dir, config_path, config) = write_test_config();
+        fs::write(temp_dir.path().join("kernel.img"), b"torn kernel").unwrap();
+        fs::write(temp_dir.path().join("kernel.img.backup"), b"old kernel").unwrap();
+
+        // Killed right after the rename, before the image was verified
+        let metadata = KernelMetadata {
+            latest_version: "2.0.0".to_string(),
+            kernel_file: "kernel-v2.0.0.img".to_string(),
+            file_size: 10,
+            checksum: crate::delta::checksum_of(b"new kernel"),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v2.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        };
+        let mut journal = crate::journal::InstallJournal::new(&metadata, None);
+        journal.step = crate::installer::InstallStep::KernelReplaced;
+        crate::journal::save_install_journal(&config, &journal)
+            .await
+            .unwrap();
+
+        let daemon = OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap();
+
+        let restored = fs::read(temp_dir.path().join("kernel.img")).unwrap();
+        assert_eq!(restored, b"old kernel");
+        assert!(
+            crate::journal::load_install_journal(&config)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let last_update = daemon.get_status().await.last_update.unwrap();
+        assert_eq!(last_update.version, "2.0.0");
+        assert_eq!(last_update.status, UpdateStatus::RolledBack);
+    }
+
+    #[tokio::test]
+    async fn test_local_install_recorded_in_history() {
+        let (temp_dir, config_path, mut config) = write_test_config();
+        config.allow_unsigned = true;
+        config.bundle_watch.path = temp_dir.path().join("usb").to_string_lossy().to_string();
+        fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+        fs::write(temp_dir.path().join("kernel.img"), b"old kernel").unwrap();
+        let daemon = OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap();
+
+        let bundle_dir = temp_dir.path().join("usb");
+        fs::create_dir_all(&bundle_dir).unwrap();
+        fs::write(bundle_dir.join("kernel-v2.0.0.img"), b"new kernel").unwrap();
+        let metadata = KernelMetadata {
+            latest_version: "2.0.0".to_string(),
+            kernel_file: "kernel-v2.0.0.img".to_string(),
+            file_size: 10,
+            checksum: crate::delta::checksum_of(b"new kernel"),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v2.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        };
+        fs::write(
+            bundle_dir.join(BUNDLE_METADATA_FILE),
+            serde_json::to_string(&metadata).unwrap(),
+        )
+        .unwrap();
+
+        // The watcher only takes signed bundles, even with allow_unsigned
+        let mut last_seen = None;
+        assert!(
+            daemon
+                .scan_bundle_mount(&config.bundle_watch.path, &mut last_seen)
+                .await
+                .is_err()
+        );
+        assert!(daemon.get_status().await.last_update.is_none());
+
+        // An explicit install goes through the same installer and history
+        let source = LocalSource::Bundle {
+            path: config.bundle_watch.path.clone(),
+        };
+        let version = daemon.install_local_source(&source).await.unwrap();
+        assert_eq!(version, "2.0.0");
+        assert_eq!(
+            fs::read(temp_dir.path().join("kernel.img")).unwrap(),
+            b"new kernel"
+        );
+        assert!(bundle_dir.join("kernel-v2.0.0.img").exists());
+
+        let last_update = daemon.get_status().await.last_update.unwrap();
+        assert_eq!(last_update.version, "2.0.0");
+        assert_eq!(last_update.status, UpdateStatus::Success);
+    }
+
+    /// Daemon with the given reboot settings whose reboots are only recorded
+    async fn create_reboot_test_daemon(
+        reboot: RebootConfig,
+    ) -> (TempDir, Arc<OtaDaemon>, Arc<RecordingReboot>) {
+        let (temp_dir, config_path, mut config) = write_test_config();
+        config.reboot = reboot;
+        fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+
+        let recorder = Arc::new(RecordingReboot::default());
+        let daemon = OtaDaemon::new(config_path.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_reboot_executor(Arc::clone(&recorder) as Arc<dyn RebootExecutor>);
+        (temp_dir, Arc::new(daemon), recorder)
+    }
+
+    #[tokio::test]
+    async fn test_immediate_reboot_after_install() {
+        let (_temp_dir, daemon, recorder) = create_reboot_test_daemon(RebootConfig {
+            policy: RebootPolicy::Immediate,
+            systemd_inhibitors: false,
+            ..RebootConfig::default()
+        })
+        .await;
+        tokio::spawn(Arc::clone(&daemon).reboot_when_due());
+
+        daemon.schedule_reboot("1.1.0").await;
+        for _ in 0..100 {
+            if recorder.actions().contains(&RebootAction::Reboot) {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            recorder.actions(),
+            [
+                RebootAction::Broadcast("Rebooting now to activate kernel 1.1.0.".to_string()),
+                RebootAction::Reboot
+            ]
+        );
+
+        // The marker stays until the next boot, but the reboot is not repeated
+        let config = daemon.config.read().await.clone();
+        let marker = load_pending_reboot(&config).await.unwrap().unwrap();
+        assert_eq!(marker.version, "1.1.0");
+        assert!(marker.reboot_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delayed_reboot_warns_and_waits_for_inhibitor() {
+        let (temp_dir, daemon, recorder) = create_reboot_test_daemon(RebootConfig {
+            policy: RebootPolicy::Delayed,
+            delay_secs: 0,
+            systemd_inhibitors: false,
+            ..RebootConfig::default()
+        })
+        .await;
+        let lock_file = temp_dir.path().join("busy.lock");
+        fs::write(&lock_file, "").unwrap();
+        daemon.config.write().await.reboot.lock_files =
+            vec![lock_file.to_string_lossy().to_string()];
+
+        daemon.schedule_reboot("1.1.0").await;
+        assert_eq!(
+            recorder.actions(),
+            [RebootAction::Broadcast(
+                "Kernel 1.1.0 was installed. The system will reboot in 0 seconds.".to_string()
+            )]
+        );
+
+        let config = daemon.config.read().await.clone();
+        let marker = load_pending_reboot(&config).await.unwrap().unwrap();
+        daemon.attempt_reboot(&config, marker).await.unwrap();
+        assert!(!recorder.actions().contains(&RebootAction::Reboot));
+
+        // Held back by the lock file, the reboot is retried later
+        let marker = load_pending_reboot(&config).await.unwrap().unwrap();
+        assert!(marker.reboot_at.unwrap() > Utc::now() + chrono::Duration::seconds(30));
+
+        fs::remove_file(&lock_file).unwrap();
+        daemon.attempt_reboot(&config, marker).await.unwrap();
+        assert_eq!(recorder.actions().last(), Some(&RebootAction::Reboot));
+    }
+
+    #[tokio::test]
+    async fn test_failed_ab_install_keeps_active_slot() {
+        let (temp_dir, config_path, mut config) = write_test_config();
+        let slots = AbSlotConfig {
+            slot_a_path: temp_dir
+                .path()
+                .join("kernel_a.img")
+                .to_string_lossy()
+                .to_string(),
+            slot_b_path: temp_dir
+                .path()
+                .join("kernel_b.img")
+                .to_string_lossy()
+                .to_string(),
+            boot_config_path: temp_dir
+                .path()
+                .join("config.txt")
+                .to_string_lossy()
+                .to_string(),
+        };
+        fs::write(&slots.slot_a_path, b"kernel zero").unwrap();
+        fs::write(&slots.boot_config_path, "kernel=kernel_a.img\n").unwrap();
+        config.ab_slots = Some(slots.clone());
+        config.allow_unsigned = true;
+        fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+        let daemon = OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap();
+
+        let image = temp_dir.path().join("kernel-v1.img");
+        fs::write(&image, b"kernel one").unwrap();
+        let metadata = KernelMetadata {
+            latest_version: "1.0.0".to_string(),
+            kernel_file: "kernel-v1.img".to_string(),
+            file_size: 10,
+            checksum: crate::delta::checksum_of(b"kernel one"),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v1.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        };
+        let mut installer = daemon.installer.lock().await;
+        installer
+            .install_update(&image.to_string_lossy(), &[], &metadata, None)
+            .await
+            .unwrap();
+
+        // The next image fails to install, which the installer undoes itself
+        let bad_metadata = KernelMetadata {
+            latest_version: "2.0.0".to_string(),
+            checksum: crate::delta::checksum_of(b"kernel two"),
+            ..metadata
+        };
+        let error = installer
+            .install_update(&image.to_string_lossy(), &[], &bad_metadata, None)
+            .await
+            .context("Failed to install kernel")
+            .unwrap_err();
+        drop(installer);
+        daemon.rollback_if_needed(&error).await;
+
+        assert_eq!(
+            fs::read_to_string(&slots.boot_config_path).unwrap(),
+            "kernel=kernel_b.img\n"
+        );
+        let installed = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0");
+    }
+
     #[tokio::test]
     async fn test_rollback_decision() {
         let (_temp_dir, daemon) = create_test_daemon().await;
 
-        let install_error = anyhow::anyhow!("Installation failed: checksum mismatch");
-        assert!(daemon.should_rollback(&install_error).await);
+        // Undone by the installer already; a rollback would go a version further back
+        let install_error = anyhow::anyhow!("Installation failed: checksum mismatch")
+            .context("Failed to install kernel");
+        assert!(!daemon.should_rollback(&install_error).await);
+
+        let undo_error = anyhow::anyhow!("Installation failed: checksum mismatch")
+            .context(UndoFailedError {
+                reason: "Backup file not found".to_string(),
+            })
+            .context("Failed to install kernel");
+        assert!(daemon.should_rollback(&undo_error).await);
 
         let network_error = anyhow::anyhow!("Network timeout during discovery");
         assert!(!daemon.should_rollback(&network_error).await);
+
+        // A download that failed never touched the installed kernel
+        let download_error =
+            anyhow::anyhow!("connection reset by peer").context("Failed to download kernel");
+        assert!(!daemon.should_rollback(&download_error).await);
+
+        let signature_error =
+            anyhow::Error::new(SignatureError::Invalid).context("Failed to download kernel");
+        assert!(!daemon.should_rollback(&signature_error).await);
+
+        let anti_rollback_error = anyhow::Error::new(AntiRollbackError {
+            version: "0.9.0".to_string(),
+            security_version: 1,
+            minimum: 2,
+        })
+        .context("Failed to install kernel");
+        assert!(!daemon.should_rollback(&anti_rollback_error).await);
+
+        let pinned_error = anyhow::Error::new(PinnedError {
+            version: "2.0.0".to_string(),
+            pin: crate::channel::UpdatePin::Hold,
+        })
+        .context("Failed to install kernel");
+        assert!(!daemon.should_rollback(&pinned_error).await);
+
+        let space_error = anyhow::Error::new(InsufficientSpaceError {
+            path: "/boot/kernel.img".into(),
+            required: 2048,
+            available: 1024,
+        })
+        .context("Not enough free space to install the kernel");
+        assert!(!daemon.should_rollback(&space_error).await);
+
+        let hook_error = anyhow::Error::new(HookError {
+            stage: crate::hooks::HookStage::BeforeBackup,
+            command: "systemctl stop app".to_string(),
+            reason: "exited with exit status: 1".to_string(),
+        })
+        .context("Install aborted by hook");
+        assert!(!daemon.should_rollback(&hook_error).await);
     }
 
     #[tokio::test]
@@ -643,6 +1852,7 @@ mod tests {
             status: UpdateStatus::Success,
             error_message: None,
             duration_seconds: 60,
+            device_id: None,
         };
 
         daemon.save_update_record(record).await.unwrap();
@@ -653,6 +1863,17 @@ mod tests {
         assert_eq!(status.update_count, 1);
     }
 
+    #[tokio::test]
+    async fn test_next_action_reported_in_status() {
+        let (_temp_dir, daemon) = create_test_daemon().await;
+
+        daemon.set_next_action(Duration::from_secs(600)).await;
+
+        let next_check_in = daemon.get_status().await.next_check_in;
+        assert!(next_check_in > Duration::from_secs(590));
+        assert!(next_check_in <= Duration::from_secs(600));
+    }
+
     #[tokio::test]
     async fn test_history_size_limit() {
         let (_temp_dir, daemon) = create_test_daemon().await;
@@ -665,6 +1886,7 @@ mod tests {
                 status: UpdateStatus::Success,
                 error_message: None,
                 duration_seconds: 60,
+                device_id: None,
             };
             daemon.save_update_record(record).await.unwrap();
         }
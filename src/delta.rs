@@ -0,0 +1,235 @@
+use crate::checksum::HASH_CHUNK_SIZE;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Patch format produced by `zstd --patch-from=<old> <new>`
+pub const ZSTD_PATCH_FORMAT: &str = "zstd";
+
+/// Largest back-reference window accepted when applying a patch (2 GiB)
+///
+/// `--patch-from` raises the window to cover the whole base image, so the
+/// decoder must allow more than zstd's 128 MiB default.
+const MAX_WINDOW_LOG: u32 = 31;
+
+/// Whether this client can apply patches in the given format
+pub fn is_supported_format(format: &str) -> bool {
+    format.eq_ignore_ascii_case(ZSTD_PATCH_FORMAT)
+}
+
+/// `sha256:<hex>` digest of a buffer, in the format used by the metadata
+pub fn checksum_of(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+/// Read-only memory map of a file
+///
+/// The patch decoder needs the whole base image as its reference; mapping it
+/// keeps it in the page cache instead of copying it onto the heap. The base is
+/// the running kernel, which installs only ever replace by rename.
+pub struct MappedFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl MappedFile {
+    /// Map `path` for reading
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open base image: {}", path.display()))?;
+        let len = usize::try_from(file.metadata()?.len()).context("Base image too large to map")?;
+        if len == 0 {
+            return Ok(Self {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to map base image: {}", path.display()));
+        }
+
+        Ok(Self { ptr, len })
+    }
+
+    /// Contents of the mapped file
+    pub fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// Rebuild a target image by applying a zstd patch to the base image
+///
+/// The output is streamed to `output` in bounded chunks and hashed on the
+/// way; it is capped at `expected_size` so a corrupt patch can't fill the
+/// disk. Returns the `sha256:<hex>` digest of what was written.
+pub fn apply_zstd_patch<R: BufRead, W: Write>(
+    base: &[u8],
+    patch: R,
+    mut output: W,
+    expected_size: u64,
+) -> Result<String> {
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch, base)
+        .context("Failed to initialize patch decoder")?;
+    decoder
+        .window_log_max(MAX_WINDOW_LOG)
+        .context("Failed to configure patch decoder")?;
+    let mut decoder = decoder.take(expected_size + 1);
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    let mut written = 0u64;
+    loop {
+        let read = decoder.read(&mut buffer).context("Failed to apply patch")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        output
+            .write_all(&buffer[..read])
+            .context("Failed to write patched image")?;
+        written += read as u64;
+    }
+    output.flush().context("Failed to write patched image")?;
+
+    if written != expected_size {
+        anyhow::bail!(
+            "Patched image is {} bytes, expected {}",
+            written,
+            expected_size
+        );
+    }
+
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Apply the patch file at `patch` to the file at `base`, writing `output`
+///
+/// The output is synced before returning its digest.
+pub fn apply_zstd_patch_file(
+    base: &Path,
+    patch: &Path,
+    output: &Path,
+    expected_size: u64,
+) -> Result<String> {
+    let base = MappedFile::open(base)?;
+    let patch =
+        File::open(patch).with_context(|| format!("Failed to open delta: {}", patch.display()))?;
+    let mut file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+
+    let checksum = apply_zstd_patch(
+        base.as_slice(),
+        std::io::BufReader::new(patch),
+        std::io::BufWriter::new(&mut file),
+        expected_size,
+    )?;
+    file.sync_all()
+        .with_context(|| format!("Failed to sync {}", output.display()))?;
+    Ok(checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn create_patch(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut encoder =
+            zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), 3, base).unwrap();
+        encoder.write_all(target).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn create_images() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let mut target = base.clone();
+        target[1000..1016].copy_from_slice(b"patched section!");
+        target.extend_from_slice(b"appended trailer");
+        (base, target)
+    }
+
+    #[test]
+    fn test_apply_zstd_patch() {
+        let (base, target) = create_images();
+        let patch = create_patch(&base, &target);
+        assert!(patch.len() < target.len() / 10);
+
+        let mut rebuilt = Vec::new();
+        let checksum =
+            apply_zstd_patch(&base, &patch[..], &mut rebuilt, target.len() as u64).unwrap();
+        assert_eq!(rebuilt, target);
+        assert_eq!(checksum, checksum_of(&target));
+    }
+
+    #[test]
+    fn test_patch_against_wrong_base() {
+        let (base, target) = create_images();
+        let patch = create_patch(&base, &target);
+
+        let other_base = vec![0u8; base.len()];
+        let result = apply_zstd_patch(&other_base, &patch[..], Vec::new(), target.len() as u64);
+
+        // Either decoding fails or the output differs; both must be caught
+        if let Ok(checksum) = result {
+            assert_ne!(checksum, checksum_of(&target));
+        }
+    }
+
+    #[test]
+    fn test_patch_size_mismatch() {
+        let (base, target) = create_images();
+        let patch = create_patch(&base, &target);
+
+        assert!(apply_zstd_patch(&base, &patch[..], Vec::new(), target.len() as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn test_apply_zstd_patch_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (base, target) = create_images();
+        let base_path = temp_dir.path().join("base.img");
+        let patch_path = temp_dir.path().join("update.zst");
+        let output_path = temp_dir.path().join("kernel.img.part");
+        std::fs::write(&base_path, &base).unwrap();
+        std::fs::write(&patch_path, create_patch(&base, &target)).unwrap();
+
+        let checksum =
+            apply_zstd_patch_file(&base_path, &patch_path, &output_path, target.len() as u64)
+                .unwrap();
+        assert_eq!(checksum, checksum_of(&target));
+        assert_eq!(std::fs::read(&output_path).unwrap(), target);
+        assert_eq!(MappedFile::open(&base_path).unwrap().as_slice(), &base[..]);
+    }
+
+    #[test]
+    fn test_supported_formats() {
+        assert!(is_supported_format("zstd"));
+        assert!(!is_supported_format("bsdiff"));
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Not enough free space on a filesystem for an update stage
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsufficientSpaceError {
+    /// First path of the stage that lives on the short filesystem
+    pub path: PathBuf,
+    pub required: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientSpaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "insufficient space for {}: {} bytes required, {} bytes available",
+            self.path.display(),
+            self.required,
+            self.available
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpaceError {}
+
+/// Bytes a stage will write under a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpaceRequirement {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+impl SpaceRequirement {
+    pub fn new(path: impl Into<PathBuf>, bytes: u64) -> Self {
+        Self {
+            path: path.into(),
+            bytes,
+        }
+    }
+}
+
+/// Closest ancestor of `path` that exists, so paths not created yet can be checked
+fn existing_ancestor(path: &Path) -> Result<&Path> {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .or_else(|| path.is_relative().then_some(Path::new(".")))
+        .with_context(|| format!("No existing directory above {}", path.display()))
+}
+
+/// Bytes available to unprivileged writers on the filesystem holding `path`
+pub fn available_space(path: &Path) -> Result<u64> {
+    let target = existing_ancestor(path)?;
+    let c_path = CString::new(target.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {}", target.display()))?;
+
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to query free space for {}", target.display()));
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok((stats.f_bavail as u64).saturating_mul(stats.f_frsize as u64))
+}
+
+/// Check every filesystem has room for what the stage writes to it
+///
+/// Paths are grouped by device, so requirements on the same mount add up.
+pub fn check_free_space(requirements: &[SpaceRequirement]) -> Result<()> {
+    // (device, first path, bytes required)
+    let mut filesystems: Vec<(u64, &Path, u64)> = Vec::new();
+    for requirement in requirements {
+        let target = existing_ancestor(&requirement.path)?;
+        let device = std::fs::metadata(target)
+            .with_context(|| format!("Failed to stat {}", target.display()))?
+            .dev();
+
+        match filesystems.iter_mut().find(|(dev, _, _)| *dev == device) {
+            Some((_, _, bytes)) => *bytes = bytes.saturating_add(requirement.bytes),
+            None => filesystems.push((device, &requirement.path, requirement.bytes)),
+        }
+    }
+
+    for (_, path, required) in filesystems {
+        let available = available_space(path)?;
+        debug!(
+            "Free space for {}: {} bytes required, {} available",
+            path.display(),
+            required,
+            available
+        );
+        if available < required {
+            return Err(InsufficientSpaceError {
+                path: path.to_path_buf(),
+                required,
+                available,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn is_insufficient_space(error: &anyhow::Error) -> bool {
+        error
+            .chain()
+            .any(|cause| cause.is::<InsufficientSpaceError>())
+    }
+
+    #[test]
+    fn test_available_space_of_missing_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let missing = temp_dir.path().join("not/created/yet");
+
+        // Resolved against the temp directory that does exist
+        available_space(&missing).unwrap();
+        check_free_space(&[SpaceRequirement::new(&missing, 1)]).unwrap();
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let temp_dir = TempDir::new().unwrap();
+
+        check_free_space(&[SpaceRequirement::new(temp_dir.path(), 1)]).unwrap();
+
+        let error =
+            check_free_space(&[SpaceRequirement::new(temp_dir.path(), u64::MAX)]).unwrap_err();
+        assert!(is_insufficient_space(&error));
+    }
+
+    #[test]
+    fn test_requirements_on_same_filesystem_add_up() {
+        let temp_dir = TempDir::new().unwrap();
+        let available = available_space(temp_dir.path()).unwrap();
+        // Margin for other tests freeing or using space meanwhile
+        let share = available / 4 * 3;
+
+        check_free_space(&[SpaceRequirement::new(temp_dir.path(), share)]).unwrap();
+
+        let error = check_free_space(&[
+            SpaceRequirement::new(temp_dir.path().join("a"), share),
+            SpaceRequirement::new(temp_dir.path().join("b"), share),
+        ])
+        .unwrap_err();
+        let space_error = error.downcast_ref::<InsufficientSpaceError>().unwrap();
+        assert_eq!(space_error.required, share * 2);
+    }
+}
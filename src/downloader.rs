@@ -1,35 +1,145 @@
-use crate::types::{DownloadProgress, KernelMetadata, OtaConfig, ServerInfo};
+use crate::artifacts::{StagedArtifact, validate_artifact_name, verify_artifact_file};
+use crate::channel::{UpdatePin, device_info, load_pin, version_query};
+use crate::checksum::file_checksum;
+use crate::delta::{apply_zstd_patch_file, is_supported_format};
+use crate::disk_space::{InsufficientSpaceError, SpaceRequirement, check_free_space};
+use crate::durable::durable_rename;
+use crate::security_version::minimum_security_version;
+use crate::signature::verify_metadata_signature;
+use crate::tls::{client_tls_config, select_scheme};
+use crate::types::{DeltaPatch, DownloadProgress, KernelMetadata, OtaConfig, ServerInfo};
+use crate::version::{is_newer, load_installed_version};
 use anyhow::{Context, Result};
 use futures_util::{pin_mut, stream::StreamExt};
 use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 // use std::io::Write;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use tracing::{debug, error, info, warn};
 
+/// Bytes written between checkpoints of a partial download
+const RESUME_CHECKPOINT_BYTES: u64 = 1024 * 1024;
+
+/// Sidecar state kept next to a partial download
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PartialDownload {
+    version: String,
+    checksum: String,
+    offset: u64,
+}
+
+/// Feed the first `len` bytes of a file into a hasher
+async fn hash_file_prefix(path: &str, len: u64, hasher: &mut Sha256) -> Result<()> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .context("Failed to open partial download")?;
+    let mut reader = file.take(len);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .await
+            .context("Failed to read partial download")?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(())
+}
+
+/// First byte of a `Content-Range: bytes START-END/TOTAL` header
+fn content_range_start(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    headers
+        .get(reqwest::header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .trim()
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Build the HTTP client, pointing the server's host name at its discovered address
+fn build_client(
+    config: &OtaConfig,
+    tls_config: Option<&rustls::ClientConfig>,
+    server: Option<&ServerInfo>,
+) -> Result<Client> {
+    let mut builder = Client::builder().timeout(Duration::from_secs(config.download_timeout_secs));
+
+    if let Some(tls_config) = tls_config {
+        builder = builder.use_preconfigured_tls(tls_config.clone());
+    }
+
+    // mDNS names usually aren't resolvable through the system resolver
+    if let Some(server) = server
+        && let Some(hostname) = &server.hostname
+    {
+        builder = builder.resolve(hostname, server.address);
+    }
+
+    builder.build().context("Failed to create HTTP client")
+}
+
 /// HTTP downloader with mDNS server discovery
 pub struct Downloader {
     client: Client,
     config: OtaConfig,
+    /// Custom TLS trust settings, kept to rebuild the client per server
+    tls_config: Option<rustls::ClientConfig>,
     server_info: Option<ServerInfo>,
+    /// Total bytes received from the server, shared with whoever reports it
+    bytes_received: Arc<AtomicU64>,
 }
 
 impl Downloader {
     /// Create new downloader instance
-    pub fn new(config: OtaConfig) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(config.download_timeout_secs))
-            .build()
-            .expect("Failed to create HTTP client");
+    pub fn new(config: OtaConfig) -> Result<Self> {
+        let tls_config = client_tls_config(&config.tls).context("Invalid TLS configuration")?;
+        let client = build_client(&config, tls_config.as_ref(), None)?;
 
-        Self {
+        Ok(Self {
             client,
             config,
+            tls_config,
             server_info: None,
-        }
+            bytes_received: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// HTTP client addressing the given server
+    fn client_for(&self, server: &ServerInfo) -> Result<Client> {
+        build_client(&self.config, self.tls_config.as_ref(), Some(server))
+    }
+
+    /// Remember the server and talk to it from now on
+    fn use_server(&mut self, server: &ServerInfo) -> Result<()> {
+        self.client = self.client_for(server)?;
+        self.server_info = Some(server.clone());
+        Ok(())
+    }
+
+    /// Count received bytes into a shared counter that outlives this downloader
+    pub fn set_byte_counter(&mut self, counter: Arc<AtomicU64>) {
+        self.bytes_received = counter;
+    }
+
+    /// Total bytes received from the server so far
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
     }
 
     /// Discover OTA server using mDNS
@@ -46,7 +156,7 @@ impl Downloader {
                     server.address.ip(),
                     server.address.port()
                 );
-                self.server_info = Some(server.clone());
+                self.use_server(&server)?;
                 Ok(server)
             }
             Err(e) => {
@@ -83,6 +193,7 @@ impl Downloader {
             let mut name = String::new();
             let mut ip = None;
             let mut port = None;
+            let mut advertised_scheme = None;
 
             for record in response.records() {
                 info!("Processing record: {:?}", &record.kind);
@@ -108,15 +219,35 @@ impl Downloader {
                         name = target.to_string();
                         debug!("Found SRV record: {}:{}", target, srv_port);
                     }
+                    RecordKind::TXT(entries) => {
+                        for entry in entries {
+                            if let Some(scheme) = entry.strip_prefix("scheme=") {
+                                advertised_scheme = Some(scheme.to_string());
+                                debug!("Found TXT scheme: {}", scheme);
+                            }
+                        }
+                    }
                     _ => {}
                 }
             }
 
             if let (Some(ip_addr), Some(port_num)) = (ip, port) {
+                let scheme =
+                    match select_scheme(&self.config.server_scheme, advertised_scheme.as_deref()) {
+                        Ok(scheme) => scheme,
+                        Err(e) => {
+                            warn!("Skipping server {}:{}: {}", ip_addr, port_num, e);
+                            continue;
+                        }
+                    };
+
                 let socket_addr = SocketAddr::new(ip_addr, *port_num);
+                let hostname = name.trim_end_matches('.');
                 let server_info = ServerInfo {
                     address: socket_addr,
                     name: name.clone(),
+                    scheme,
+                    hostname: (!hostname.is_empty()).then(|| hostname.to_string()),
                 };
 
                 // Test connectivity before returning
@@ -146,8 +277,10 @@ impl Downloader {
     async fn try_fallback_server(&mut self, server_url: &str) -> Result<ServerInfo> {
         let url = reqwest::Url::parse(server_url).context("Invalid fallback server URL")?;
 
+        let scheme = select_scheme(&self.config.server_scheme, Some(url.scheme()))
+            .context("Unusable fallback server URL")?;
         let host = url.host_str().context("No host in fallback server URL")?;
-        let port = url.port().unwrap_or(80);
+        let port = url.port_or_known_default().unwrap_or(80);
 
         let socket_addr = tokio::net::lookup_host((host, port))
             .await
@@ -155,13 +288,21 @@ impl Downloader {
             .next()
             .context("No address resolved for fallback server")?;
 
+        // Keep the name in URLs so TLS can check it, unless it's an IP literal
+        let hostname = host
+            .trim_matches(['[', ']'])
+            .parse::<std::net::IpAddr>()
+            .is_err()
+            .then(|| host.to_string());
         let server_info = ServerInfo {
             address: socket_addr,
             name: host.to_string(),
+            scheme,
+            hostname,
         };
 
         if self.test_server_connectivity(&server_info).await.is_ok() {
-            self.server_info = Some(server_info.clone());
+            self.use_server(&server_info)?;
             Ok(server_info)
         } else {
             anyhow::bail!("Fallback server is not responding")
@@ -170,16 +311,12 @@ impl Downloader {
 
     /// Test server connectivity
     async fn test_server_connectivity(&self, server: &ServerInfo) -> Result<()> {
-        let url = format!(
-            "http://{}:{}/health",
-            server.address.ip(),
-            server.address.port()
-        );
+        let url = format!("{}/health", server.base_url());
 
         debug!("Testing connectivity to: {}", url);
 
         let response = self
-            .client
+            .client_for(server)?
             .get(&url)
             .timeout(Duration::from_secs(5))
             .send()
@@ -201,17 +338,30 @@ impl Downloader {
             .as_ref()
             .context("No server discovered. Call discover_server() first")?;
 
-        let url = format!(
-            "http://{}:{}/version",
-            server.address.ip(),
-            server.address.port()
+        let url = format!("{}/version", server.base_url());
+
+        // An operator hold stops updates before the server is even asked
+        let pin = load_pin(&self.config).await?.map(|record| record.pin);
+        if pin == Some(UpdatePin::Hold) {
+            info!("Updates are held on this device, not checking the server");
+            return Ok(None);
+        }
+
+        info!(
+            "Checking for updates on the {} channel at: {}",
+            self.config.channel.name(),
+            url
         );
 
-        info!("Checking for updates at: {}", url);
+        // Tell the server what we run so it can pick an image and offer a delta
+        let installed = load_installed_version(&self.config).await?;
+        let device = device_info(&self.config).await;
+        let query = version_query(&self.config, installed.as_ref(), pin.as_ref(), &device);
 
         let response = self
             .client
             .get(&url)
+            .query(&query)
             .send()
             .await
             .context("Failed to check for updates")?;
@@ -226,16 +376,57 @@ impl Downloader {
             .context("Failed to parse version response")?;
 
         // Extract kernel info from response
-        let kernel_info = if let Some(kernel_info) = version_response.get("kernel_info") {
-            serde_json::from_value(kernel_info.clone())
-                .context("Failed to parse kernel metadata")?
-        } else {
-            // Fallback: try to parse entire response as KernelMetadata
-            serde_json::from_value(version_response)
-                .context("Failed to parse kernel metadata from response")?
-        };
+        let kernel_info: KernelMetadata =
+            if let Some(kernel_info) = version_response.get("kernel_info") {
+                serde_json::from_value(kernel_info.clone())
+                    .context("Failed to parse kernel metadata")?
+            } else {
+                // Fallback: try to parse entire response as KernelMetadata
+                serde_json::from_value(version_response)
+                    .context("Failed to parse kernel metadata from response")?
+            };
+
+        if let Some(pin) = &pin
+            && !pin.allows(&kernel_info.latest_version)
+        {
+            warn!(
+                "Ignoring server version {}: updates are {}",
+                kernel_info.latest_version, pin
+            );
+            return Ok(None);
+        }
+
+        // The installer would refuse it anyway, so don't download it
+        let minimum = minimum_security_version(&self.config).await?;
+        if kernel_info.security_version < minimum {
+            warn!(
+                "Ignoring server version {}: security version {} is below the minimum {}",
+                kernel_info.latest_version, kernel_info.security_version, minimum
+            );
+            return Ok(None);
+        }
 
-        Ok(Some(kernel_info))
+        // Compare against the kernel already on the device
+        match installed {
+            Some(installed) if !is_newer(&kernel_info.latest_version, &installed.version) => {
+                info!(
+                    "Server version {} is not newer than installed version {}",
+                    kernel_info.latest_version, installed.version
+                );
+                Ok(None)
+            }
+            Some(installed) => {
+                info!(
+                    "Server version {} is newer than installed version {}",
+                    kernel_info.latest_version, installed.version
+                );
+                Ok(Some(kernel_info))
+            }
+            None => {
+                info!("No installed version recorded, treating server version as an update");
+                Ok(Some(kernel_info))
+            }
+        }
     }
 
     /// Download kernel file with progress tracking
@@ -249,12 +440,13 @@ impl Downloader {
             .as_ref()
             .context("No server discovered. Call discover_server() first")?;
 
-        let url = format!(
-            "http://{}:{}{}",
-            server.address.ip(),
-            server.address.port(),
-            metadata.download_url
-        );
+        let url = format!("{}{}", server.base_url(), metadata.download_url);
+
+        // Refuse to fetch images that aren't signed by a trusted key
+        verify_metadata_signature(&self.config, metadata)
+            .context("Image signature verification failed")?;
+        validate_artifact_name(&metadata.kernel_file)
+            .context("Invalid kernel file name in kernel metadata")?;
 
         info!("Downloading kernel from: {}", url);
 
@@ -265,19 +457,67 @@ impl Downloader {
 
         let filename = &metadata.kernel_file;
         let file_path = format!("{}/{}", self.config.download_path, filename);
+        let part_path = format!("{}.part", file_path);
+        let sidecar_path = format!("{}.json", part_path);
+
+        // Pick up where a previous attempt left off
+        let mut resume_offset = self
+            .load_partial_download(metadata, &part_path, &sidecar_path)
+            .await;
+
+        // Only the part not already on disk still needs room
+        self.check_download_space(metadata.file_size.saturating_sub(resume_offset))?;
+
+        let mut request = self.client.get(&url);
+        if resume_offset > 0 {
+            info!("Resuming download at byte {}", resume_offset);
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_offset));
+        }
 
         // Start download
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to start download")?;
+        let mut response = request.send().await.context("Failed to start download")?;
+
+        if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+            // Stale partial state; start over on the next attempt
+            self.discard_partial_download(&part_path, &sidecar_path)
+                .await;
+            anyhow::bail!("Server rejected resume offset {}", resume_offset);
+        }
+
+        // A range starting elsewhere would be appended at the wrong offset
+        if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            let range_start = content_range_start(response.headers());
+            if range_start != Some(resume_offset) {
+                warn!(
+                    "Server answered resume at byte {} with range starting at {:?}, restarting download",
+                    resume_offset, range_start
+                );
+                self.discard_partial_download(&part_path, &sidecar_path)
+                    .await;
+                resume_offset = 0;
+                response = self
+                    .client
+                    .get(&url)
+                    .send()
+                    .await
+                    .context("Failed to restart download")?;
+            }
+        }
 
         if !response.status().is_success() {
             anyhow::bail!("Download failed with status: {}", response.status());
         }
 
+        // Server may ignore the Range header and send the whole file
+        let offset = if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+            resume_offset
+        } else {
+            if resume_offset > 0 {
+                warn!("Server does not support range requests, restarting download");
+            }
+            0
+        };
+
         // Get expected checksum from headers
         let expected_checksum = response
             .headers()
@@ -285,19 +525,47 @@ impl Downloader {
             .and_then(|h| h.to_str().ok())
             .map(|s| s.to_string());
 
-        let content_length = response.content_length().unwrap_or(metadata.file_size);
-
-        // Create file and download with progress tracking
-        let mut file = tokio::fs::File::create(&file_path)
+        let content_length = response
+            .content_length()
+            .map(|remaining| remaining + offset)
+            .unwrap_or(metadata.file_size);
+
+        // Open the partial file, dropping anything past the recorded offset
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&part_path)
             .await
             .context("Failed to create download file")?;
+        file.set_len(offset)
+            .await
+            .context("Failed to truncate download file")?;
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .context("Failed to seek download file")?;
 
-        let mut downloaded = 0u64;
+        // Rebuild the running hash from the bytes already on disk
         let mut hasher = Sha256::new();
+        if offset > 0 {
+            hash_file_prefix(&part_path, offset, &mut hasher).await?;
+        }
+
+        let mut downloaded = offset;
+        let mut last_saved = offset;
         let mut stream = response.bytes_stream();
 
         while let Some(chunk_result) = tokio_stream::StreamExt::next(&mut stream).await {
-            let chunk = chunk_result.context("Failed to read chunk")?;
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    // Keep what we have so the next attempt can resume
+                    file.flush().await.context("Failed to flush file")?;
+                    self.save_partial_download(metadata, &sidecar_path, downloaded)
+                        .await;
+                    return Err(e).context("Failed to read chunk");
+                }
+            };
 
             file.write_all(&chunk)
                 .await
@@ -305,6 +573,16 @@ impl Downloader {
 
             hasher.update(&chunk);
             downloaded += chunk.len() as u64;
+            self.bytes_received
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+
+            // Checkpoint the offset periodically
+            if downloaded - last_saved >= RESUME_CHECKPOINT_BYTES {
+                file.flush().await.context("Failed to flush file")?;
+                self.save_partial_download(metadata, &sidecar_path, downloaded)
+                    .await;
+                last_saved = downloaded;
+            }
 
             // Report progress
             if let Some(ref callback) = progress_callback {
@@ -318,6 +596,7 @@ impl Downloader {
         }
 
         file.flush().await.context("Failed to flush file")?;
+        drop(file);
 
         // Verify checksum
         let calculated_checksum = format!("sha256:{:x}", hasher.finalize());
@@ -329,7 +608,8 @@ impl Downloader {
                     expected, calculated_checksum
                 );
                 // Clean up corrupted file
-                let _ = tokio::fs::remove_file(&file_path).await;
+                self.discard_partial_download(&part_path, &sidecar_path)
+                    .await;
                 anyhow::bail!("Checksum verification failed");
             }
         } else if calculated_checksum != metadata.checksum {
@@ -337,16 +617,86 @@ impl Downloader {
                 "Checksum mismatch! Expected: {}, Got: {}",
                 metadata.checksum, calculated_checksum
             );
-            let _ = tokio::fs::remove_file(&file_path).await;
+            self.discard_partial_download(&part_path, &sidecar_path)
+                .await;
             anyhow::bail!("Checksum verification failed");
         }
 
+        // Promote the completed download to its final name
+        tokio::fs::rename(&part_path, &file_path)
+            .await
+            .context("Failed to finalize download file")?;
+        let _ = tokio::fs::remove_file(&sidecar_path).await;
+
         info!("Download completed successfully: {}", file_path);
         info!("Checksum verified: {}", calculated_checksum);
 
         Ok(file_path)
     }
 
+    /// Determine how many bytes of a previous partial download can be reused
+    async fn load_partial_download(
+        &self,
+        metadata: &KernelMetadata,
+        part_path: &str,
+        sidecar_path: &str,
+    ) -> u64 {
+        let partial = match tokio::fs::read_to_string(sidecar_path).await {
+            Ok(content) => serde_json::from_str::<PartialDownload>(&content).ok(),
+            Err(_) => None,
+        };
+
+        let Some(partial) = partial else {
+            return 0;
+        };
+
+        // A partial file for a different image can't be resumed
+        if partial.version != metadata.latest_version || partial.checksum != metadata.checksum {
+            info!("Discarding partial download of version {}", partial.version);
+            self.discard_partial_download(part_path, sidecar_path).await;
+            return 0;
+        }
+
+        let on_disk = match tokio::fs::metadata(part_path).await {
+            Ok(file_metadata) => file_metadata.len(),
+            Err(_) => return 0,
+        };
+
+        // Only trust bytes that were checkpointed and are actually on disk
+        partial.offset.min(on_disk).min(metadata.file_size)
+    }
+
+    /// Record the progress of a partial download in its sidecar file
+    async fn save_partial_download(
+        &self,
+        metadata: &KernelMetadata,
+        sidecar_path: &str,
+        offset: u64,
+    ) {
+        let partial = PartialDownload {
+            version: metadata.latest_version.clone(),
+            checksum: metadata.checksum.clone(),
+            offset,
+        };
+
+        match serde_json::to_string(&partial) {
+            Ok(content) => {
+                if let Err(e) = tokio::fs::write(sidecar_path, content).await {
+                    warn!("Failed to save partial download state: {}", e);
+                } else {
+                    debug!("Saved partial download state at byte {}", offset);
+                }
+            }
+            Err(e) => warn!("Failed to serialize partial download state: {}", e),
+        }
+    }
+
+    /// Remove a partial download and its sidecar
+    async fn discard_partial_download(&self, part_path: &str, sidecar_path: &str) {
+        let _ = tokio::fs::remove_file(part_path).await;
+        let _ = tokio::fs::remove_file(sidecar_path).await;
+    }
+
     /// Download with retry logic
     pub async fn download_with_retries(
         &self,
@@ -361,6 +711,10 @@ impl Downloader {
                 .await
             {
                 Ok(path) => return Ok(path),
+                // Retrying won't free up any space
+                Err(e) if e.chain().any(|cause| cause.is::<InsufficientSpaceError>()) => {
+                    return Err(e);
+                }
                 Err(e) => {
                     warn!("Download attempt {} failed: {}", attempt, e);
                     last_error = Some(e);
@@ -377,15 +731,320 @@ impl Downloader {
         Err(last_error.unwrap_or_else(|| anyhow::anyhow!("All download attempts failed")))
     }
 
+    /// Fetch an update, rebuilding it from a delta against `base_image` when offered
+    ///
+    /// Falls back to the full image if the delta can't be used for any reason.
+    pub async fn download_update(
+        &self,
+        metadata: &KernelMetadata,
+        base_image: &Path,
+        progress_callback: Option<Box<dyn Fn(DownloadProgress) + Send + Sync>>,
+    ) -> Result<String> {
+        if let Some(delta) = &metadata.delta {
+            match self
+                .download_delta(metadata, delta, base_image, progress_callback.as_deref())
+                .await
+            {
+                Ok(path) => return Ok(path),
+                Err(e) => warn!(
+                    "Delta update failed, falling back to full download: {:#}",
+                    e
+                ),
+            }
+        }
+
+        self.download_with_retries(metadata, progress_callback)
+            .await
+    }
+
+    /// Download a delta patch and rebuild the full image from `base_image`
+    async fn download_delta(
+        &self,
+        metadata: &KernelMetadata,
+        delta: &DeltaPatch,
+        base_image: &Path,
+        progress_callback: Option<&(dyn Fn(DownloadProgress) + Send + Sync)>,
+    ) -> Result<String> {
+        if !is_supported_format(&delta.format) {
+            anyhow::bail!("Unsupported delta format: {}", delta.format);
+        }
+
+        // The signature covers the full image checksum we verify at the end
+        verify_metadata_signature(&self.config, metadata)
+            .context("Image signature verification failed")?;
+        validate_artifact_name(&metadata.kernel_file)
+            .context("Invalid kernel file name in kernel metadata")?;
+
+        let base_checksum = file_checksum(base_image)
+            .await
+            .with_context(|| format!("Failed to read base image: {}", base_image.display()))?;
+        if base_checksum != delta.base_checksum {
+            anyhow::bail!(
+                "Installed image {} does not match delta base {} ({})",
+                base_checksum,
+                delta.base_checksum,
+                delta.base_version
+            );
+        }
+
+        tokio::fs::create_dir_all(&self.config.download_path)
+            .await
+            .context("Failed to create download directory")?;
+        let file_path = format!("{}/{}", self.config.download_path, metadata.kernel_file);
+        let patch_path = format!("{}.delta", file_path);
+        let part_path = format!("{}.part", file_path);
+
+        let result = self
+            .fetch_and_apply_delta(
+                metadata,
+                delta,
+                base_image,
+                &patch_path,
+                &part_path,
+                progress_callback,
+            )
+            .await;
+        let _ = tokio::fs::remove_file(&patch_path).await;
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+
+        durable_rename(Path::new(&part_path), Path::new(&file_path))
+            .await
+            .context("Failed to finalize rebuilt image")?;
+
+        info!("Rebuilt version {} from delta", metadata.latest_version);
+        info!("Checksum verified: {}", metadata.checksum);
+
+        Ok(file_path)
+    }
+
+    /// Stream a delta to `patch_path`, then rebuild the image into `part_path`
+    async fn fetch_and_apply_delta(
+        &self,
+        metadata: &KernelMetadata,
+        delta: &DeltaPatch,
+        base_image: &Path,
+        patch_path: &str,
+        part_path: &str,
+        progress_callback: Option<&(dyn Fn(DownloadProgress) + Send + Sync)>,
+    ) -> Result<()> {
+        let server = self
+            .server_info
+            .as_ref()
+            .context("No server discovered. Call discover_server() first")?;
+        self.check_download_space(delta.file_size.saturating_add(metadata.file_size))?;
+
+        let url = format!("{}{}", server.base_url(), delta.download_url);
+        info!(
+            "Downloading delta from {} ({} bytes instead of {})",
+            url, delta.file_size, metadata.file_size
+        );
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to start delta download")?;
+        if !response.status().is_success() {
+            anyhow::bail!("Delta download failed with status: {}", response.status());
+        }
+
+        let mut file = tokio::fs::File::create(patch_path)
+            .await
+            .context("Failed to create delta file")?;
+        let mut hasher = Sha256::new();
+        let mut downloaded = 0u64;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = tokio_stream::StreamExt::next(&mut stream).await {
+            let chunk = chunk.context("Failed to read delta chunk")?;
+            self.bytes_received
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            downloaded += chunk.len() as u64;
+
+            if downloaded > delta.file_size {
+                anyhow::bail!(
+                    "Delta is larger than the advertised {} bytes",
+                    delta.file_size
+                );
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk)
+                .await
+                .context("Failed to write delta chunk")?;
+
+            if let Some(callback) = progress_callback {
+                callback(DownloadProgress {
+                    downloaded,
+                    total: delta.file_size,
+                    percentage: (downloaded as f64 / delta.file_size as f64) * 100.0,
+                });
+            }
+        }
+        file.flush().await.context("Failed to flush delta file")?;
+        drop(file);
+
+        if format!("sha256:{:x}", hasher.finalize()) != delta.checksum {
+            anyhow::bail!("Delta checksum verification failed");
+        }
+
+        // Any partial full download is superseded by the rebuilt image
+        self.discard_partial_download(part_path, &format!("{}.json", part_path))
+            .await;
+
+        let base = base_image.to_path_buf();
+        let patch = PathBuf::from(patch_path);
+        let output = PathBuf::from(part_path);
+        let expected_size = metadata.file_size;
+        let target_checksum = tokio::task::spawn_blocking(move || {
+            apply_zstd_patch_file(&base, &patch, &output, expected_size)
+        })
+        .await
+        .context("Delta application task failed")??;
+
+        if target_checksum != metadata.checksum {
+            anyhow::bail!(
+                "Rebuilt image checksum {} does not match {}",
+                target_checksum,
+                metadata.checksum
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the download directory's filesystem has room for `bytes` more
+    fn check_download_space(&self, bytes: u64) -> Result<()> {
+        check_free_space(&[SpaceRequirement::new(&self.config.download_path, bytes)])
+            .context("Not enough free space to download the kernel")
+    }
+
+    /// Download and verify every artifact listed alongside the kernel
+    pub async fn download_artifacts(
+        &self,
+        metadata: &KernelMetadata,
+    ) -> Result<Vec<StagedArtifact>> {
+        if metadata.artifacts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let server = self
+            .server_info
+            .as_ref()
+            .context("No server discovered. Call discover_server() first")?;
+
+        // Artifact checksums are only trustworthy if the manifest is
+        verify_metadata_signature(&self.config, metadata)
+            .context("Image signature verification failed")?;
+
+        let artifact_dir = Path::new(&self.config.download_path).join("artifacts");
+        tokio::fs::create_dir_all(&artifact_dir)
+            .await
+            .context("Failed to create artifact download directory")?;
+
+        let mut staged = Vec::new();
+        for artifact in &metadata.artifacts {
+            validate_artifact_name(&artifact.name)?;
+            let path = artifact_dir.join(&artifact.name);
+
+            // Reuse a copy left by an earlier attempt if it is intact
+            if verify_artifact_file(&path, artifact).await.is_ok() {
+                debug!("Artifact {} already downloaded", artifact.name);
+                staged.push(StagedArtifact {
+                    artifact: artifact.clone(),
+                    path,
+                });
+                continue;
+            }
+
+            let url = format!("{}{}", server.base_url(), artifact.download_url);
+            info!("Downloading artifact {} from {}", artifact.name, url);
+
+            let response = self
+                .client
+                .get(&url)
+                .send()
+                .await
+                .with_context(|| format!("Failed to download artifact {}", artifact.name))?;
+            if !response.status().is_success() {
+                anyhow::bail!(
+                    "Artifact {} download failed with status: {}",
+                    artifact.name,
+                    response.status()
+                );
+            }
+
+            let mut file = tokio::fs::File::create(&path)
+                .await
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let mut written = 0u64;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = tokio_stream::StreamExt::next(&mut stream).await {
+                let chunk = chunk.context("Failed to read artifact chunk")?;
+                written += chunk.len() as u64;
+                if written > artifact.file_size {
+                    anyhow::bail!(
+                        "Artifact {} is larger than the advertised {} bytes",
+                        artifact.name,
+                        artifact.file_size
+                    );
+                }
+                self.bytes_received
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                file.write_all(&chunk)
+                    .await
+                    .context("Failed to write artifact chunk")?;
+            }
+            file.flush().await.context("Failed to flush artifact")?;
+            drop(file);
+
+            if let Err(e) = verify_artifact_file(&path, artifact).await {
+                let _ = tokio::fs::remove_file(&path).await;
+                return Err(e);
+            }
+
+            staged.push(StagedArtifact {
+                artifact: artifact.clone(),
+                path,
+            });
+        }
+
+        info!("Downloaded {} artifacts", staged.len());
+        Ok(staged)
+    }
+
     /// Get current server info
     pub fn get_server_info(&self) -> Option<&ServerInfo> {
         self.server_info.as_ref()
     }
 }
 
+/// Remove a downloaded kernel and its staged artifacts
+pub async fn remove_downloads(kernel_path: Option<&str>, artifacts: &[StagedArtifact]) {
+    if let Some(kernel_path) = kernel_path
+        && let Err(e) = tokio::fs::remove_file(kernel_path).await
+    {
+        warn!("Failed to cleanup downloaded file: {}", e);
+    }
+    for artifact in artifacts {
+        if let Err(e) = tokio::fs::remove_file(&artifact.path).await {
+            warn!(
+                "Failed to cleanup artifact {}: {}",
+                artifact.artifact.name, e
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::delta::checksum_of;
+    use crate::types::{
+        BackupConfig, BootConfirmationConfig, BundleWatchConfig, HooksConfig, MetricsConfig,
+        RebootConfig, ScheduleConfig, TlsConfig, UpdateChannel,
+    };
     use std::net::{IpAddr, Ipv4Addr};
 
     fn create_test_config() -> OtaConfig {
@@ -398,6 +1057,24 @@ mod tests {
             mdns_service: "_ota._tcp.local".to_string(),
             fallback_server: Some("http://192.168.1.100:8080".to_string()),
             download_timeout_secs: 30,
+            state_path: "/tmp/ota_test_state".to_string(),
+            trusted_public_keys: Vec::new(),
+            allow_unsigned: false,
+            ab_slots: None,
+            boot_confirmation: BootConfirmationConfig::default(),
+            control_socket_path: "/tmp/ota_test_control.sock".to_string(),
+            metrics: MetricsConfig::default(),
+            schedule: ScheduleConfig::default(),
+            server_scheme: "http".to_string(),
+            tls: TlsConfig::default(),
+            device_id: None,
+            channel: UpdateChannel::default(),
+            device_model: None,
+            hardware_revision: None,
+            backups: BackupConfig::default(),
+            bundle_watch: BundleWatchConfig::default(),
+            reboot: RebootConfig::default(),
+            hooks: HooksConfig::default(),
         }
     }
 
@@ -405,6 +1082,8 @@ mod tests {
         ServerInfo {
             address: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)), 8080),
             name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
         }
     }
 
@@ -417,13 +1096,17 @@ mod tests {
             release_date: "2025-06-16T10:30:00Z".to_string(),
             description: "Test kernel".to_string(),
             download_url: "/kernels/kernel-v1.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
         }
     }
 
     #[test]
     fn test_downloader_creation() {
         let config = create_test_config();
-        let downloader = Downloader::new(config.clone());
+        let downloader = Downloader::new(config.clone()).unwrap();
 
         assert_eq!(downloader.config.download_timeout_secs, 30);
         assert_eq!(downloader.config.max_retries, 3);
@@ -433,7 +1116,7 @@ mod tests {
     #[tokio::test]
     async fn test_fallback_server_parsing() {
         let config = create_test_config();
-        let mut downloader = Downloader::new(config);
+        let mut downloader = Downloader::new(config).unwrap();
 
         // Test with valid URL format
         let result = downloader
@@ -446,7 +1129,7 @@ mod tests {
     #[test]
     fn test_server_info_storage() {
         let config = create_test_config();
-        let mut downloader = Downloader::new(config);
+        let mut downloader = Downloader::new(config).unwrap();
         let server_info = create_test_server_info();
 
         assert!(downloader.get_server_info().is_none());
@@ -474,7 +1157,7 @@ mod tests {
     #[tokio::test]
     async fn test_download_directory_creation() {
         let config = create_test_config();
-        let _downloader = Downloader::new(config.clone());
+        let _downloader = Downloader::new(config.clone()).unwrap();
 
         // Clean up before test
         let _ = tokio::fs::remove_dir_all(&config.download_path).await;
@@ -518,7 +1201,7 @@ mod tests {
     #[tokio::test]
     async fn test_retry_logic_parameters() {
         let config = create_test_config();
-        let downloader = Downloader::new(config);
+        let downloader = Downloader::new(config).unwrap();
 
         // Test that retry logic uses correct max_retries
         assert_eq!(downloader.config.max_retries, 3);
@@ -540,22 +1223,583 @@ mod tests {
         let server = create_test_server_info();
         let metadata = create_test_metadata();
 
-        let version_url = format!(
-            "http://{}:{}/version",
-            server.address.ip(),
-            server.address.port()
-        );
+        let version_url = format!("{}/version", server.base_url());
         assert_eq!(version_url, "http://192.168.1.100:8080/version");
 
-        let download_url = format!(
-            "http://{}:{}{}",
-            server.address.ip(),
-            server.address.port(),
-            metadata.download_url
-        );
+        let download_url = format!("{}{}", server.base_url(), metadata.download_url);
         assert_eq!(
             download_url,
             "http://192.168.1.100:8080/kernels/kernel-v1.0.0.img"
         );
     }
+
+    /// Serve a single GET request, honouring `Range: bytes=N-`, and report the range asked for
+    async fn serve_once(body: Vec<u8>) -> (SocketAddr, tokio::task::JoinHandle<Option<u64>>) {
+        let (address, requests) = serve_requests(body, 1, 0).await;
+        let handle = tokio::spawn(async move { requests.await.unwrap().pop().flatten() });
+        (address, handle)
+    }
+
+    /// Serve `count` GET requests, answering ranges `skew` bytes past the one asked for
+    async fn serve_requests(
+        body: Vec<u8>,
+        count: usize,
+        skew: usize,
+    ) -> (SocketAddr, tokio::task::JoinHandle<Vec<Option<u64>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let mut ranges = Vec::new();
+            for _ in 0..count {
+                let (mut socket, _) = listener.accept().await.unwrap();
+
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let range_start = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim_end_matches('-').parse::<u64>().ok());
+
+                let header = match range_start {
+                    Some(start) => {
+                        let start = start as usize + skew;
+                        format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n",
+                            start,
+                            body.len() - 1,
+                            body.len(),
+                            body.len() - start
+                        )
+                    }
+                    None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n", body.len()),
+                };
+                let payload = &body[range_start.map_or(0, |start| start as usize + skew)..];
+                socket.write_all(header.as_bytes()).await.unwrap();
+                socket
+                    .write_all(b"Connection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                socket.write_all(payload).await.unwrap();
+                socket.shutdown().await.unwrap();
+
+                ranges.push(range_start);
+            }
+            ranges
+        });
+
+        (address, handle)
+    }
+
+    /// Answer a single `/version` request with `metadata`, reporting the request line
+    async fn serve_version_once(
+        metadata: KernelMetadata,
+    ) -> (SocketAddr, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            let body = serde_json::to_vec(&serde_json::json!({ "kernel_info": metadata })).unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(header.as_bytes()).await.unwrap();
+            socket.write_all(&body).await.unwrap();
+            socket.shutdown().await.unwrap();
+
+            let request = String::from_utf8_lossy(&request);
+            request.lines().next().unwrap_or_default().to_string()
+        });
+
+        (address, handle)
+    }
+
+    #[tokio::test]
+    async fn test_check_sends_channel_and_respects_pin() {
+        use crate::channel::{PinRecord, UpdatePin, save_pin};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            channel: UpdateChannel::Beta,
+            device_model: Some("Pi 4".to_string()),
+            hardware_revision: Some("c03114".to_string()),
+            ..create_test_config()
+        };
+        let mut downloader = Downloader::new(config.clone()).unwrap();
+        let serve = |address| ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        };
+
+        let (address, server) = serve_version_once(create_test_metadata()).await;
+        downloader.server_info = Some(serve(address));
+        let update = downloader.check_for_updates().await.unwrap();
+        assert_eq!(update, Some(create_test_metadata()));
+        assert_eq!(
+            server.await.unwrap(),
+            "GET /version?channel=beta&device_model=Pi+4&hardware_revision=c03114 HTTP/1.1"
+        );
+
+        // Pinned to another version: the server's offer is refused
+        let pin_to = |pin| PinRecord {
+            pin,
+            set_at: chrono::Utc::now(),
+        };
+        let pinned = UpdatePin::Version("0.9.0".to_string());
+        save_pin(&config, &pin_to(pinned)).await.unwrap();
+        let (address, server) = serve_version_once(create_test_metadata()).await;
+        downloader.server_info = Some(serve(address));
+        assert!(downloader.check_for_updates().await.unwrap().is_none());
+        assert!(server.await.unwrap().contains("&pinned_version=0.9.0 "));
+
+        // Held: the server isn't asked at all
+        save_pin(&config, &pin_to(UpdatePin::Hold)).await.unwrap();
+        downloader.server_info = Some(serve(address));
+        assert!(downloader.check_for_updates().await.unwrap().is_none());
+    }
+
+    fn create_resume_fixture(download_path: &str) -> (Downloader, KernelMetadata, Vec<u8>) {
+        let body: Vec<u8> = (0..4096u32).map(|i| (i % 251) as u8).collect();
+        let checksum = format!("sha256:{:x}", Sha256::digest(&body));
+
+        let config = OtaConfig {
+            download_path: download_path.to_string(),
+            allow_unsigned: true,
+            ab_slots: None,
+            boot_confirmation: BootConfirmationConfig::default(),
+            ..create_test_config()
+        };
+        let metadata = KernelMetadata {
+            file_size: body.len() as u64,
+            checksum,
+            ..create_test_metadata()
+        };
+
+        (Downloader::new(config).unwrap(), metadata, body)
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_from_partial_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, metadata, body) = create_resume_fixture(&download_path);
+
+        // Leave behind the first 1000 bytes from an interrupted attempt
+        let part_path = format!("{}/{}.part", download_path, metadata.kernel_file);
+        let sidecar_path = format!("{}.json", part_path);
+        tokio::fs::write(&part_path, &body[..1000]).await.unwrap();
+        downloader
+            .save_partial_download(&metadata, &sidecar_path, 1000)
+            .await;
+
+        let (address, server) = serve_once(body.clone()).await;
+        downloader.server_info = Some(ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        });
+
+        let path = downloader.download_kernel(&metadata, None).await.unwrap();
+
+        assert_eq!(server.await.unwrap(), Some(1000));
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        assert_eq!(downloader.bytes_received(), body.len() as u64 - 1000);
+        assert!(!std::path::Path::new(&part_path).exists());
+        assert!(!std::path::Path::new(&sidecar_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_content_range_restarts_download() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, metadata, body) = create_resume_fixture(&download_path);
+
+        let part_path = format!("{}/{}.part", download_path, metadata.kernel_file);
+        let sidecar_path = format!("{}.json", part_path);
+        tokio::fs::write(&part_path, &body[..1000]).await.unwrap();
+        downloader
+            .save_partial_download(&metadata, &sidecar_path, 1000)
+            .await;
+
+        // The server answers the resume with a range 24 bytes further on
+        let (address, server) = serve_requests(body.clone(), 2, 24).await;
+        downloader.server_info = Some(ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        });
+
+        let path = downloader.download_kernel(&metadata, None).await.unwrap();
+
+        assert_eq!(server.await.unwrap(), [Some(1000), None]);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+    }
+
+    #[test]
+    fn test_content_range_start() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(content_range_start(&headers), None);
+
+        headers.insert(
+            reqwest::header::CONTENT_RANGE,
+            "bytes 1000-4095/4096".parse().unwrap(),
+        );
+        assert_eq!(content_range_start(&headers), Some(1000));
+
+        headers.insert(
+            reqwest::header::CONTENT_RANGE,
+            "bytes */4096".parse().unwrap(),
+        );
+        assert_eq!(content_range_start(&headers), None);
+    }
+
+    #[tokio::test]
+    async fn test_partial_file_for_other_version_is_discarded() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, metadata, body) = create_resume_fixture(&download_path);
+
+        let part_path = format!("{}/{}.part", download_path, metadata.kernel_file);
+        let sidecar_path = format!("{}.json", part_path);
+        let other_metadata = KernelMetadata {
+            latest_version: "0.9.0".to_string(),
+            ..metadata.clone()
+        };
+        tokio::fs::write(&part_path, vec![0u8; 1000]).await.unwrap();
+        downloader
+            .save_partial_download(&other_metadata, &sidecar_path, 1000)
+            .await;
+
+        let (address, server) = serve_once(body.clone()).await;
+        downloader.server_info = Some(ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        });
+
+        let path = downloader.download_kernel(&metadata, None).await.unwrap();
+
+        assert_eq!(server.await.unwrap(), None);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+    }
+
+    fn attach_delta(metadata: &mut KernelMetadata, base: &[u8], target: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut encoder =
+            zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), 3, base).unwrap();
+        encoder.write_all(target).unwrap();
+        let patch = encoder.finish().unwrap();
+
+        metadata.delta = Some(DeltaPatch {
+            format: "zstd".to_string(),
+            base_version: "0.9.0".to_string(),
+            base_checksum: checksum_of(base),
+            download_url: "/deltas/kernel-v1.0.0.patch".to_string(),
+            file_size: patch.len() as u64,
+            checksum: checksum_of(&patch),
+        });
+        patch
+    }
+
+    #[tokio::test]
+    async fn test_delta_update_rebuilds_image() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, mut metadata, body) = create_resume_fixture(&download_path);
+
+        let mut base = body.clone();
+        base[100..110].copy_from_slice(b"old kernel");
+        let base_path = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&base_path, &base).await.unwrap();
+        let patch = attach_delta(&mut metadata, &base, &body);
+
+        let (address, server) = serve_once(patch.clone()).await;
+        downloader.server_info = Some(ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        });
+
+        let path = downloader
+            .download_update(&metadata, &base_path, None)
+            .await
+            .unwrap();
+
+        server.await.unwrap();
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+        assert_eq!(downloader.bytes_received(), patch.len() as u64);
+        assert!(!Path::new(&format!("{}.delta", path)).exists());
+        assert!(!Path::new(&format!("{}.part", path)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_delta_refused_before_download() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, mut metadata, body) = create_resume_fixture(&download_path);
+
+        let base_path = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&base_path, &body).await.unwrap();
+        attach_delta(&mut metadata, &body, &body);
+        let delta = metadata.delta.as_mut().unwrap();
+        delta.file_size = u64::MAX;
+        let delta = delta.clone();
+        downloader.server_info = Some(create_test_server_info());
+
+        // An absurd advertised size is an error, never an allocation
+        let error = downloader
+            .download_delta(&metadata, &delta, &base_path, None)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .chain()
+                .any(|cause| cause.is::<InsufficientSpaceError>())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kernel_file_outside_download_path_refused() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().join("downloads");
+        let download_path = download_path.to_string_lossy().to_string();
+        let (mut downloader, mut metadata, body) = create_resume_fixture(&download_path);
+        metadata.kernel_file = "../escaped.img".to_string();
+        downloader.server_info = Some(create_test_server_info());
+
+        let base_path = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&base_path, &body).await.unwrap();
+        attach_delta(&mut metadata, &body, &body);
+        let delta = metadata.delta.clone().unwrap();
+
+        let error = downloader
+            .download_kernel(&metadata, None)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Invalid kernel file name"));
+        let error = downloader
+            .download_delta(&metadata, &delta, &base_path, None)
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Invalid kernel file name"));
+        assert!(!temp_dir.path().join("escaped.img").exists());
+        assert!(!temp_dir.path().join("escaped.img.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_delta_falls_back_to_full_download() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let download_path = temp_dir.path().to_string_lossy().to_string();
+        let (mut downloader, mut metadata, body) = create_resume_fixture(&download_path);
+
+        // The patch was made against an image this device doesn't have
+        attach_delta(&mut metadata, &vec![0u8; body.len()], &body);
+        let base_path = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&base_path, b"some other kernel")
+            .await
+            .unwrap();
+
+        let (address, server) = serve_once(body.clone()).await;
+        downloader.server_info = Some(ServerInfo {
+            address,
+            name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
+        });
+
+        let path = downloader
+            .download_update(&metadata, &base_path, None)
+            .await
+            .unwrap();
+
+        server.await.unwrap();
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), body);
+    }
+
+    /// Serve `/health` over TLS with a fresh self-signed certificate for `ota.local`
+    ///
+    /// With `client_ca`, clients must present a certificate issued by it.
+    async fn serve_tls_health(
+        client_ca: Option<rustls::pki_types::CertificateDer<'static>>,
+    ) -> (SocketAddr, String, String) {
+        use base64::Engine;
+        use rcgen::PublicKeyData;
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["ota.local".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let pin = format!(
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD
+                .encode(Sha256::digest(key.subject_public_key_info()))
+        );
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let mut roots = rustls::RootCertStore::empty();
+                roots.add(client_ca).unwrap();
+                let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(roots),
+                    provider,
+                )
+                .build()
+                .unwrap();
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let server_config = builder
+            .with_single_cert(
+                vec![cert.der().clone()],
+                rustls::pki_types::PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(socket).await else {
+                        return;
+                    };
+                    let mut buffer = [0u8; 1024];
+                    let _ = stream.read(&mut buffer).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        )
+                        .await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        (address, cert.pem(), pin)
+    }
+
+    #[tokio::test]
+    async fn test_https_server_with_private_ca_and_pin() {
+        use base64::Engine;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (address, ca_pem, pin) = serve_tls_health(None).await;
+        let ca_path = temp_dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca_pem).unwrap();
+
+        let server = ServerInfo {
+            address,
+            name: "ota.local.".to_string(),
+            scheme: "https".to_string(),
+            hostname: Some("ota.local".to_string()),
+        };
+        let config_with_pins = |spki_pins: Vec<String>| OtaConfig {
+            server_scheme: "https".to_string(),
+            tls: TlsConfig {
+                ca_bundle_path: Some(ca_path.to_string_lossy().to_string()),
+                spki_pins,
+                ..TlsConfig::default()
+            },
+            ..create_test_config()
+        };
+
+        // Trusted CA and matching pin
+        let downloader = Downloader::new(config_with_pins(vec![pin])).unwrap();
+        downloader.test_server_connectivity(&server).await.unwrap();
+
+        // Trusted CA, but the key isn't the pinned one
+        let other_pin = format!(
+            "sha256/{}",
+            base64::engine::general_purpose::STANDARD.encode([0u8; 32])
+        );
+        let downloader = Downloader::new(config_with_pins(vec![other_pin])).unwrap();
+        assert!(downloader.test_server_connectivity(&server).await.is_err());
+
+        // Default roots don't know the private CA
+        let downloader = Downloader::new(OtaConfig {
+            server_scheme: "https".to_string(),
+            ..create_test_config()
+        })
+        .unwrap();
+        assert!(downloader.test_server_connectivity(&server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_presented_for_mutual_tls() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let client_cert = rcgen::CertificateParams::new(vec!["pi-0042".to_string()])
+            .unwrap()
+            .self_signed(&client_key)
+            .unwrap();
+        let (address, ca_pem, _) = serve_tls_health(Some(client_cert.der().clone())).await;
+
+        let path_of = |name: &str, content: String| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let ca_path = path_of("ca.pem", ca_pem);
+        let cert_path = path_of("client.pem", client_cert.pem());
+        let key_path = path_of("client.key", client_key.serialize_pem());
+
+        let server = ServerInfo {
+            address,
+            name: "ota.local".to_string(),
+            scheme: "https".to_string(),
+            hostname: Some("ota.local".to_string()),
+        };
+        let config_with_identity = |client_cert_path, client_key_path| OtaConfig {
+            tls: TlsConfig {
+                ca_bundle_path: Some(ca_path.clone()),
+                client_cert_path,
+                client_key_path,
+                ..TlsConfig::default()
+            },
+            ..create_test_config()
+        };
+
+        let downloader = Downloader::new(config_with_identity(
+            Some(cert_path.clone()),
+            Some(key_path.clone()),
+        ))
+        .unwrap();
+        downloader.test_server_connectivity(&server).await.unwrap();
+
+        // Without a certificate the server refuses the handshake
+        let downloader = Downloader::new(config_with_identity(None, None)).unwrap();
+        assert!(downloader.test_server_connectivity(&server).await.is_err());
+    }
 }
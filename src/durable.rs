@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use tracing::debug;
+
+/// Flush a file's data and metadata to stable storage
+pub async fn sync_file(path: &Path) -> Result<()> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {} for sync", path.display()))?;
+    file.sync_all()
+        .await
+        .with_context(|| format!("Failed to sync {}", path.display()))
+}
+
+/// Flush a directory so entries created or renamed in it survive a power cut
+///
+/// Filesystems that can't sync a directory handle report `EINVAL`; their
+/// entries are already written with the file data, so that is not an error.
+pub async fn sync_dir(dir: &Path) -> Result<()> {
+    let handle = tokio::fs::File::open(dir)
+        .await
+        .with_context(|| format!("Failed to open directory {} for sync", dir.display()))?;
+    match handle.sync_all().await {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+            debug!("Directory sync not supported for {}", dir.display());
+            Ok(())
+        }
+        result => result.with_context(|| format!("Failed to sync directory {}", dir.display())),
+    }
+}
+
+/// Flush the directory holding `path`
+pub async fn sync_parent(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent).await,
+        _ => sync_dir(Path::new(".")).await,
+    }
+}
+
+/// Sync a written file, then the directory entry pointing at it
+pub async fn sync_file_and_parent(path: &Path) -> Result<()> {
+    sync_file(path).await?;
+    sync_parent(path).await
+}
+
+/// Rename `from` over `to` and sync the directory so the rename is durable
+pub async fn durable_rename(from: &Path, to: &Path) -> Result<()> {
+    tokio::fs::rename(from, to)
+        .await
+        .with_context(|| format!("Failed to rename {} to {}", from.display(), to.display()))?;
+    sync_parent(to).await?;
+
+    // A rename across directories also changes the source directory
+    if from.parent() != to.parent() {
+        sync_parent(from).await?;
+    }
+    Ok(())
+}
+
+/// Load a JSON state record; `None` when it has not been written yet
+///
+/// `what` names the record in error messages.
+pub async fn read_json<T: DeserializeOwned>(path: &Path, what: &str) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}: {}", what, path.display()))?;
+
+    let value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}: {}", what, path.display()))?;
+    Ok(Some(value))
+}
+
+/// Persist a JSON state record, synced before returning
+///
+/// The record is written next to `path` and renamed over it, so a crash
+/// leaves either the old or the new record, never a torn one.
+pub async fn write_json<T: Serialize>(path: &Path, value: &T, what: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create state directory: {:?}", parent))?;
+    }
+
+    let content = serde_json::to_string_pretty(value)
+        .with_context(|| format!("Failed to serialize {}", what))?;
+
+    let temp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&temp_path, content)
+        .await
+        .with_context(|| format!("Failed to write {}: {}", what, temp_path.display()))?;
+    sync_file(&temp_path).await?;
+    durable_rename(&temp_path, path)
+        .await
+        .with_context(|| format!("Failed to replace {}: {}", what, path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_sync_file_and_parent() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&path, b"kernel").await.unwrap();
+
+        sync_file_and_parent(&path).await.unwrap();
+        assert!(sync_file(&temp_dir.path().join("missing")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_durable_rename() {
+        let temp_dir = TempDir::new().unwrap();
+        let from = temp_dir.path().join("kernel.installing");
+        let to = temp_dir.path().join("kernel.img");
+        tokio::fs::write(&from, b"new").await.unwrap();
+        tokio::fs::write(&to, b"old").await.unwrap();
+
+        durable_rename(&from, &to).await.unwrap();
+        assert!(!from.exists());
+        assert_eq!(tokio::fs::read(&to).await.unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn test_json_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("state").join("record.json");
+
+        let missing: Option<Vec<u64>> = read_json(&path, "record").await.unwrap();
+        assert!(missing.is_none());
+
+        write_json(&path, &vec![1u64, 2, 3], "record")
+            .await
+            .unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+        let loaded: Option<Vec<u64>> = read_json(&path, "record").await.unwrap();
+        assert_eq!(loaded, Some(vec![1, 2, 3]));
+
+        tokio::fs::write(&path, b"{not json").await.unwrap();
+        let error = read_json::<Vec<u64>>(&path, "record").await.unwrap_err();
+        assert!(error.to_string().contains("Failed to parse record"));
+    }
+}
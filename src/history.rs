@@ -0,0 +1,299 @@
+use crate::types::{OtaConfig, UpdateRecord, UpdateStatus};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Records kept in the history file; the oldest are dropped first
+pub const MAX_HISTORY_RECORDS: usize = 100;
+
+/// Path of the update history file for a configuration
+pub fn history_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.download_path).join("ota_update_history.json")
+}
+
+/// Load the update history, oldest first; empty when none has been written
+pub async fn load_history(path: &Path) -> Result<Vec<UpdateRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .context("Failed to read update history file")?;
+
+    serde_json::from_str(&content).context("Failed to parse update history")
+}
+
+/// Write the update history file
+pub async fn save_history(path: &Path, history: &[UpdateRecord]) -> Result<()> {
+    let content =
+        serde_json::to_string_pretty(history).context("Failed to serialize update history")?;
+
+    tokio::fs::write(path, content)
+        .await
+        .context("Failed to write update history file")?;
+
+    debug!("Saved {} update records to history", history.len());
+    Ok(())
+}
+
+/// Add a record to the history file, for commands run without the daemon
+pub async fn append_history(path: &Path, record: UpdateRecord) -> Result<()> {
+    let mut history = load_history(path).await?;
+    history.push(record);
+
+    let len = history.len();
+    if len > MAX_HISTORY_RECORDS {
+        history.drain(0..len - MAX_HISTORY_RECORDS);
+    }
+
+    save_history(path, &history).await
+}
+
+/// Selection of update records for `ota-client history`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryFilter {
+    pub status: Option<UpdateStatus>,
+    pub version: Option<String>,
+    /// Earliest timestamp included
+    pub since: Option<DateTime<Utc>>,
+    /// Latest timestamp included
+    pub until: Option<DateTime<Utc>>,
+    /// Keep only this many of the newest matching records
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    /// Whether a record passes every condition except the limit
+    pub fn matches(&self, record: &UpdateRecord) -> bool {
+        self.status.as_ref().is_none_or(|s| *s == record.status)
+            && self.version.as_deref().is_none_or(|v| v == record.version)
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.until.is_none_or(|until| record.timestamp <= until)
+    }
+
+    /// Matching records in their original order, trimmed to the newest `limit`
+    pub fn apply<'a>(&self, history: &'a [UpdateRecord]) -> Vec<&'a UpdateRecord> {
+        let mut selected: Vec<_> = history
+            .iter()
+            .filter(|record| self.matches(record))
+            .collect();
+
+        if let Some(limit) = self.limit {
+            let excess = selected.len().saturating_sub(limit);
+            selected.drain(..excess);
+        }
+        selected
+    }
+}
+
+/// Parse a date bound given as RFC 3339 or a plain `YYYY-MM-DD`
+///
+/// A plain date means the start of that day, or its end when `end_of_day` is
+/// set, so `--until 2025-06-16` still includes updates made that afternoon.
+fn parse_date_bound(value: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+
+    let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") else {
+        bail!("expected RFC 3339 or YYYY-MM-DD, got '{}'", value);
+    };
+    let time = if end_of_day {
+        date.and_hms_nano_opt(23, 59, 59, 999_999_999)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    Ok(time.expect("valid time of day").and_utc())
+}
+
+/// Parse a `--since` argument
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    parse_date_bound(value, false)
+}
+
+/// Parse an `--until` argument; a plain date covers the whole day
+pub fn parse_until(value: &str) -> Result<DateTime<Utc>> {
+    parse_date_bound(value, true)
+}
+
+/// Short label for an update status
+pub fn status_label(status: &UpdateStatus) -> &'static str {
+    match status {
+        UpdateStatus::Success => "success",
+        UpdateStatus::Failed => "failed",
+        UpdateStatus::RolledBack => "rolled-back",
+        UpdateStatus::DryRun => "dry-run",
+    }
+}
+
+/// Render records as an aligned table with a header row
+pub fn format_table(records: &[&UpdateRecord]) -> String {
+    let rows: Vec<[String; 5]> = records
+        .iter()
+        .map(|record| {
+            [
+                record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+                record.version.clone(),
+                status_label(&record.status).to_string(),
+                format!("{}s", record.duration_seconds),
+                record.error_message.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let header = ["TIME", "VERSION", "STATUS", "DURATION", "ERROR"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+/// Render records as JSON lines, one record per line
+pub fn format_json_lines(records: &[&UpdateRecord]) -> Result<String> {
+    let mut output = String::new();
+    for record in records {
+        output
+            .push_str(&serde_json::to_string(record).context("Failed to serialize update record")?);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::TempDir;
+
+    fn record(day: u32, version: &str, status: UpdateStatus) -> UpdateRecord {
+        UpdateRecord {
+            timestamp: Utc.with_ymd_and_hms(2025, 6, day, 10, 30, 0).unwrap(),
+            version: version.to_string(),
+            error_message: (status == UpdateStatus::Failed).then(|| "checksum mismatch".into()),
+            status,
+            duration_seconds: 42,
+            device_id: None,
+        }
+    }
+
+    fn sample_history() -> Vec<UpdateRecord> {
+        vec![
+            record(10, "1.0.0", UpdateStatus::Success),
+            record(12, "1.1.0", UpdateStatus::Failed),
+            record(14, "1.1.0", UpdateStatus::Success),
+            record(16, "1.2.0", UpdateStatus::RolledBack),
+        ]
+    }
+
+    fn versions(records: &[&UpdateRecord]) -> Vec<String> {
+        records.iter().map(|r| r.version.clone()).collect()
+    }
+
+    #[test]
+    fn test_filter_by_status_version_and_limit() {
+        let history = sample_history();
+
+        let filter = HistoryFilter {
+            status: Some(UpdateStatus::Success),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(versions(&filter.apply(&history)), ["1.0.0", "1.1.0"]);
+
+        let filter = HistoryFilter {
+            version: Some("1.1.0".into()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(filter.apply(&history).len(), 2);
+
+        // The limit keeps the newest records, still oldest first
+        let filter = HistoryFilter {
+            limit: Some(2),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(versions(&filter.apply(&history)), ["1.1.0", "1.2.0"]);
+    }
+
+    #[test]
+    fn test_filter_by_date_range() {
+        let history = sample_history();
+
+        let filter = HistoryFilter {
+            since: Some(parse_since("2025-06-12").unwrap()),
+            until: Some(parse_until("2025-06-14").unwrap()),
+            ..HistoryFilter::default()
+        };
+        let selected = filter.apply(&history);
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[0].status, UpdateStatus::Failed);
+        assert_eq!(selected[1].status, UpdateStatus::Success);
+
+        let filter = HistoryFilter {
+            since: Some(parse_since("2025-06-14T10:30:01+00:00").unwrap()),
+            ..HistoryFilter::default()
+        };
+        assert_eq!(versions(&filter.apply(&history)), ["1.2.0"]);
+
+        assert!(parse_since("last week").is_err());
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let history = sample_history();
+        let selected: Vec<_> = history.iter().collect();
+
+        let table = format_table(&selected);
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("TIME"));
+        assert!(lines[2].contains("failed") && lines[2].ends_with("checksum mismatch"));
+        assert!(lines[4].contains("rolled-back"));
+
+        let json = format_json_lines(&selected).unwrap();
+        let parsed: Vec<UpdateRecord> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(parsed.len(), 4);
+        assert_eq!(parsed[3].status, UpdateStatus::RolledBack);
+
+        assert_eq!(format_table(&[]).lines().count(), 1);
+        assert_eq!(format_json_lines(&[]).unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn test_history_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            download_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+        let path = history_path(&config);
+        assert!(load_history(&path).await.unwrap().is_empty());
+
+        save_history(&path, &sample_history()).await.unwrap();
+        let loaded = load_history(&path).await.unwrap();
+        assert_eq!(loaded.len(), 4);
+        assert_eq!(
+            loaded[1].error_message.as_deref(),
+            Some("checksum mismatch")
+        );
+    }
+}
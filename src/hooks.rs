@@ -0,0 +1,215 @@
+use crate::types::HooksConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Point in an install or rollback at which hooks run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    BeforeBackup,
+    AfterInstall,
+    AfterVerification,
+    OnFailure,
+    OnRollback,
+}
+
+impl HookStage {
+    /// Name used in the config file and in `OTA_HOOK_STAGE`
+    pub fn name(self) -> &'static str {
+        match self {
+            HookStage::BeforeBackup => "before_backup",
+            HookStage::AfterInstall => "after_install",
+            HookStage::AfterVerification => "after_verification",
+            HookStage::OnFailure => "on_failure",
+            HookStage::OnRollback => "on_rollback",
+        }
+    }
+
+    /// Commands configured for this stage
+    pub fn commands(self, settings: &HooksConfig) -> &[String] {
+        match self {
+            HookStage::BeforeBackup => &settings.before_backup,
+            HookStage::AfterInstall => &settings.after_install,
+            HookStage::AfterVerification => &settings.after_verification,
+            HookStage::OnFailure => &settings.on_failure,
+            HookStage::OnRollback => &settings.on_rollback,
+        }
+    }
+}
+
+impl fmt::Display for HookStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a hook is told about the install or rollback it runs in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HookContext {
+    /// Version being installed, or restored by a rollback
+    pub version: String,
+    /// Checksum of that version's image
+    pub checksum: String,
+    /// Kernel image the boot loader loads, or the slot being written
+    pub kernel_path: String,
+    pub backup_path: String,
+    /// Downloaded or local image being installed
+    pub image_path: Option<String>,
+    /// Version installed before this install or rollback
+    pub previous_version: Option<String>,
+    /// Why the install failed, for `on_failure` hooks
+    pub error: Option<String>,
+}
+
+impl HookContext {
+    /// Environment variables passed to hooks of `stage`
+    pub fn env(&self, stage: HookStage) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("OTA_HOOK_STAGE", stage.name().to_string()),
+            ("OTA_VERSION", self.version.clone()),
+            ("OTA_CHECKSUM", self.checksum.clone()),
+            ("OTA_KERNEL_PATH", self.kernel_path.clone()),
+            ("OTA_BACKUP_PATH", self.backup_path.clone()),
+        ];
+        let optional = [
+            ("OTA_IMAGE_PATH", &self.image_path),
+            ("OTA_PREVIOUS_VERSION", &self.previous_version),
+            ("OTA_ERROR", &self.error),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                env.push((name, value.clone()));
+            }
+        }
+        env
+    }
+}
+
+/// A hook command failed, timed out or could not be started
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookError {
+    pub stage: HookStage,
+    pub command: String,
+    pub reason: String,
+}
+
+impl fmt::Display for HookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hook `{}` {}", self.stage, self.command, self.reason)
+    }
+}
+
+impl std::error::Error for HookError {}
+
+/// Run the hooks of `stage` in order, stopping at the first that fails
+pub async fn run_hooks(
+    settings: &HooksConfig,
+    stage: HookStage,
+    context: &HookContext,
+) -> Result<(), HookError> {
+    let timeout = Duration::from_secs(settings.timeout_secs);
+
+    for command in stage.commands(settings) {
+        info!("Running {} hook: {}", stage, command);
+        let status = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(context.env(stage))
+            .kill_on_drop(true)
+            .status();
+
+        let reason = match tokio::time::timeout(timeout, status).await {
+            Ok(Ok(status)) if status.success() => continue,
+            Ok(Ok(status)) => format!("exited with {}", status),
+            Ok(Err(e)) => format!("could not be run: {}", e),
+            Err(_) => format!("timed out after {} seconds", timeout.as_secs()),
+        };
+        return Err(HookError {
+            stage,
+            command: command.clone(),
+            reason,
+        });
+    }
+
+    Ok(())
+}
+
+/// Run the hooks of `stage`, logging a failure instead of returning it
+pub async fn run_hooks_logged(settings: &HooksConfig, stage: HookStage, context: &HookContext) {
+    if let Err(e) = run_hooks(settings, stage, context).await {
+        warn!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn context() -> HookContext {
+        HookContext {
+            version: "1.1.0".to_string(),
+            checksum: "sha256:abc".to_string(),
+            kernel_path: "/boot/kernel8.img".to_string(),
+            backup_path: "/boot/kernel8.img.backup".to_string(),
+            image_path: Some("/var/lib/ota/kernel.img".to_string()),
+            ..HookContext::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_get_environment() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = temp_dir.path().join("env.txt");
+        let settings = HooksConfig {
+            after_install: vec![format!(
+                "echo \"$OTA_HOOK_STAGE $OTA_VERSION $OTA_CHECKSUM $OTA_KERNEL_PATH $OTA_IMAGE_PATH ${{OTA_ERROR:-none}}\" > {}",
+                output.display()
+            )],
+            ..HooksConfig::default()
+        };
+
+        run_hooks(&settings, HookStage::AfterInstall, &context())
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "after_install 1.1.0 sha256:abc /boot/kernel8.img /var/lib/ota/kernel.img none\n"
+        );
+
+        // Other stages run their own commands only
+        run_hooks(&settings, HookStage::OnRollback, &context())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failing_and_slow_hooks() {
+        let temp_dir = TempDir::new().unwrap();
+        let marker = temp_dir.path().join("ran");
+        let settings = HooksConfig {
+            before_backup: vec!["exit 4".to_string(), format!("touch {}", marker.display())],
+            on_failure: vec!["sleep 5".to_string()],
+            timeout_secs: 1,
+            ..HooksConfig::default()
+        };
+
+        let error = run_hooks(&settings, HookStage::BeforeBackup, &context())
+            .await
+            .unwrap_err();
+        assert_eq!(error.command, "exit 4");
+        assert!(
+            error
+                .to_string()
+                .starts_with("before_backup hook `exit 4` exited with")
+        );
+        assert!(!marker.exists());
+
+        let error = run_hooks(&settings, HookStage::OnFailure, &context())
+            .await
+            .unwrap_err();
+        assert_eq!(error.reason, "timed out after 1 seconds");
+    }
+}
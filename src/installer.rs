@@ -1,14 +1,48 @@
-use crate::types::{KernelMetadata, OtaConfig};
+use crate::artifacts::{
+    StagedArtifact, clear_artifact_state, install_artifacts, load_artifact_state,
+    restore_artifacts, rollback_artifacts,
+};
+use crate::backup_store::{
+    BackupEntry, backup_dir, backup_file_name, load_backup_index, prune_backups, save_backup_index,
+};
+use crate::boot::clear_pending_confirmation;
+use crate::channel::check_pin;
+use crate::checksum::{copy_and_hash, file_checksum, read_prefix};
+use crate::disk_space::{SpaceRequirement, check_free_space};
+use crate::durable::{durable_rename, sync_file_and_parent};
+use crate::hooks::{HookContext, HookStage, run_hooks, run_hooks_logged};
+use crate::journal::{
+    InstallJournal, UndoFailedError, advance_install_journal, clear_install_journal,
+    load_install_journal, save_install_journal,
+};
+use crate::security_version::{
+    check_plain_rollback, check_rollback_security_version, check_security_version,
+    load_security_version, record_installed_security_version, record_rollback_security_version,
+};
+use crate::signature::verify_metadata_signature;
+use crate::slots::{
+    SlotState, kernel_value_path, load_slot_state, read_active_kernel_value, save_slot_state,
+    slot_kernel_value, switch_boot_kernel,
+};
+use crate::types::{AbSlotConfig, KernelMetadata, OtaConfig};
+use crate::version::{
+    InstalledVersion, clear_installed_version, load_installed_version, save_installed_version,
+};
 use anyhow::{Context, Result};
-use sha2::{Digest, Sha256};
+use std::ffi::CString;
+use std::fmt;
 use std::fs::Permissions;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use tokio::fs as async_fs;
 use tracing::{debug, error, info, warn};
 
+/// Bytes of the image header needed to check the kernel format
+const KERNEL_HEADER_SIZE: usize = 64;
+
 /// Installation status tracking
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum InstallationStatus {
     NotStarted,
     BackupCreated,
@@ -18,6 +52,104 @@ pub enum InstallationStatus {
     Failed(String),
 }
 
+/// Points in an installation after which everything written so far is durable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum InstallStep {
+    /// The install journal is open, nothing has been changed yet
+    Started,
+    /// Artifacts of the new version are in place
+    ArtifactsInstalled,
+    /// Backups of the running kernel are on disk
+    BackupCreated,
+    /// The new kernel is copied into the workspace
+    KernelPrepared,
+    /// The new image is written next to its target, not yet renamed
+    KernelStaged,
+    /// The new image has replaced the target
+    KernelReplaced,
+    /// The new image is verified and selected for the next boot
+    Verified,
+    /// The installed version and security counter are recorded
+    Recorded,
+    /// The backup is written next to the kernel during a rollback
+    RollbackStaged,
+    /// The backup has replaced the kernel
+    RollbackReplaced,
+}
+
+/// File operation an install would perform, as reported by a dry run
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum PlannedOperation {
+    /// Put an artifact in place, moving the current one aside
+    InstallArtifact { name: String, destination: String },
+    /// Copy the running kernel to a backup location
+    Backup { source: String, destination: String },
+    /// Write the new image next to `target` and rename it over it
+    ReplaceKernel { target: String },
+    /// Point the boot config `kernel=` line at the new slot
+    SwitchBootConfig { boot_config: String, kernel: String },
+    /// Record the installed version and security version
+    RecordVersion {
+        version: String,
+        security_version: u64,
+    },
+    /// Run a configured hook command
+    RunHook { stage: HookStage, command: String },
+}
+
+impl fmt::Display for PlannedOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlannedOperation::InstallArtifact { name, destination } => {
+                write!(f, "install artifact {} to {}", name, destination)
+            }
+            PlannedOperation::Backup {
+                source,
+                destination,
+            } => write!(f, "back up {} to {}", source, destination),
+            PlannedOperation::ReplaceKernel { target } => {
+                write!(f, "replace {} with the new image", target)
+            }
+            PlannedOperation::SwitchBootConfig {
+                boot_config,
+                kernel,
+            } => {
+                write!(f, "set kernel={} in {}", kernel, boot_config)
+            }
+            PlannedOperation::RecordVersion {
+                version,
+                security_version,
+            } => write!(
+                f,
+                "record version {} (security version {})",
+                version, security_version
+            ),
+            PlannedOperation::RunHook { stage, command } => {
+                write!(f, "run {} hook `{}`", stage, command)
+            }
+        }
+    }
+}
+
+/// Outcome of a dry run: the checks passed and this is what an install would do
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct InstallPlan {
+    pub version: String,
+    /// Whether a downloaded image was checked against the metadata
+    pub image_verified: bool,
+    pub operations: Vec<PlannedOperation>,
+}
+
+/// What startup recovery did with an interrupted install
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryOutcome {
+    /// The new kernel was in place and verified, so the install was finished
+    Completed(KernelMetadata),
+    /// The install was undone and the previous kernel kept
+    RolledBack(KernelMetadata),
+}
+
 /// Installation progress callback
 pub type InstallProgressCallback = dyn Fn(InstallationStatus) + Send + Sync;
 
@@ -26,6 +158,9 @@ pub struct Installer {
     config: OtaConfig,
     backup_paths: Vec<PathBuf>,
     temp_dir: PathBuf,
+    /// Step at which a test simulates a power cut
+    #[cfg(test)]
+    crash_at: Option<InstallStep>,
 }
 
 impl Installer {
@@ -37,9 +172,233 @@ impl Installer {
             config,
             backup_paths: Vec::new(),
             temp_dir,
+            #[cfg(test)]
+            crash_at: None,
+        })
+    }
+
+    /// Install a kernel together with its artifacts as one transaction
+    ///
+    /// Artifacts go in first; if the kernel then fails to install, they are
+    /// restored so the device never boots with a mismatched set.
+    pub async fn install_update(
+        &mut self,
+        downloaded_kernel_path: &str,
+        artifacts: &[StagedArtifact],
+        metadata: &KernelMetadata,
+        progress_callback: Option<&InstallProgressCallback>,
+    ) -> Result<()> {
+        let hook_context = self
+            .install_hook_context(metadata, Some(downloaded_kernel_path))
+            .await;
+        let result = self
+            .install_with_hooks(
+                downloaded_kernel_path,
+                artifacts,
+                metadata,
+                progress_callback,
+                &hook_context,
+            )
+            .await;
+
+        if let Err(e) = &result {
+            let context = HookContext {
+                error: Some(format!("{:#}", e)),
+                ..hook_context
+            };
+            run_hooks_logged(&self.config.hooks, HookStage::OnFailure, &context).await;
+        }
+        result
+    }
+
+    /// Body of `install_update`, with the `before_backup` hooks able to stop it
+    async fn install_with_hooks(
+        &mut self,
+        downloaded_kernel_path: &str,
+        artifacts: &[StagedArtifact],
+        metadata: &KernelMetadata,
+        progress_callback: Option<&InstallProgressCallback>,
+        hook_context: &HookContext,
+    ) -> Result<()> {
+        // An install cut short earlier must be settled before starting over
+        self.recover_interrupted_install().await?;
+
+        // Refuse an old or pinned-out image before touching any artifacts
+        check_security_version(
+            &self.config,
+            &metadata.latest_version,
+            metadata.security_version,
+        )
+        .await?;
+        check_pin(&self.config, &metadata.latest_version).await?;
+
+        // Nothing has been changed yet, so a failing hook aborts cleanly
+        run_hooks(&self.config.hooks, HookStage::BeforeBackup, hook_context)
+            .await
+            .context("Install aborted by hook")?;
+
+        let previous_slot = match &self.config.ab_slots {
+            Some(slots) => {
+                Some(read_active_kernel_value(slots, &self.default_kernel_value()).await?)
+            }
+            None => None,
+        };
+        save_install_journal(&self.config, &InstallJournal::new(metadata, previous_slot))
+            .await
+            .context("Failed to open install journal")?;
+
+        let result = self
+            .install_journaled(
+                downloaded_kernel_path,
+                artifacts,
+                metadata,
+                progress_callback,
+            )
+            .await;
+        if let Err(e) = &result {
+            // Failures are already undone unless the undo itself failed; keep
+            // the journal then so recovery retries it at the next start
+            if e.downcast_ref::<UndoFailedError>().is_some() {
+                return result;
+            }
+            if let Err(e) = clear_install_journal(&self.config).await {
+                warn!("Failed to clear install journal: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Run the install checks and list what an install would do, writing nothing
+    ///
+    /// Without a downloaded image only the environment, space, security
+    /// version and pin checks run.
+    pub async fn plan_install(
+        &self,
+        downloaded_kernel_path: Option<&str>,
+        artifacts: &[StagedArtifact],
+        metadata: &KernelMetadata,
+    ) -> Result<InstallPlan> {
+        info!("Dry run for kernel version {}", metadata.latest_version);
+
+        check_security_version(
+            &self.config,
+            &metadata.latest_version,
+            metadata.security_version,
+        )
+        .await?;
+        check_pin(&self.config, &metadata.latest_version).await?;
+        self.validate_environment(metadata, artifacts).await?;
+        if let Some(path) = downloaded_kernel_path {
+            self.validate_downloaded_kernel(path, metadata).await?;
+        }
+
+        let hooks = |stage: HookStage| {
+            stage
+                .commands(&self.config.hooks)
+                .iter()
+                .map(move |command| PlannedOperation::RunHook {
+                    stage,
+                    command: command.clone(),
+                })
+        };
+
+        let mut operations: Vec<_> = hooks(HookStage::BeforeBackup).collect();
+        operations.extend(
+            artifacts
+                .iter()
+                .map(|staged| PlannedOperation::InstallArtifact {
+                    name: staged.artifact.name.clone(),
+                    destination: staged.artifact.destination.clone(),
+                }),
+        );
+
+        match &self.config.ab_slots {
+            Some(slots) => {
+                let active = read_active_kernel_value(slots, &self.default_kernel_value()).await?;
+                let (target_path, target_value) = inactive_slot(slots, &active);
+                operations.push(PlannedOperation::ReplaceKernel {
+                    target: target_path.to_string_lossy().to_string(),
+                });
+                operations.extend(hooks(HookStage::AfterInstall));
+                operations.push(PlannedOperation::SwitchBootConfig {
+                    boot_config: slots.boot_config_path.clone(),
+                    kernel: target_value,
+                });
+            }
+            None => {
+                operations.push(PlannedOperation::Backup {
+                    source: self.config.kernel_path.clone(),
+                    destination: self.config.backup_path.clone(),
+                });
+                operations.push(PlannedOperation::Backup {
+                    source: self.config.kernel_path.clone(),
+                    destination: backup_dir(&self.config).to_string_lossy().to_string(),
+                });
+                operations.push(PlannedOperation::ReplaceKernel {
+                    target: self.config.kernel_path.clone(),
+                });
+                operations.extend(hooks(HookStage::AfterInstall));
+            }
+        }
+
+        operations.extend(hooks(HookStage::AfterVerification));
+        operations.push(PlannedOperation::RecordVersion {
+            version: metadata.latest_version.clone(),
+            security_version: metadata.security_version,
+        });
+
+        Ok(InstallPlan {
+            version: metadata.latest_version.clone(),
+            image_verified: downloaded_kernel_path.is_some(),
+            operations,
         })
     }
 
+    /// Body of `install_update` once the journal is open
+    async fn install_journaled(
+        &mut self,
+        downloaded_kernel_path: &str,
+        artifacts: &[StagedArtifact],
+        metadata: &KernelMetadata,
+        progress_callback: Option<&InstallProgressCallback>,
+    ) -> Result<()> {
+        if artifacts.is_empty() {
+            // Artifacts of an older kernel must not be restored by a later rollback
+            clear_artifact_state(&self.config).await?;
+            return self
+                .install_kernel(downloaded_kernel_path, metadata, progress_callback)
+                .await;
+        }
+
+        // Nothing may be replaced before the kernel and the room for it are
+        // known good; install_kernel checks the kernel again on its own
+        self.validate_environment(metadata, artifacts).await?;
+        self.validate_downloaded_kernel(downloaded_kernel_path, metadata)
+            .await?;
+
+        let installed = install_artifacts(&self.config, &metadata.latest_version, artifacts)
+            .await
+            .context("Failed to install artifacts")?;
+        self.checkpoint(InstallStep::ArtifactsInstalled).await?;
+
+        if let Err(e) = self
+            .install_kernel(downloaded_kernel_path, metadata, progress_callback)
+            .await
+        {
+            error!("Kernel installation failed, restoring artifacts");
+            if let Err(restore_err) = restore_artifacts(&installed).await {
+                error!("CRITICAL: Artifact restore failed: {}", restore_err);
+                return Err(e.context(UndoFailedError {
+                    reason: restore_err.to_string(),
+                }));
+            }
+            clear_artifact_state(&self.config).await?;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
     /// Install kernel with full backup and verification
     pub async fn install_kernel(
         &mut self,
@@ -49,19 +408,38 @@ impl Installer {
     ) -> Result<()> {
         info!("Starting kernel installation: {}", metadata.latest_version);
         self.notify_progress(&progress_callback, InstallationStatus::NotStarted);
+        let hook_context = self
+            .install_hook_context(metadata, Some(downloaded_kernel_path))
+            .await;
 
         // Step 1: Pre-installation validation
-        self.validate_environment().await?;
+        self.validate_environment(metadata, &[]).await?;
         self.validate_downloaded_kernel(downloaded_kernel_path, metadata)
             .await?;
 
         // Step 2: Create temporary workspace
         self.setup_temp_workspace().await?;
 
+        if let Some(slots) = self.config.ab_slots.clone() {
+            // A/B mode: the running kernel is left untouched in its slot
+            self.install_to_inactive_slot(
+                &slots,
+                downloaded_kernel_path,
+                metadata,
+                &progress_callback,
+                &hook_context,
+            )
+            .await?;
+            return self
+                .finalize_installation(metadata, &progress_callback)
+                .await;
+        }
+
         // Step 3: Create comprehensive backup
         self.create_backup()
             .await
             .context("Failed to create kernel backup")?;
+        self.cleanup_old_backups(self.config.backups.keep).await?;
         self.notify_progress(&progress_callback, InstallationStatus::BackupCreated);
 
         // Step 4: Prepare new kernel in temp location
@@ -70,32 +448,39 @@ impl Installer {
             .await?;
 
         // Step 5: Atomic installation (the critical moment)
-        match self.perform_atomic_installation(&temp_kernel_path).await {
+        let kernel_path = PathBuf::from(&self.config.kernel_path);
+        match self
+            .perform_atomic_installation(&temp_kernel_path, &kernel_path)
+            .await
+        {
             Ok(_) => {
                 self.notify_progress(&progress_callback, InstallationStatus::KernelInstalled);
+                run_hooks_logged(&self.config.hooks, HookStage::AfterInstall, &hook_context).await;
 
                 // Step 6: Verify installation
-                if let Err(e) = self.verify_installation(metadata).await {
+                if let Err(e) = self.verify_installation(&kernel_path, metadata).await {
                     error!("Installation verification failed: {}", e);
                     // Attempt rollback
-                    if let Err(rollback_err) = self.rollback().await {
+                    if let Err(rollback_err) = self.restore_backup().await {
                         error!("CRITICAL: Rollback also failed: {}", rollback_err);
-                        return Err(anyhow::anyhow!(
-                            "Installation failed and rollback failed: {}. Manual intervention required.",
-                            rollback_err
-                        ));
+                        return Err(e.context(UndoFailedError {
+                            reason: rollback_err.to_string(),
+                        }));
                     }
                     return Err(e);
                 }
 
+                self.checkpoint(InstallStep::Verified).await?;
                 self.notify_progress(&progress_callback, InstallationStatus::Verified);
-
-                // Step 7: Cleanup and finalize
-                self.cleanup_temp_workspace().await?;
-                self.notify_progress(&progress_callback, InstallationStatus::Completed);
-
-                info!("Kernel installation completed successfully");
-                Ok(())
+                run_hooks_logged(
+                    &self.config.hooks,
+                    HookStage::AfterVerification,
+                    &hook_context,
+                )
+                .await;
+
+                self.finalize_installation(metadata, &progress_callback)
+                    .await
             }
             Err(e) => {
                 error!("Atomic installation failed: {}", e);
@@ -105,12 +490,11 @@ impl Installer {
                 );
 
                 // Attempt rollback
-                if let Err(rollback_err) = self.rollback().await {
+                if let Err(rollback_err) = self.restore_backup().await {
                     error!("CRITICAL: Rollback failed: {}", rollback_err);
-                    return Err(anyhow::anyhow!(
-                        "Installation failed and rollback failed: {}. Manual intervention required.",
-                        rollback_err
-                    ));
+                    return Err(e.context(UndoFailedError {
+                        reason: rollback_err.to_string(),
+                    }));
                 }
 
                 Err(e)
@@ -118,27 +502,172 @@ impl Installer {
         }
     }
 
+    /// Record the new version and clean up after a verified installation
+    async fn finalize_installation(
+        &self,
+        metadata: &KernelMetadata,
+        progress_callback: &Option<&InstallProgressCallback>,
+    ) -> Result<()> {
+        // Step 7: Record the newly installed version
+        self.record_installed_version(metadata).await?;
+        record_installed_security_version(&self.config, metadata.security_version).await?;
+        self.checkpoint(InstallStep::Recorded).await?;
+
+        // Step 8: Cleanup and finalize
+        self.cleanup_temp_workspace().await?;
+        clear_install_journal(&self.config).await?;
+        self.notify_progress(progress_callback, InstallationStatus::Completed);
+
+        info!("Kernel installation completed successfully");
+        Ok(())
+    }
+
+    /// Install into the inactive A/B slot and switch the boot config to it
+    async fn install_to_inactive_slot(
+        &self,
+        slots: &AbSlotConfig,
+        downloaded_kernel_path: &str,
+        metadata: &KernelMetadata,
+        progress_callback: &Option<&InstallProgressCallback>,
+        hook_context: &HookContext,
+    ) -> Result<()> {
+        let active = read_active_kernel_value(slots, &self.default_kernel_value()).await?;
+        let (target_path, target_value) = inactive_slot(slots, &active);
+
+        info!(
+            "Active kernel is {}, installing into inactive slot {}",
+            active, target_value
+        );
+
+        // The active slot stays in place as the fallback
+        self.notify_progress(progress_callback, InstallationStatus::BackupCreated);
+
+        let temp_kernel_path = self
+            .prepare_kernel_for_installation(downloaded_kernel_path)
+            .await?;
+        self.perform_atomic_installation(&temp_kernel_path, &target_path)
+            .await?;
+        self.notify_progress(progress_callback, InstallationStatus::KernelInstalled);
+        run_hooks_logged(&self.config.hooks, HookStage::AfterInstall, hook_context).await;
+
+        // Boot config is untouched until the new slot verifies
+        self.verify_installation(&target_path, metadata).await?;
+
+        switch_boot_kernel(slots, &target_value)
+            .await
+            .context("Failed to switch boot config to new slot")?;
+        save_slot_state(
+            &self.config,
+            &SlotState {
+                active: target_value,
+                previous: Some(active),
+            },
+        )
+        .await?;
+
+        self.checkpoint(InstallStep::Verified).await?;
+        self.notify_progress(progress_callback, InstallationStatus::Verified);
+        run_hooks_logged(
+            &self.config.hooks,
+            HookStage::AfterVerification,
+            hook_context,
+        )
+        .await;
+        Ok(())
+    }
+
+    /// What hooks are told about installing `metadata` from `image_path`
+    async fn install_hook_context(
+        &self,
+        metadata: &KernelMetadata,
+        image_path: Option<&str>,
+    ) -> HookContext {
+        let kernel_path = match &self.config.ab_slots {
+            Some(slots) => read_active_kernel_value(slots, &self.default_kernel_value())
+                .await
+                .map(|active| inactive_slot(slots, &active).0)
+                .unwrap_or_else(|_| PathBuf::from(&self.config.kernel_path)),
+            None => PathBuf::from(&self.config.kernel_path),
+        };
+
+        HookContext {
+            version: metadata.latest_version.clone(),
+            checksum: metadata.checksum.clone(),
+            kernel_path: kernel_path.to_string_lossy().to_string(),
+            backup_path: self.config.backup_path.clone(),
+            image_path: image_path.map(str::to_string),
+            previous_version: self.installed_version_name().await,
+            error: None,
+        }
+    }
+
+    /// Version recorded as installed, if it can be read
+    async fn installed_version_name(&self) -> Option<String> {
+        match load_installed_version(&self.config).await {
+            Ok(record) => record.map(|record| record.version),
+            Err(e) => {
+                warn!("Failed to read installed version for hooks: {}", e);
+                None
+            }
+        }
+    }
+
+    /// `kernel=` value assumed when the boot config has none
+    fn default_kernel_value(&self) -> String {
+        Path::new(&self.config.kernel_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.config.kernel_path.clone())
+    }
+
+    /// Path of the kernel image the boot loader will load
+    pub async fn active_kernel_path(&self) -> Result<PathBuf> {
+        match &self.config.ab_slots {
+            Some(slots) => {
+                let active = read_active_kernel_value(slots, &self.default_kernel_value()).await?;
+                Ok(kernel_value_path(slots, &active))
+            }
+            None => Ok(PathBuf::from(&self.config.kernel_path)),
+        }
+    }
+
     /// Validate system environment before installation
-    async fn validate_environment(&self) -> Result<()> {
+    async fn validate_environment(
+        &self,
+        metadata: &KernelMetadata,
+        artifacts: &[StagedArtifact],
+    ) -> Result<()> {
         info!("Validating installation environment");
 
-        // Check if kernel path exists and is writable
-        let kernel_path = Path::new(&self.config.kernel_path);
-        if !kernel_path.exists() {
-            anyhow::bail!("Kernel path does not exist: {}", self.config.kernel_path);
-        }
+        let parent_dir = if let Some(slots) = &self.config.ab_slots {
+            // Check the boot config we will switch exists
+            if !Path::new(&slots.boot_config_path).exists() {
+                anyhow::bail!("Boot config does not exist: {}", slots.boot_config_path);
+            }
+
+            Path::new(&slots.slot_a_path)
+                .parent()
+                .context("Cannot determine slot parent directory")?
+        } else {
+            // Check if kernel path exists and is writable
+            let kernel_path = Path::new(&self.config.kernel_path);
+            if !kernel_path.exists() {
+                anyhow::bail!("Kernel path does not exist: {}", self.config.kernel_path);
+            }
+
+            kernel_path
+                .parent()
+                .context("Cannot determine kernel parent directory")?
+        };
 
         // Check parent directory permissions
-        let parent_dir = kernel_path
-            .parent()
-            .context("Cannot determine kernel parent directory")?;
 
         if !self.is_directory_writable(parent_dir).await? {
             anyhow::bail!("Insufficient permissions to write to kernel directory");
         }
 
         // Check available disk space
-        self.check_disk_space().await?;
+        self.check_disk_space(metadata, artifacts)?;
 
         // Verify we're running with appropriate privileges
         if !self.has_required_privileges() {
@@ -162,15 +691,13 @@ impl Installer {
             anyhow::bail!("Downloaded kernel file not found: {}", kernel_path);
         }
 
-        // Read file content for validation
-        let file_content = async_fs::read(path).await?;
-
         // Verify file size
-        if file_content.len() as u64 != metadata.file_size {
+        let file_size = async_fs::metadata(path).await?.len();
+        if file_size != metadata.file_size {
             anyhow::bail!(
                 "File size mismatch: expected {}, got {}",
                 metadata.file_size,
-                file_content.len()
+                file_size
             );
         }
 
@@ -184,8 +711,21 @@ impl Installer {
             );
         }
 
-        // Validate ARM64 kernel format
-        self.validate_kernel_format(&file_content)?;
+        // Verify the detached signature over version and checksum
+        verify_metadata_signature(&self.config, metadata)
+            .context("Image signature verification failed")?;
+
+        // Anti-rollback: never install below the highest security version seen
+        check_security_version(
+            &self.config,
+            &metadata.latest_version,
+            metadata.security_version,
+        )
+        .await?;
+
+        // Validate ARM64 kernel format; only the header is needed
+        let header = read_prefix(path, KERNEL_HEADER_SIZE).await?;
+        self.validate_kernel_format(&header)?;
 
         debug!("Downloaded kernel validation passed");
         Ok(())
@@ -202,33 +742,26 @@ impl Installer {
         Ok(())
     }
 
-    /// Create comprehensive backup of current kernel
+    /// Back up the current kernel before it is replaced
+    ///
+    /// `backup_path` holds the kernel a plain rollback restores; a copy named
+    /// by version also goes into the backup store for rollbacks further back.
     async fn create_backup(&mut self) -> Result<()> {
         info!("Creating kernel backup");
 
         let kernel_path = Path::new(&self.config.kernel_path);
         let backup_path = Path::new(&self.config.backup_path);
 
-        // Create timestamped backup
-        let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S");
-        let _timestamped_backup = backup_path.with_extension(format!("backup_{}", timestamp));
-
         // Primary backup
-        self.copy_with_verification(kernel_path, backup_path)
+        let checksum = self
+            .copy_with_verification(kernel_path, backup_path)
             .await?;
         self.backup_paths.push(PathBuf::from(backup_path));
 
-        // Timestamped backup (for history)
-        let timestamped_backup = self.temp_dir.join("original_kernel.backup");
-        self.copy_with_verification(kernel_path, &timestamped_backup)
-            .await?;
-        self.backup_paths.push(timestamped_backup);
-
-        // Additional safety: backup to temp directory
-        let temp_backup = self.temp_dir.join("original_kernel.backup");
-        self.copy_with_verification(kernel_path, &temp_backup)
-            .await?;
-        self.backup_paths.push(temp_backup);
+        // Versioned backup, copied from the verified primary
+        let stored = self.store_backup(backup_path, &checksum).await?;
+        self.backup_paths.push(stored);
+        self.checkpoint(InstallStep::BackupCreated).await?;
 
         info!(
             "Backup created successfully with {} copies",
@@ -237,20 +770,75 @@ impl Installer {
         Ok(())
     }
 
+    /// Add a copy of a verified kernel image to the backup store
+    ///
+    /// An image already in the store is not copied again, only marked newest.
+    /// Pruning is left to the caller, so a rollback can still read the backup
+    /// it restores from.
+    async fn store_backup(&self, image: &Path, checksum: &str) -> Result<PathBuf> {
+        let mut index = load_backup_index(&self.config).await?;
+
+        // The recorded version only describes this image if the checksums agree
+        let version = load_installed_version(&self.config)
+            .await?
+            .filter(|record| record.checksum == checksum)
+            .map(|record| record.version);
+
+        let existing = index
+            .entries
+            .iter()
+            .rposition(|entry| entry.checksum == checksum && Path::new(&entry.file).exists());
+        let file = match existing {
+            Some(position) => {
+                let mut entry = index.entries.remove(position);
+                debug!("Image already in backup store: {}", entry.file);
+                entry.version = entry.version.or(version);
+                let file = PathBuf::from(&entry.file);
+                index.entries.push(entry);
+                file
+            }
+            None => {
+                let created_at = chrono::Utc::now();
+                let file =
+                    backup_dir(&self.config).join(backup_file_name(version.as_deref(), created_at));
+                self.copy_with_verification(image, &file).await?;
+
+                let security_version = load_security_version(&self.config)
+                    .await?
+                    .map(|state| state.installed)
+                    .unwrap_or(0);
+                index.entries.push(BackupEntry {
+                    version,
+                    security_version,
+                    checksum: checksum.to_string(),
+                    size: async_fs::metadata(&file).await?.len(),
+                    file: file.to_string_lossy().to_string(),
+                    created_at,
+                });
+                info!("Stored kernel backup: {}", file.display());
+                file
+            }
+        };
+
+        save_backup_index(&self.config, &index)
+            .await
+            .context("Failed to update backup index")?;
+        Ok(file)
+    }
+
     /// Copy file with integrity verification
-    async fn copy_with_verification(&self, src: &Path, dst: &Path) -> Result<()> {
+    async fn copy_with_verification(&self, src: &Path, dst: &Path) -> Result<String> {
         // Ensure destination directory exists
         if let Some(parent) = dst.parent() {
             async_fs::create_dir_all(parent).await?;
         }
 
-        // Copy file
-        async_fs::copy(src, dst)
+        // Copy file, hashing the source on the way through
+        let (_, src_checksum) = copy_and_hash(src, dst)
             .await
             .with_context(|| format!("Failed to copy {} to {}", src.display(), dst.display()))?;
 
-        // Verify copy integrity
-        let src_checksum = self.calculate_file_checksum(src).await?;
+        // Verify copy integrity by reading back what landed on disk
         let dst_checksum = self.calculate_file_checksum(dst).await?;
 
         if src_checksum != dst_checksum {
@@ -267,12 +855,15 @@ impl Installer {
         let permissions = src_metadata.permissions();
         async_fs::set_permissions(dst, permissions).await?;
 
+        // Nothing may build on this copy until it would survive a power cut
+        sync_file_and_parent(dst).await?;
+
         debug!(
             "Successfully copied and verified: {} -> {}",
             src.display(),
             dst.display()
         );
-        Ok(())
+        Ok(src_checksum)
     }
 
     /// Setup temporary workspace for installation
@@ -296,6 +887,8 @@ impl Installer {
         // Set appropriate permissions (typically 644 for kernel files)
         let permissions = Permissions::from_mode(0o644);
         async_fs::set_permissions(&temp_kernel, permissions).await?;
+        sync_file_and_parent(&temp_kernel).await?;
+        self.checkpoint(InstallStep::KernelPrepared).await?;
 
         debug!(
             "Kernel prepared for installation: {}",
@@ -305,31 +898,57 @@ impl Installer {
     }
 
     /// Perform atomic kernel installation
-    async fn perform_atomic_installation(&self, temp_kernel_path: &Path) -> Result<()> {
+    async fn perform_atomic_installation(
+        &self,
+        temp_kernel_path: &Path,
+        kernel_path: &Path,
+    ) -> Result<()> {
         info!("Performing atomic kernel installation");
 
-        let kernel_path = Path::new(&self.config.kernel_path);
-        let temp_install_path = kernel_path.with_extension("installing");
+        self.replace_atomically(
+            temp_kernel_path,
+            kernel_path,
+            InstallStep::KernelStaged,
+            InstallStep::KernelReplaced,
+        )
+        .await
+        .context("Failed to perform atomic kernel replacement")?;
 
-        // Step 1: Copy new kernel to temporary name in target location
-        self.copy_with_verification(temp_kernel_path, &temp_install_path)
-            .await?;
+        info!("Atomic installation completed");
+        Ok(())
+    }
+
+    /// Replace `target` with a copy of `src` so it is never seen half written
+    ///
+    /// Returns the checksum of the copy.
+    async fn replace_atomically(
+        &self,
+        src: &Path,
+        target: &Path,
+        staged: InstallStep,
+        replaced: InstallStep,
+    ) -> Result<String> {
+        let staging_path = target.with_extension("installing");
+
+        // Step 1: Copy to a temporary name next to the target, synced to disk
+        let checksum = self.copy_with_verification(src, &staging_path).await?;
+        self.checkpoint(staged).await?;
 
         // Step 2: Atomic rename (this is the critical moment)
-        async_fs::rename(&temp_install_path, kernel_path)
-            .await
-            .context("Failed to perform atomic kernel replacement")?;
+        durable_rename(&staging_path, target).await?;
+        self.checkpoint(replaced).await?;
 
-        info!("Atomic installation completed");
-        Ok(())
+        Ok(checksum)
     }
 
     /// Verify installation success
-    async fn verify_installation(&self, metadata: &KernelMetadata) -> Result<()> {
+    async fn verify_installation(
+        &self,
+        kernel_path: &Path,
+        metadata: &KernelMetadata,
+    ) -> Result<()> {
         info!("Verifying kernel installation");
 
-        let kernel_path = Path::new(&self.config.kernel_path);
-
         // Check file exists
         if !kernel_path.exists() {
             anyhow::bail!("Kernel file missing after installation");
@@ -368,127 +987,461 @@ impl Installer {
         Ok(())
     }
 
+    /// Record the installed version, remembering the one now held in backup
+    async fn record_installed_version(&self, metadata: &KernelMetadata) -> Result<()> {
+        let previous_version = load_installed_version(&self.config)
+            .await?
+            .map(|record| record.version);
+
+        let record = InstalledVersion {
+            version: metadata.latest_version.clone(),
+            checksum: metadata.checksum.clone(),
+            installed_at: chrono::Utc::now(),
+            previous_version,
+        };
+
+        save_installed_version(&self.config, &record)
+            .await
+            .context("Failed to record installed version")
+    }
+
     /// Rollback to previous kernel
-    pub async fn rollback(&self) -> Result<()> {
-        warn!("Performing kernel rollback");
+    ///
+    /// `allow_below_counter` is set only for rollbacks a user asked for; without
+    /// it a previous kernel below the anti-rollback counter is refused.
+    pub async fn rollback(&self, allow_below_counter: bool) -> Result<()> {
+        let target = load_installed_version(&self.config)
+            .await?
+            .and_then(|record| record.previous_version)
+            .unwrap_or_else(|| "previous kernel".to_string());
+        check_plain_rollback(&self.config, &target, allow_below_counter).await?;
+
+        let (kernel_path, swaps_slots) = match &self.config.ab_slots {
+            Some(slots) => (self.switch_to_previous_slot(slots).await?, true),
+            None => {
+                self.restore_backup().await?;
+                (PathBuf::from(&self.config.kernel_path), false)
+            }
+        };
 
-        let kernel_path = Path::new(&self.config.kernel_path);
-        let backup_path = Path::new(&self.config.backup_path);
+        // The backup now runs, so record its version as installed
+        let checksum = self.calculate_file_checksum(&kernel_path).await?;
+        let recorded = load_installed_version(&self.config).await?;
+        // A failed install may already have put the recorded kernel back
+        let already_recorded = recorded
+            .as_ref()
+            .is_some_and(|record| record.checksum == checksum);
+        let (restored_version, replaced_version) = match recorded {
+            Some(record) if already_recorded => {
+                debug!("Restored kernel already recorded as {}", record.version);
+                (Some(record.version), None)
+            }
+            Some(InstalledVersion {
+                version,
+                previous_version: Some(previous_version),
+                ..
+            }) => {
+                // With A/B slots the version we left stays in the other slot
+                let record = InstalledVersion {
+                    version: previous_version.clone(),
+                    checksum: checksum.clone(),
+                    installed_at: chrono::Utc::now(),
+                    previous_version: swaps_slots.then(|| version.clone()),
+                };
+                save_installed_version(&self.config, &record).await?;
+                (Some(previous_version), Some(version))
+            }
+            record => {
+                warn!("Version of the restored kernel is unknown, clearing installed version");
+                clear_installed_version(&self.config).await?;
+                (None, record.map(|record| record.version))
+            }
+        };
 
-        if !backup_path.exists() {
-            anyhow::bail!("Backup file not found: {}", backup_path.display());
+        // Nothing changed if the restored kernel is the one already recorded
+        if !already_recorded {
+            // The one path allowed below the anti-rollback counter, by a single step
+            record_rollback_security_version(&self.config, swaps_slots).await?;
+
+            // DTBs, overlays and modules must match the restored kernel
+            rollback_artifacts(&self.config).await?;
         }
 
-        // Verify backup integrity before rollback
-        let backup_checksum = self.calculate_file_checksum(backup_path).await?;
-        debug!("Backup checksum: {}", backup_checksum);
+        // Nothing is left to confirm once the new kernel is gone
+        clear_pending_confirmation(&self.config).await?;
 
-        // Perform rollback copy
-        self.copy_with_verification(backup_path, kernel_path)
-            .await?;
+        let hook_context = HookContext {
+            version: restored_version.unwrap_or_else(|| "unknown".to_string()),
+            checksum,
+            kernel_path: kernel_path.to_string_lossy().to_string(),
+            backup_path: self.config.backup_path.clone(),
+            previous_version: replaced_version,
+            ..HookContext::default()
+        };
+        run_hooks_logged(&self.config.hooks, HookStage::OnRollback, &hook_context).await;
 
-        info!("Kernel rollback completed successfully");
         Ok(())
     }
 
-    /// Calculate file checksum
-    async fn calculate_file_checksum(&self, path: &Path) -> Result<String> {
-        let contents = async_fs::read(path)
+    /// Roll back to a specific earlier version, as explicitly asked for by a user
+    ///
+    /// The previous version goes through `rollback`. Older versions come from
+    /// the backup store, with the running kernel backed up first so the
+    /// rollback can itself be undone.
+    pub async fn rollback_to(&mut self, version: &str) -> Result<()> {
+        let installed = load_installed_version(&self.config).await?;
+        if let Some(record) = &installed {
+            if record.version == version {
+                anyhow::bail!("Version {} is already installed", version);
+            }
+            if record.previous_version.as_deref() == Some(version) {
+                return self.rollback(true).await;
+            }
+        }
+
+        if self.config.ab_slots.is_some() {
+            anyhow::bail!(
+                "With A/B slots only the version in the other slot ({}) can be restored",
+                installed
+                    .and_then(|record| record.previous_version)
+                    .unwrap_or_else(|| "none recorded".to_string())
+            );
+        }
+
+        let entry = load_backup_index(&self.config)
+            .await?
+            .find(version)
+            .cloned()
+            .with_context(|| format!("No backup of version {} in the backup store", version))?;
+        warn!("Rolling back to version {} from {}", version, entry.file);
+
+        // The store lives on the same media as /boot, so check it is intact
+        let checksum = self.calculate_file_checksum(Path::new(&entry.file)).await?;
+        if checksum != entry.checksum {
+            anyhow::bail!(
+                "Backup of version {} is corrupt: checksum {} does not match {}",
+                version,
+                checksum,
+                entry.checksum
+            );
+        }
+
+        check_rollback_security_version(&self.config, version, entry.security_version).await?;
+        check_free_space(&[
+            SpaceRequirement::new(&self.config.kernel_path, entry.size),
+            SpaceRequirement::new(&self.config.backup_path, entry.size),
+            SpaceRequirement::new(backup_dir(&self.config), entry.size),
+        ])
+        .context("Not enough free space to roll back")?;
+
+        self.create_backup()
             .await
-            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+            .context("Failed to back up the running kernel")?;
+
+        let kernel_path = PathBuf::from(&self.config.kernel_path);
+        self.replace_atomically(
+            Path::new(&entry.file),
+            &kernel_path,
+            InstallStep::RollbackStaged,
+            InstallStep::RollbackReplaced,
+        )
+        .await
+        .context("Failed to restore kernel from the backup store")?;
+        self.cleanup_old_backups(self.config.backups.keep).await?;
+
+        // The kernel just replaced is now the one a plain rollback restores
+        let record = InstalledVersion {
+            version: version.to_string(),
+            checksum: entry.checksum.clone(),
+            installed_at: chrono::Utc::now(),
+            previous_version: installed.map(|record| record.version),
+        };
+        save_installed_version(&self.config, &record).await?;
+        record_installed_security_version(&self.config, entry.security_version).await?;
 
-        let mut hasher = Sha256::new();
-        hasher.update(&contents);
-        let hash = hasher.finalize();
+        // Artifacts are only tracked one version back
+        if load_artifact_state(&self.config).await?.is_some() {
+            warn!("Artifacts of the replaced kernel are left in place");
+        }
+
+        clear_pending_confirmation(&self.config).await?;
 
-        Ok(format!("sha256:{:x}", hash))
+        let hook_context = HookContext {
+            version: version.to_string(),
+            checksum: entry.checksum,
+            kernel_path: self.config.kernel_path.clone(),
+            backup_path: entry.file,
+            previous_version: record.previous_version,
+            ..HookContext::default()
+        };
+        run_hooks_logged(&self.config.hooks, HookStage::OnRollback, &hook_context).await;
+
+        info!("Rolled back to version {}", version);
+        Ok(())
     }
 
-    /// Check if directory is writable
-    async fn is_directory_writable(&self, dir: &Path) -> Result<bool> {
-        let test_file = dir.join(".ota_write_test");
+    /// Point the boot config back at the previously active slot
+    async fn switch_to_previous_slot(&self, slots: &AbSlotConfig) -> Result<PathBuf> {
+        warn!("Performing A/B slot rollback");
 
-        match async_fs::write(&test_file, b"test").await {
-            Ok(_) => {
-                let _ = async_fs::remove_file(&test_file).await;
-                Ok(true)
-            }
-            Err(_) => Ok(false),
+        let state = load_slot_state(&self.config)
+            .await?
+            .context("No slot state recorded, nothing to roll back to")?;
+        let previous = state
+            .previous
+            .context("No previous slot recorded, nothing to roll back to")?;
+
+        let previous_path = kernel_value_path(slots, &previous);
+        if !previous_path.exists() {
+            anyhow::bail!(
+                "Previous slot kernel not found: {}",
+                previous_path.display()
+            );
         }
+
+        switch_boot_kernel(slots, &previous).await?;
+        save_slot_state(
+            &self.config,
+            &SlotState {
+                active: previous,
+                previous: Some(state.active),
+            },
+        )
+        .await?;
+
+        info!("Slot rollback completed successfully");
+        Ok(previous_path)
     }
 
-    /// Check available disk space
-    async fn check_disk_space(&self) -> Result<()> {
-        // This is a simplified check - in production, you'd want to use statvfs or similar
+    /// Restore the backup kernel over the current kernel
+    async fn restore_backup(&self) -> Result<()> {
+        warn!("Performing kernel rollback");
+
         let kernel_path = Path::new(&self.config.kernel_path);
-        if let Ok(metadata) = async_fs::metadata(kernel_path).await {
-            let required_space = metadata.len() * 3; // Need space for original + backup + new
-            debug!("Estimated space required: {} bytes", required_space);
-            // Additional space checks would go here
+        let backup_path = Path::new(&self.config.backup_path);
+
+        if !backup_path.exists() {
+            anyhow::bail!("Backup file not found: {}", backup_path.display());
         }
+
+        // Perform rollback copy; the copy itself verifies the restored image
+        let backup_checksum = self
+            .replace_atomically(
+                backup_path,
+                kernel_path,
+                InstallStep::RollbackStaged,
+                InstallStep::RollbackReplaced,
+            )
+            .await
+            .context("Failed to restore kernel backup")?;
+        debug!("Restored backup checksum: {}", backup_checksum);
+
+        info!("Kernel rollback completed successfully");
         Ok(())
     }
 
+    /// Calculate file checksum
+    pub async fn calculate_file_checksum(&self, path: &Path) -> Result<String> {
+        file_checksum(path).await
+    }
+
+    /// Check if directory is writable
+    ///
+    /// Asks the kernel with `access(2)` rather than creating a file, so a dry
+    /// run leaves the boot partition untouched; read-only mounts report `EROFS`.
+    async fn is_directory_writable(&self, dir: &Path) -> Result<bool> {
+        let c_path = CString::new(dir.as_os_str().as_bytes())
+            .with_context(|| format!("Invalid path: {}", dir.display()))?;
+        Ok(unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0)
+    }
+
+    /// Check each filesystem the installation writes to has room for its copies
+    ///
+    /// Each artifact is staged next to its destination; the old content is
+    /// only renamed aside, so it takes no extra room.
+    fn check_disk_space(
+        &self,
+        metadata: &KernelMetadata,
+        artifacts: &[StagedArtifact],
+    ) -> Result<()> {
+        let size = metadata.file_size;
+
+        let mut requirements = if let Some(slots) = &self.config.ab_slots {
+            // Workspace copy, plus the new image written into a slot
+            vec![
+                SpaceRequirement::new(&self.temp_dir, size),
+                SpaceRequirement::new(&slots.slot_a_path, size),
+            ]
+        } else {
+            // Workspace holds the new image, which then sits next to the old
+            // kernel until it is renamed over it; the old one is backed up twice
+            vec![
+                SpaceRequirement::new(&self.temp_dir, size),
+                SpaceRequirement::new(&self.config.kernel_path, size),
+                SpaceRequirement::new(&self.config.backup_path, size),
+                SpaceRequirement::new(backup_dir(&self.config), size),
+            ]
+        };
+        requirements.extend(artifacts.iter().map(|item| {
+            SpaceRequirement::new(&item.artifact.destination, item.artifact.file_size)
+        }));
+
+        check_free_space(&requirements).context("Not enough free space to install the kernel")
+    }
+
     /// Check if running with required privileges
     fn has_required_privileges(&self) -> bool {
         // Check if running as root or with appropriate capabilities
         unsafe { libc::geteuid() == 0 }
     }
 
-    /// Cleanup temporary workspace
-    async fn cleanup_temp_workspace(&self) -> Result<()> {
-        if self.temp_dir.exists() {
-            async_fs::remove_dir_all(&self.temp_dir).await?;
-            debug!("Temporary workspace cleaned up");
+    /// Journal a durable step, where a test may simulate a power cut
+    async fn checkpoint(&self, step: InstallStep) -> Result<()> {
+        advance_install_journal(&self.config, step)
+            .await
+            .context("Failed to update install journal")?;
+
+        #[cfg(test)]
+        if self.crash_at == Some(step) {
+            panic!("simulated power cut at {:?}", step);
         }
+
+        debug!("Installation step reached: {:?}", step);
         Ok(())
     }
 
-    /// Cleanup old backups (keep only N most recent)
-    pub async fn cleanup_old_backups(&self, keep_count: usize) -> Result<()> {
-        info!(
-            "Cleaning up old backups, keeping {} most recent",
-            keep_count
+    /// Finish or undo an install the process didn't live to complete
+    ///
+    /// Must run before a new install overwrites the backup of the kernel the
+    /// interrupted one replaced.
+    pub async fn recover_interrupted_install(&mut self) -> Result<Option<RecoveryOutcome>> {
+        let Some(journal) = load_install_journal(&self.config).await? else {
+            return Ok(None);
+        };
+        let metadata = journal.metadata.clone();
+        warn!(
+            "Recovering interrupted installation of {} (last step {:?})",
+            metadata.latest_version, journal.step
         );
 
-        let backup_dir = Path::new(&self.config.backup_path)
-            .parent()
-            .context("Cannot determine backup directory")?;
-
-        let mut backup_files = Vec::new();
-        let mut entries = async_fs::read_dir(backup_dir).await?;
-
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                if name.contains("backup_") && name.ends_with(".backup") {
-                    if let Ok(metadata) = entry.metadata().await {
-                        backup_files.push((
-                            path,
-                            metadata
-                                .modified()
-                                .unwrap_or(std::time::SystemTime::UNIX_EPOCH),
-                        ));
+        let finish = match journal.step {
+            InstallStep::Verified | InstallStep::Recorded => true,
+            // The image is in place but was never checked; A/B installs still
+            // need their boot config switch, so those are undone instead
+            InstallStep::KernelReplaced if self.config.ab_slots.is_none() => {
+                let kernel_path = PathBuf::from(&self.config.kernel_path);
+                match self.verify_installation(&kernel_path, &metadata).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Interrupted installation does not verify: {}", e);
+                        false
                     }
                 }
             }
+            _ => false,
+        };
+
+        if !finish {
+            self.undo_interrupted_install(&journal)
+                .await
+                .context("Failed to undo interrupted installation")?;
+            info!(
+                "Interrupted installation of {} undone",
+                metadata.latest_version
+            );
+            return Ok(Some(RecoveryOutcome::RolledBack(metadata)));
         }
 
-        // Sort by modification time (newest first)
-        backup_files.sort_by(|a, b| b.1.cmp(&a.1));
+        if journal.step == InstallStep::Recorded {
+            self.cleanup_temp_workspace().await?;
+            clear_install_journal(&self.config).await?;
+        } else {
+            self.finalize_installation(&metadata, &None)
+                .await
+                .context("Failed to finish interrupted installation")?;
+        }
+        info!(
+            "Interrupted installation of {} finished",
+            metadata.latest_version
+        );
+        Ok(Some(RecoveryOutcome::Completed(metadata)))
+    }
 
-        // Remove old backups
-        for (path, _) in backup_files.iter().skip(keep_count) {
-            if let Err(e) = async_fs::remove_file(path).await {
-                warn!("Failed to remove old backup {}: {}", path.display(), e);
-            } else {
-                debug!("Removed old backup: {}", path.display());
+    /// Put back the kernel, boot config and artifacts an interrupted install replaced
+    async fn undo_interrupted_install(&self, journal: &InstallJournal) -> Result<()> {
+        match (&self.config.ab_slots, &journal.previous_slot) {
+            (Some(slots), Some(previous)) => {
+                // The boot config may already point at the new slot
+                let active = read_active_kernel_value(slots, &self.default_kernel_value()).await?;
+                if &active != previous {
+                    switch_boot_kernel(slots, previous).await?;
+                }
+                if let Some(state) = load_slot_state(&self.config).await?
+                    && &state.active != previous
+                {
+                    let state = SlotState {
+                        active: previous.clone(),
+                        previous: None,
+                    };
+                    save_slot_state(&self.config, &state).await?;
+                }
+            }
+            _ => {
+                // From BackupCreated on the backup is whole and synced, and the
+                // rename may have landed just before the journal caught up
+                if !matches!(
+                    journal.step,
+                    InstallStep::Started | InstallStep::ArtifactsInstalled
+                ) {
+                    let kernel_path = Path::new(&self.config.kernel_path);
+                    let backup_path = Path::new(&self.config.backup_path);
+                    let kernel_checksum = self.calculate_file_checksum(kernel_path).await.ok();
+                    let backup_checksum = self.calculate_file_checksum(backup_path).await?;
+                    if kernel_checksum.as_ref() != Some(&backup_checksum) {
+                        self.restore_backup().await?;
+                    }
+                }
+
+                let staging_path = Path::new(&self.config.kernel_path).with_extension("installing");
+                if staging_path.exists() {
+                    async_fs::remove_file(&staging_path).await?;
+                }
             }
         }
 
+        // Artifacts placed for this version go back as well
+        if load_artifact_state(&self.config)
+            .await?
+            .is_some_and(|state| state.version == journal.metadata.latest_version)
+        {
+            rollback_artifacts(&self.config).await?;
+        }
+
+        self.cleanup_temp_workspace().await?;
+        clear_install_journal(&self.config).await
+    }
+
+    /// Cleanup temporary workspace
+    async fn cleanup_temp_workspace(&self) -> Result<()> {
+        if self.temp_dir.exists() {
+            async_fs::remove_dir_all(&self.temp_dir).await?;
+            debug!("Temporary workspace cleaned up");
+        }
         Ok(())
     }
 
+    /// Cleanup old backups (keep only N most recent)
+    pub async fn cleanup_old_backups(&self, keep_count: usize) -> Result<()> {
+        info!(
+            "Cleaning up old backups, keeping {} most recent",
+            keep_count
+        );
+
+        let mut index = load_backup_index(&self.config).await?;
+        prune_backups(&mut index, keep_count).await?;
+        save_backup_index(&self.config, &index).await
+    }
+
     /// Get installation status
     pub fn get_backup_paths(&self) -> &[PathBuf] {
         &self.backup_paths
@@ -506,9 +1459,31 @@ impl Installer {
     }
 }
 
+/// Path and `kernel=` value of the slot that is not `active`
+fn inactive_slot(slots: &AbSlotConfig, active: &str) -> (PathBuf, String) {
+    let slot_a = slot_kernel_value(slots, &slots.slot_a_path);
+    if active == slot_a {
+        (
+            PathBuf::from(&slots.slot_b_path),
+            slot_kernel_value(slots, &slots.slot_b_path),
+        )
+    } else {
+        (PathBuf::from(&slots.slot_a_path), slot_a)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::channel::{PinRecord, PinnedError, UpdatePin, save_pin};
+    use crate::disk_space::InsufficientSpaceError;
+    use crate::security_version::{AntiRollbackError, load_security_version};
+    use crate::signature::SignatureError;
+    use crate::types::{
+        BackupConfig, BootConfirmationConfig, BundleWatchConfig, HooksConfig, MetricsConfig,
+        RebootConfig, ScheduleConfig, TlsConfig, UpdateChannel,
+    };
+    use sha2::{Digest, Sha256};
     use tempfile::TempDir;
     use tokio::io::AsyncWriteExt;
 
@@ -525,6 +1500,24 @@ mod tests {
             mdns_service: "_ota._tcp.local".to_string(),
             fallback_server: None,
             download_timeout_secs: 30,
+            state_path: format!("{}/state", temp_path),
+            trusted_public_keys: Vec::new(),
+            allow_unsigned: true,
+            ab_slots: None,
+            boot_confirmation: BootConfirmationConfig::default(),
+            control_socket_path: format!("{}/control.sock", temp_path),
+            metrics: MetricsConfig::default(),
+            schedule: ScheduleConfig::default(),
+            server_scheme: "http".to_string(),
+            tls: TlsConfig::default(),
+            device_id: None,
+            channel: UpdateChannel::default(),
+            device_model: None,
+            hardware_revision: None,
+            backups: BackupConfig::default(),
+            bundle_watch: BundleWatchConfig::default(),
+            reboot: RebootConfig::default(),
+            hooks: HooksConfig::default(),
         };
 
         // Create a dummy kernel file
@@ -545,6 +1538,10 @@ mod tests {
             release_date: "2025-06-16T10:30:00Z".to_string(),
             description: "Test kernel".to_string(),
             download_url: "/kernels/kernel-v1.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
         };
 
         (temp_dir, config, metadata)
@@ -625,7 +1622,7 @@ mod tests {
             .unwrap();
 
         // Perform rollback
-        let result = installer.rollback().await;
+        let result = installer.rollback(true).await;
         assert!(result.is_ok());
 
         // Verify kernel was restored
@@ -633,6 +1630,205 @@ mod tests {
         assert_eq!(restored_content, b"dummy kernel data");
     }
 
+    #[tokio::test]
+    async fn test_rollback_restores_previous_version() {
+        let (_temp_dir, config, metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        installer.setup_temp_workspace().await.unwrap();
+        installer.create_backup().await.unwrap();
+
+        // Simulate two successful installs
+        let old_metadata = KernelMetadata {
+            latest_version: "0.9.0".to_string(),
+            ..metadata.clone()
+        };
+        installer
+            .record_installed_version(&old_metadata)
+            .await
+            .unwrap();
+        async_fs::write(&config.kernel_path, b"new kernel image")
+            .await
+            .unwrap();
+        let metadata = KernelMetadata {
+            checksum: crate::delta::checksum_of(b"new kernel image"),
+            ..metadata
+        };
+        installer.record_installed_version(&metadata).await.unwrap();
+
+        let installed = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0");
+        assert_eq!(installed.previous_version.as_deref(), Some("0.9.0"));
+
+        installer.rollback(true).await.unwrap();
+
+        let installed = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(installed.version, "0.9.0");
+        assert!(installed.previous_version.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_kernel_install_restores_artifacts() {
+        let (temp_dir, config, mut metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        let dtb_path = temp_dir.path().join("bcm2711.dtb");
+        async_fs::write(&dtb_path, b"old dtb").await.unwrap();
+        let staged_dtb = temp_dir.path().join("new.dtb");
+        async_fs::write(&staged_dtb, b"new dtb").await.unwrap();
+
+        let artifact = crate::types::Artifact {
+            name: "bcm2711.dtb".to_string(),
+            download_url: "/kernels/bcm2711.dtb".to_string(),
+            destination: dtb_path.to_string_lossy().to_string(),
+            file_size: 7,
+            checksum: crate::delta::checksum_of(b"new dtb"),
+            mode: None,
+            install_type: crate::types::ArtifactInstallType::File,
+        };
+        metadata.artifacts.push(artifact.clone());
+
+        // Kernel that doesn't match the metadata checksum
+        let download_path = temp_dir.path().join("new_kernel.img");
+        async_fs::write(&download_path, b"corrupted kernel!")
+            .await
+            .unwrap();
+
+        let staged = vec![StagedArtifact {
+            artifact,
+            path: staged_dtb,
+        }];
+        let result = installer
+            .install_update(download_path.to_str().unwrap(), &staged, &metadata, None)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(async_fs::read(&dtb_path).await.unwrap(), b"old dtb");
+        assert!(
+            crate::artifacts::load_artifact_state(&config)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // The bad image was caught before the DTB was even moved aside
+        assert!(!temp_dir.path().join("bcm2711.dtb.ota_backup").exists());
+    }
+
+    #[tokio::test]
+    async fn test_kernel_failing_verification_restores_artifacts() {
+        let (temp_dir, mut config, mut metadata) = create_test_environment().await;
+        let download_path = stage_new_kernel(&config, &mut metadata).await;
+
+        let dtb_path = temp_dir.path().join("bcm2711.dtb");
+        async_fs::write(&dtb_path, b"old dtb").await.unwrap();
+        let staged_dtb = temp_dir.path().join("new.dtb");
+        async_fs::write(&staged_dtb, b"new dtb").await.unwrap();
+        let artifact = crate::types::Artifact {
+            name: "bcm2711.dtb".to_string(),
+            download_url: "/kernels/bcm2711.dtb".to_string(),
+            destination: dtb_path.to_string_lossy().to_string(),
+            file_size: 7,
+            checksum: crate::delta::checksum_of(b"new dtb"),
+            mode: None,
+            install_type: crate::types::ArtifactInstallType::File,
+        };
+        metadata.artifacts.push(artifact.clone());
+
+        // The image passes every check, then is damaged once in place
+        config.hooks = HooksConfig {
+            after_install: vec![format!("echo corrupt >> {}", config.kernel_path)],
+            ..HooksConfig::default()
+        };
+        let mut installer = Installer::new(config.clone()).unwrap();
+        let staged = vec![StagedArtifact {
+            artifact,
+            path: staged_dtb,
+        }];
+        let error = installer
+            .install_update(&download_path, &staged, &metadata, None)
+            .await
+            .unwrap_err();
+
+        assert!(error.downcast_ref::<UndoFailedError>().is_none());
+        assert_eq!(async_fs::read(&dtb_path).await.unwrap(), b"old dtb");
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+        assert!(load_install_journal(&config).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ab_slot_install_and_rollback() {
+        let (temp_dir, mut config, metadata) = create_test_environment().await;
+        let temp_path = temp_dir.path();
+
+        let slots = AbSlotConfig {
+            slot_a_path: temp_path.join("kernel_a.img").to_string_lossy().to_string(),
+            slot_b_path: temp_path.join("kernel_b.img").to_string_lossy().to_string(),
+            boot_config_path: temp_path.join("config.txt").to_string_lossy().to_string(),
+        };
+        async_fs::write(&slots.slot_a_path, b"old kernel data")
+            .await
+            .unwrap();
+        async_fs::write(
+            &slots.boot_config_path,
+            "arm_64bit=1\nkernel=kernel_a.img\n",
+        )
+        .await
+        .unwrap();
+        config.ab_slots = Some(slots.clone());
+
+        let installer = Installer::new(config.clone()).unwrap();
+        installer.setup_temp_workspace().await.unwrap();
+
+        let download_path = temp_path.join("new_kernel.img");
+        async_fs::write(&download_path, b"dummy kernel data")
+            .await
+            .unwrap();
+
+        installer
+            .install_to_inactive_slot(
+                &slots,
+                download_path.to_str().unwrap(),
+                &metadata,
+                &None,
+                &HookContext::default(),
+            )
+            .await
+            .unwrap();
+
+        // New kernel lands in slot B and the running slot A is untouched
+        assert_eq!(
+            async_fs::read(&slots.slot_b_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+        assert_eq!(
+            async_fs::read(&slots.slot_a_path).await.unwrap(),
+            b"old kernel data"
+        );
+        let boot_config = async_fs::read_to_string(&slots.boot_config_path)
+            .await
+            .unwrap();
+        assert_eq!(boot_config, "arm_64bit=1\nkernel=kernel_b.img\n");
+        assert_eq!(
+            installer.active_kernel_path().await.unwrap(),
+            PathBuf::from(&slots.slot_b_path)
+        );
+
+        // Rollback flips back to slot A
+        installer.rollback(true).await.unwrap();
+        let boot_config = async_fs::read_to_string(&slots.boot_config_path)
+            .await
+            .unwrap();
+        assert_eq!(boot_config, "arm_64bit=1\nkernel=kernel_a.img\n");
+
+        let state = load_slot_state(&config).await.unwrap().unwrap();
+        assert_eq!(state.active, "kernel_a.img");
+        assert_eq!(state.previous.as_deref(), Some("kernel_b.img"));
+    }
+
     #[tokio::test]
     async fn test_file_copy_with_verification() {
         let (_temp_dir, config, _metadata) = create_test_environment().await;
@@ -688,6 +1884,57 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_dry_run_plans_without_writing() {
+        let (temp_dir, config, metadata) = create_test_environment().await;
+        let installer = Installer::new(config.clone()).unwrap();
+        let download_path = temp_dir.path().join("downloaded_kernel.img");
+        async_fs::write(&download_path, b"dummy kernel data")
+            .await
+            .unwrap();
+        let list_files = || {
+            let mut names: Vec<_> = std::fs::read_dir(temp_dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            names.sort();
+            names
+        };
+        let before = list_files();
+
+        let plan = installer
+            .plan_install(download_path.to_str(), &[], &metadata)
+            .await
+            .unwrap();
+        assert!(plan.image_verified);
+        assert_eq!(
+            plan.operations.last(),
+            Some(&PlannedOperation::RecordVersion {
+                version: "1.0.0".to_string(),
+                security_version: 0,
+            })
+        );
+        assert!(plan.operations.contains(&PlannedOperation::ReplaceKernel {
+            target: config.kernel_path.clone(),
+        }));
+
+        // The checks ran but nothing was backed up, staged or replaced
+        assert_eq!(list_files(), before);
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+
+        // A corrupt image still fails the dry run
+        async_fs::write(&download_path, b"corrupted").await.unwrap();
+        assert!(
+            installer
+                .plan_install(download_path.to_str(), &[], &metadata)
+                .await
+                .is_err()
+        );
+    }
+
     #[tokio::test]
     async fn test_downloaded_kernel_validation_failure() {
         let (_temp_dir, config, metadata) = create_test_environment().await;
@@ -708,4 +1955,561 @@ mod tests {
             .await;
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_unsigned_kernel_rejected() {
+        let (_temp_dir, mut config, metadata) = create_test_environment().await;
+        config.allow_unsigned = false;
+        let installer = Installer::new(config.clone()).unwrap();
+
+        let download_path = format!("{}/unsigned_kernel.img", config.download_path);
+        async_fs::create_dir_all(&config.download_path)
+            .await
+            .unwrap();
+        async_fs::write(&download_path, b"dummy kernel data")
+            .await
+            .unwrap();
+
+        // Correct checksum, but no signature
+        let error = installer
+            .validate_downloaded_kernel(&download_path, &metadata)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<SignatureError>(),
+            Some(&SignatureError::Missing)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_security_version_below_counter_rejected() {
+        let (_temp_dir, config, mut metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+        record_installed_security_version(&config, 5).await.unwrap();
+        let download_path = stage_new_kernel(&config, &mut metadata).await;
+
+        metadata.security_version = 4;
+        let error = installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<AntiRollbackError>().is_some());
+        assert!(!Path::new(&config.backup_path).exists());
+
+        metadata.security_version = 6;
+        installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap();
+
+        // An automatic rollback can't go below the counter
+        let error = installer.rollback(false).await.unwrap_err();
+        assert!(error.downcast_ref::<AntiRollbackError>().is_some());
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"new kernel image"
+        );
+
+        // A local rollback may go back one step, but the counter stays
+        installer.rollback(true).await.unwrap();
+        let state = load_security_version(&config).await.unwrap().unwrap();
+        assert_eq!(state.highest, 6);
+        assert_eq!(state.installed, 5);
+    }
+
+    #[tokio::test]
+    async fn test_pin_enforced_for_every_install() {
+        let (_temp_dir, config, mut metadata) = create_test_environment().await;
+        let download_path = stage_new_kernel(&config, &mut metadata).await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        let pin = |pin| PinRecord {
+            pin,
+            set_at: chrono::Utc::now(),
+        };
+        save_pin(&config, &pin(UpdatePin::Hold)).await.unwrap();
+        let error = installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<PinnedError>().is_some());
+        assert!(
+            installer
+                .plan_install(None, &[], &metadata)
+                .await
+                .unwrap_err()
+                .downcast_ref::<PinnedError>()
+                .is_some()
+        );
+        assert!(!Path::new(&config.backup_path).exists());
+
+        save_pin(&config, &pin(UpdatePin::Version("1.0.0".to_string())))
+            .await
+            .unwrap();
+        installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_insufficient_space_fails_before_backup() {
+        let (_temp_dir, config, mut metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        let download_path = format!("{}/downloaded_kernel.img", config.download_path);
+        async_fs::create_dir_all(&config.download_path)
+            .await
+            .unwrap();
+        async_fs::write(&download_path, b"dummy kernel data")
+            .await
+            .unwrap();
+
+        // No filesystem has room for this many copies
+        metadata.file_size = u64::MAX / 2;
+        let error = installer
+            .install_kernel(&download_path, &metadata, None)
+            .await
+            .unwrap_err();
+        let space_error = error.downcast_ref::<InsufficientSpaceError>().unwrap();
+        assert_eq!(space_error.required, u64::MAX);
+
+        assert!(!Path::new(&config.backup_path).exists());
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+    }
+    /// Stage a new image and point the metadata at it
+    async fn stage_new_kernel(config: &OtaConfig, metadata: &mut KernelMetadata) -> String {
+        let content = b"new kernel image";
+        let download_path = format!("{}/downloaded_kernel.img", config.download_path);
+        async_fs::create_dir_all(&config.download_path)
+            .await
+            .unwrap();
+        async_fs::write(&download_path, content).await.unwrap();
+
+        metadata.file_size = content.len() as u64;
+        metadata.checksum = crate::delta::checksum_of(content);
+        download_path
+    }
+
+    /// Run an installation that loses power right after `step`
+    async fn install_with_power_cut(
+        config: OtaConfig,
+        metadata: KernelMetadata,
+        download_path: String,
+        step: InstallStep,
+    ) {
+        let result = tokio::spawn(async move {
+            let mut installer = Installer::new(config).unwrap();
+            installer.crash_at = Some(step);
+            installer
+                .install_update(&download_path, &[], &metadata, None)
+                .await
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+    }
+
+    /// Run a rollback that loses power right after `step`
+    async fn rollback_with_power_cut(config: OtaConfig, step: InstallStep) {
+        let result = tokio::spawn(async move {
+            let mut installer = Installer::new(config).unwrap();
+            installer.crash_at = Some(step);
+            installer.rollback(true).await
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+    }
+
+    #[tokio::test]
+    async fn test_power_cut_during_install_leaves_whole_kernel() {
+        for step in [
+            InstallStep::BackupCreated,
+            InstallStep::KernelPrepared,
+            InstallStep::KernelStaged,
+            InstallStep::KernelReplaced,
+        ] {
+            let (_temp_dir, config, mut metadata) = create_test_environment().await;
+            let download_path = stage_new_kernel(&config, &mut metadata).await;
+
+            install_with_power_cut(config.clone(), metadata, download_path, step).await;
+
+            // The kernel is the old or the new image, never a torn mix
+            let kernel = async_fs::read(&config.kernel_path).await.unwrap();
+            if step == InstallStep::KernelReplaced {
+                assert_eq!(kernel, b"new kernel image", "{:?}", step);
+            } else {
+                assert_eq!(kernel, b"dummy kernel data", "{:?}", step);
+            }
+            assert_eq!(
+                async_fs::read(&config.backup_path).await.unwrap(),
+                b"dummy kernel data"
+            );
+
+            // After the reboot the backup can always be restored
+            let installer = Installer::new(config.clone()).unwrap();
+            installer.rollback(true).await.unwrap();
+            assert_eq!(
+                async_fs::read(&config.kernel_path).await.unwrap(),
+                b"dummy kernel data"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_power_cut_during_rollback_leaves_whole_kernel() {
+        for step in [InstallStep::RollbackStaged, InstallStep::RollbackReplaced] {
+            let (_temp_dir, config, mut metadata) = create_test_environment().await;
+            let download_path = stage_new_kernel(&config, &mut metadata).await;
+            let mut installer = Installer::new(config.clone()).unwrap();
+            installer
+                .install_kernel(&download_path, &metadata, None)
+                .await
+                .unwrap();
+
+            rollback_with_power_cut(config.clone(), step).await;
+
+            let kernel = async_fs::read(&config.kernel_path).await.unwrap();
+            if step == InstallStep::RollbackReplaced {
+                assert_eq!(kernel, b"dummy kernel data", "{:?}", step);
+            } else {
+                assert_eq!(kernel, b"new kernel image", "{:?}", step);
+            }
+
+            // Running the rollback again after the reboot completes it
+            installer.rollback(true).await.unwrap();
+            assert_eq!(
+                async_fs::read(&config.kernel_path).await.unwrap(),
+                b"dummy kernel data"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_install_recovered() {
+        for step in [
+            InstallStep::BackupCreated,
+            InstallStep::KernelPrepared,
+            InstallStep::KernelStaged,
+            InstallStep::KernelReplaced,
+            InstallStep::Verified,
+            InstallStep::Recorded,
+        ] {
+            let (_temp_dir, config, mut metadata) = create_test_environment().await;
+            let download_path = stage_new_kernel(&config, &mut metadata).await;
+
+            install_with_power_cut(config.clone(), metadata.clone(), download_path, step).await;
+            let journal = load_install_journal(&config).await.unwrap().unwrap();
+            assert_eq!(journal.step, step);
+
+            let mut installer = Installer::new(config.clone()).unwrap();
+            let outcome = installer.recover_interrupted_install().await.unwrap();
+
+            let kernel = async_fs::read(&config.kernel_path).await.unwrap();
+            let installed = load_installed_version(&config).await.unwrap();
+            match step {
+                InstallStep::KernelReplaced | InstallStep::Verified | InstallStep::Recorded => {
+                    assert_eq!(outcome, Some(RecoveryOutcome::Completed(metadata.clone())));
+                    assert_eq!(kernel, b"new kernel image", "{:?}", step);
+                    assert_eq!(installed.unwrap().version, "1.0.0");
+                }
+                _ => {
+                    assert_eq!(outcome, Some(RecoveryOutcome::RolledBack(metadata.clone())));
+                    assert_eq!(kernel, b"dummy kernel data", "{:?}", step);
+                    assert!(installed.is_none());
+                }
+            }
+
+            // Settled once; the next start has nothing left to do
+            assert!(load_install_journal(&config).await.unwrap().is_none());
+            assert!(!installer.temp_dir.exists());
+            assert!(
+                !Path::new(&config.kernel_path)
+                    .with_extension("installing")
+                    .exists()
+            );
+            assert_eq!(installer.recover_interrupted_install().await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unverified_interrupted_install_undone() {
+        let (_temp_dir, config, mut metadata) = create_test_environment().await;
+        let download_path = stage_new_kernel(&config, &mut metadata).await;
+        install_with_power_cut(
+            config.clone(),
+            metadata,
+            download_path,
+            InstallStep::KernelReplaced,
+        )
+        .await;
+
+        // The image on disk no longer matches what the journal expects
+        async_fs::write(&config.kernel_path, b"torn kernel")
+            .await
+            .unwrap();
+
+        let mut installer = Installer::new(config.clone()).unwrap();
+        let outcome = installer.recover_interrupted_install().await.unwrap();
+        assert!(matches!(outcome, Some(RecoveryOutcome::RolledBack(_))));
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+    }
+
+    /// Install `content` as `version` through the normal path
+    async fn install_version(
+        installer: &mut Installer,
+        config: &OtaConfig,
+        metadata: &KernelMetadata,
+        version: &str,
+        content: &[u8],
+    ) {
+        let download_path = format!("{}/kernel-{}.img", config.download_path, version);
+        async_fs::create_dir_all(&config.download_path)
+            .await
+            .unwrap();
+        async_fs::write(&download_path, content).await.unwrap();
+
+        let metadata = KernelMetadata {
+            latest_version: version.to_string(),
+            file_size: content.len() as u64,
+            checksum: crate::delta::checksum_of(content),
+            ..metadata.clone()
+        };
+        installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_after_restored_install_keeps_version() {
+        let (_temp_dir, config, mut metadata) = create_test_environment().await;
+        metadata.security_version = 2;
+        let mut installer = Installer::new(config.clone()).unwrap();
+        install_version(&mut installer, &config, &metadata, "1.0.0", b"kernel one").await;
+        install_version(&mut installer, &config, &metadata, "1.1.0", b"kernel two").await;
+        let security = load_security_version(&config).await.unwrap();
+
+        // A failed install backs up the running kernel and puts it back
+        installer.create_backup().await.unwrap();
+        installer.restore_backup().await.unwrap();
+
+        installer.rollback(true).await.unwrap();
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"kernel two"
+        );
+        let installed = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(installed.version, "1.1.0");
+        assert_eq!(installed.previous_version.as_deref(), Some("1.0.0"));
+        assert_eq!(load_security_version(&config).await.unwrap(), security);
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_at_install_stages() {
+        let (temp_dir, mut config, metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+        install_version(&mut installer, &config, &metadata, "1.0.0", b"kernel one").await;
+
+        let log = temp_dir.path().join("hooks.log");
+        let hook = format!(
+            "echo \"$OTA_HOOK_STAGE $OTA_VERSION ${{OTA_PREVIOUS_VERSION:-none}}\" >> {}",
+            log.display()
+        );
+        config.hooks = HooksConfig {
+            before_backup: vec![hook.clone()],
+            after_install: vec![hook.clone()],
+            after_verification: vec![hook.clone(), "exit 1".to_string()],
+            on_failure: vec![hook.clone()],
+            on_rollback: vec![hook],
+            ..HooksConfig::default()
+        };
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        // A failing post-install hook is logged but doesn't undo the install
+        install_version(&mut installer, &config, &metadata, "1.1.0", b"kernel two").await;
+        installer.rollback(true).await.unwrap();
+
+        assert_eq!(
+            async_fs::read_to_string(&log).await.unwrap(),
+            "before_backup 1.1.0 1.0.0\n\
+             after_install 1.1.0 1.0.0\n\
+             after_verification 1.1.0 1.0.0\n\
+             on_rollback 1.0.0 1.1.0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_pre_hook_aborts_install() {
+        let (temp_dir, mut config, metadata) = create_test_environment().await;
+        let failure_log = temp_dir.path().join("failure.log");
+        config.hooks = HooksConfig {
+            before_backup: vec!["exit 3".to_string()],
+            on_failure: vec![format!("echo \"$OTA_ERROR\" > {}", failure_log.display())],
+            ..HooksConfig::default()
+        };
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        let download_path = format!("{}/kernel-new.img", config.download_path);
+        async_fs::create_dir_all(&config.download_path)
+            .await
+            .unwrap();
+        async_fs::write(&download_path, b"dummy kernel data")
+            .await
+            .unwrap();
+        let error = installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .chain()
+                .any(|cause| cause.is::<crate::hooks::HookError>())
+        );
+
+        // Nothing was backed up, journaled or replaced
+        assert!(!Path::new(&config.backup_path).exists());
+        assert!(load_install_journal(&config).await.unwrap().is_none());
+        assert!(load_installed_version(&config).await.unwrap().is_none());
+
+        let failure = async_fs::read_to_string(&failure_log).await.unwrap();
+        assert!(failure.starts_with("Install aborted by hook: before_backup hook `exit 3`"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_undo_keeps_journal_for_recovery() {
+        let (_temp_dir, mut config, mut metadata) = create_test_environment().await;
+        let download_path = stage_new_kernel(&config, &mut metadata).await;
+
+        // Break the new kernel and take the backup away, so the undo fails too
+        let hidden_backup = format!("{}.hidden", config.backup_path);
+        config.hooks = HooksConfig {
+            after_install: vec![format!(
+                "mv {} {} && echo corrupt >> {}",
+                config.backup_path, hidden_backup, config.kernel_path
+            )],
+            ..HooksConfig::default()
+        };
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        let error = installer
+            .install_update(&download_path, &[], &metadata, None)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<UndoFailedError>().is_some());
+        assert!(load_install_journal(&config).await.unwrap().is_some());
+
+        // Once the backup is back, the next start finishes the undo
+        async_fs::rename(&hidden_backup, &config.backup_path)
+            .await
+            .unwrap();
+        let outcome = installer.recover_interrupted_install().await.unwrap();
+        assert!(matches!(outcome, Some(RecoveryOutcome::RolledBack(_))));
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"dummy kernel data"
+        );
+        assert!(load_install_journal(&config).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_backup_store_keeps_versions_for_rollback() {
+        let (_temp_dir, config, metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        install_version(&mut installer, &config, &metadata, "1.0.0", b"kernel one").await;
+        install_version(&mut installer, &config, &metadata, "2.0.0", b"kernel two").await;
+        install_version(&mut installer, &config, &metadata, "3.0.0", b"kernel three").await;
+
+        let index = load_backup_index(&config).await.unwrap();
+        let versions: Vec<_> = index
+            .entries
+            .iter()
+            .map(|entry| entry.version.as_deref())
+            .collect();
+        assert_eq!(versions, [None, Some("1.0.0"), Some("2.0.0")]);
+        let stored = index.find("1.0.0").unwrap();
+        assert!(stored.file.contains("kernel_1.0.0_"));
+        assert_eq!(stored.checksum, crate::delta::checksum_of(b"kernel one"));
+
+        // Two steps back, past what backup_path holds
+        installer.rollback_to("1.0.0").await.unwrap();
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"kernel one"
+        );
+        let installed = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(installed.version, "1.0.0");
+        assert_eq!(installed.previous_version.as_deref(), Some("3.0.0"));
+
+        // The kernel left behind was stored, and the oldest backup pruned
+        let index = load_backup_index(&config).await.unwrap();
+        assert_eq!(index.entries.len(), config.backups.keep);
+        assert!(index.find("3.0.0").is_some());
+        assert!(index.entries.iter().all(|entry| entry.version.is_some()));
+
+        // A plain rollback undoes it
+        installer.rollback(true).await.unwrap();
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"kernel three"
+        );
+
+        assert!(installer.rollback_to("0.1.0").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_to_oldest_backup_in_full_store() {
+        let (_temp_dir, config, metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        install_version(&mut installer, &config, &metadata, "1.0.0", b"kernel one").await;
+        install_version(&mut installer, &config, &metadata, "2.0.0", b"kernel two").await;
+        install_version(&mut installer, &config, &metadata, "3.0.0", b"kernel three").await;
+        install_version(&mut installer, &config, &metadata, "4.0.0", b"kernel four").await;
+
+        // The store is full and the target is its oldest entry
+        let index = load_backup_index(&config).await.unwrap();
+        assert_eq!(index.entries.len(), config.backups.keep);
+        assert_eq!(index.entries[0].version.as_deref(), Some("1.0.0"));
+
+        installer.rollback_to("1.0.0").await.unwrap();
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"kernel one"
+        );
+
+        // Pruned only after the restore, keeping the kernel left behind
+        let index = load_backup_index(&config).await.unwrap();
+        assert_eq!(index.entries.len(), config.backups.keep);
+        assert!(index.find("4.0.0").is_some());
+        assert!(index.find("1.0.0").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_backup_not_restored() {
+        let (_temp_dir, config, metadata) = create_test_environment().await;
+        let mut installer = Installer::new(config.clone()).unwrap();
+
+        install_version(&mut installer, &config, &metadata, "1.0.0", b"kernel one").await;
+        install_version(&mut installer, &config, &metadata, "2.0.0", b"kernel two").await;
+        install_version(&mut installer, &config, &metadata, "3.0.0", b"kernel three").await;
+
+        let index = load_backup_index(&config).await.unwrap();
+        async_fs::write(&index.find("1.0.0").unwrap().file, b"bit rot")
+            .await
+            .unwrap();
+
+        let error = installer.rollback_to("1.0.0").await.unwrap_err();
+        assert!(error.to_string().contains("corrupt"));
+        assert_eq!(
+            async_fs::read(&config.kernel_path).await.unwrap(),
+            b"kernel three"
+        );
+    }
 }
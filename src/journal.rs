@@ -0,0 +1,150 @@
+use crate::durable::{read_json, sync_parent, write_json};
+use crate::installer::InstallStep;
+use crate::types::{KernelMetadata, OtaConfig};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// An install failed and undoing its changes failed as well
+///
+/// The install journal is kept for these, so the next start retries the undo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoFailedError {
+    pub reason: String,
+}
+
+impl fmt::Display for UndoFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Installation failed and rollback failed: {}. Manual intervention required.",
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for UndoFailedError {}
+
+/// Record of an install in progress, advanced as each step becomes durable
+///
+/// Cleared once the install has finished or been undone, so one left behind
+/// at startup means the process died partway through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallJournal {
+    pub metadata: KernelMetadata,
+    /// Last step known to be on disk
+    pub step: InstallStep,
+    /// Boot config `kernel=` value active before an A/B install
+    pub previous_slot: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl InstallJournal {
+    /// Journal for an install that is about to start
+    pub fn new(metadata: &KernelMetadata, previous_slot: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            metadata: metadata.clone(),
+            step: InstallStep::Started,
+            previous_slot,
+            started_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Path of the install journal for a configuration
+pub fn install_journal_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("install_journal.json")
+}
+
+/// Load the install journal, if an install was left unfinished
+pub async fn load_install_journal(config: &OtaConfig) -> Result<Option<InstallJournal>> {
+    read_json(&install_journal_path(config), "install journal").await
+}
+
+/// Persist the install journal, synced before returning
+pub async fn save_install_journal(config: &OtaConfig, journal: &InstallJournal) -> Result<()> {
+    write_json(&install_journal_path(config), journal, "install journal").await?;
+
+    debug!("Install journal at step {:?}", journal.step);
+    Ok(())
+}
+
+/// Record that an install reached `step`; does nothing without an open journal
+pub async fn advance_install_journal(config: &OtaConfig, step: InstallStep) -> Result<()> {
+    let Some(mut journal) = load_install_journal(config).await? else {
+        return Ok(());
+    };
+
+    journal.step = step;
+    journal.updated_at = Utc::now();
+    save_install_journal(config, &journal).await
+}
+
+/// Remove the install journal once the install is settled
+pub async fn clear_install_journal(config: &OtaConfig) -> Result<()> {
+    let path = install_journal_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove install journal: {}", path.display()))?;
+        sync_parent(&path).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_test_metadata() -> KernelMetadata {
+        KernelMetadata {
+            latest_version: "1.1.0".to_string(),
+            kernel_file: "kernel-v1.1.0.img".to_string(),
+            file_size: 1024,
+            checksum: "sha256:abc".to_string(),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v1.1.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_journal_roundtrip_and_advance() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+
+        // Nothing to advance until an install opens the journal
+        advance_install_journal(&config, InstallStep::BackupCreated)
+            .await
+            .unwrap();
+        assert!(load_install_journal(&config).await.unwrap().is_none());
+
+        let journal = InstallJournal::new(&create_test_metadata(), Some("kernel_a.img".into()));
+        save_install_journal(&config, &journal).await.unwrap();
+        advance_install_journal(&config, InstallStep::KernelStaged)
+            .await
+            .unwrap();
+
+        let loaded = load_install_journal(&config).await.unwrap().unwrap();
+        assert_eq!(loaded.step, InstallStep::KernelStaged);
+        assert_eq!(loaded.metadata, journal.metadata);
+        assert_eq!(loaded.previous_slot.as_deref(), Some("kernel_a.img"));
+
+        clear_install_journal(&config).await.unwrap();
+        assert!(load_install_journal(&config).await.unwrap().is_none());
+    }
+}
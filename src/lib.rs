@@ -1,8 +1,30 @@
 // OTA Client Library
 // Modules for OTA client functionality
 
+pub mod artifacts;
+pub mod backup_store;
+pub mod boot;
+pub mod bundle;
+pub mod channel;
+pub mod checksum;
 pub mod config;
+pub mod control;
 pub mod daemon;
+pub mod delta;
+pub mod disk_space;
 pub mod downloader;
+pub mod durable;
+pub mod history;
+pub mod hooks;
 pub mod installer;
+pub mod journal;
+pub mod metrics;
+pub mod output;
+pub mod reboot;
+pub mod schedule;
+pub mod security_version;
+pub mod signature;
+pub mod slots;
+pub mod tls;
 pub mod types;
+pub mod version;
@@ -1,44 +1,185 @@
 use anyhow::{Context, Result};
 use clap::Parser;
+use ota_client::backup_store::{BackupEntry, load_backup_index};
+use ota_client::boot::mark_pending_confirmation;
+use ota_client::bundle::{LocalSource, open_local_update};
+use ota_client::channel::{PinRecord, UpdatePin, clear_pin, load_pin, save_pin};
 use ota_client::config::load_config;
+use ota_client::control::{ControlClient, ControlRequest, ControlResponse};
 use ota_client::daemon::OtaDaemon;
-use ota_client::downloader::Downloader;
-use ota_client::installer::Installer;
-use ota_client::types::{Cli, Commands, UpdateRecord};
+use ota_client::downloader::{Downloader, remove_downloads};
+use ota_client::history::{
+    HistoryFilter, append_history, format_json_lines, format_table, history_path, load_history,
+    save_history,
+};
+use ota_client::installer::{InstallPlan, Installer};
+use ota_client::output::{
+    ActionReport, ActionResult, CheckReport, CommandOutcome, ConfigSummary, EXIT_FAILURE,
+    ErrorReport, PinReport, StatusReport,
+};
+use ota_client::reboot::{PendingReboot, load_pending_reboot, mark_pending_reboot};
+use ota_client::tls::{client_certificate, device_identity};
+use ota_client::types::{
+    Cli, Commands, DaemonState, DaemonStatus, DryRun, HistoryFormat, KernelMetadata, OtaConfig,
+    OutputFormat, UpdateRecord, UpdateStatus,
+};
+use ota_client::version::{InstalledVersion, load_installed_version};
+use serde::Serialize;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
 use tracing::{error, info, warn};
 use tracing_subscriber::{filter::EnvFilter, fmt, prelude::*};
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     // Initialize logging
     setup_logging();
 
     // Parse command line arguments
     let cli = Cli::parse();
 
+    match run_command(&cli).await {
+        Ok(outcome) => ExitCode::from(outcome.exit_code()),
+        Err(e) => {
+            match cli.output {
+                OutputFormat::Json => {
+                    if let Err(print_err) = print_json(&ErrorReport::new(&e)) {
+                        eprintln!("Error: {:?}", print_err);
+                    }
+                }
+                OutputFormat::Text => eprintln!("Error: {:?}", e),
+            }
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Dispatch the subcommand, printing its result when JSON output is requested
+async fn run_command(cli: &Cli) -> Result<CommandOutcome> {
+    let output = cli.output;
     match &cli.command {
-        Commands::Daemon { config } => {
+        Commands::Daemon {
+            config,
+            dry_run,
+            download,
+        } => {
             info!("Starting OTA daemon with config: {}", config);
-            run_daemon(config).await
+            let dry_run = dry_run.then_some(DryRun {
+                download: *download,
+            });
+            run_daemon(config, dry_run).await?;
+            Ok(CommandOutcome::Success)
         }
         Commands::Check { config } => {
             info!("Performing one-time update check with config: {}", config);
-            run_check(config).await
+            let report = run_check(config).await?;
+            emit(output, &report)?;
+            Ok(if report.update_available {
+                CommandOutcome::UpdateAvailable
+            } else {
+                CommandOutcome::Success
+            })
         }
-        Commands::Update { config } => {
+        Commands::Update {
+            config,
+            dry_run,
+            download,
+        } => {
             info!("Forcing update with config: {}", config);
-            run_update(config).await
+            let dry_run = dry_run.then_some(DryRun {
+                download: *download,
+            });
+            let report = run_update(config, dry_run).await?;
+            emit(output, &report)?;
+            Ok(report.outcome())
         }
         Commands::Status { config } => {
             info!("Showing status with config: {}", config);
-            run_status(config).await
+            let report = run_status(config).await?;
+            emit(output, &report)?;
+            Ok(CommandOutcome::Success)
         }
-        Commands::Rollback { config } => {
+        Commands::Rollback { config, to } => {
             info!("Performing rollback with config: {}", config);
-            run_rollback(config).await
+            let report = run_rollback(config, to.as_deref()).await?;
+            emit(output, &report)?;
+            Ok(report.outcome())
         }
+        Commands::Install {
+            config,
+            bundle,
+            image,
+            metadata,
+        } => {
+            info!("Installing local update with config: {}", config);
+            let source = match (bundle, image, metadata) {
+                (Some(bundle), _, _) => LocalSource::Bundle {
+                    path: bundle.clone(),
+                },
+                (None, Some(image), Some(metadata)) => LocalSource::Files {
+                    image: image.clone(),
+                    metadata: metadata.clone(),
+                },
+                _ => anyhow::bail!("Either a bundle or --image and --metadata are required"),
+            };
+            let report = run_install(config, source).await?;
+            emit(output, &report)?;
+            Ok(report.outcome())
+        }
+        Commands::Pin {
+            config,
+            version,
+            hold,
+            clear,
+        } => {
+            let change = match (version, hold, clear) {
+                (Some(version), _, _) => Some(Some(UpdatePin::Version(version.clone()))),
+                (None, true, _) => Some(Some(UpdatePin::Hold)),
+                (None, false, true) => Some(None),
+                (None, false, false) => None,
+            };
+            let report = run_pin(config, change).await?;
+            emit(output, &report)?;
+            Ok(CommandOutcome::Success)
+        }
+        Commands::History {
+            config,
+            status,
+            version,
+            since,
+            until,
+            limit,
+            format,
+            export,
+            clear,
+        } => {
+            let filter = HistoryFilter {
+                status: status.clone(),
+                version: version.clone(),
+                since: *since,
+                until: *until,
+                limit: *limit,
+            };
+            run_history(config, &filter, *format, output, export.as_deref(), *clear).await?;
+            Ok(CommandOutcome::Success)
+        }
+    }
+}
+
+/// Print a command result on standard output as a single JSON line
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    let json = serde_json::to_string(value).context("Failed to serialize command result")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Print the result as JSON when requested; text results are already logged
+fn emit<T: Serialize>(output: OutputFormat, report: &T) -> Result<()> {
+    match output {
+        OutputFormat::Json => print_json(report),
+        OutputFormat::Text => Ok(()),
     }
 }
 
@@ -47,19 +188,19 @@ fn setup_logging() {
     let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
 
     tracing_subscriber::registry()
-        .with(fmt::layer().with_target(false))
+        .with(fmt::layer().with_target(false).with_writer(std::io::stderr))
         .with(env_filter)
         .init();
 }
 
 /// Run the daemon in background mode
-async fn run_daemon(config_path: &str) -> Result<()> {
+async fn run_daemon(config_path: &str, dry_run: Option<DryRun>) -> Result<()> {
     info!("Initializing OTA daemon");
 
     // Ensure config file exists
     ensure_config_exists(config_path).await?;
 
-    let daemon = OtaDaemon::new(config_path)
+    let daemon = OtaDaemon::with_dry_run(config_path, dry_run)
         .await
         .context("Failed to create daemon instance")?;
 
@@ -70,13 +211,49 @@ async fn run_daemon(config_path: &str) -> Result<()> {
 }
 
 /// Perform a one-time update check
-async fn run_check(config_path: &str) -> Result<()> {
+async fn run_check(config_path: &str) -> Result<CheckReport> {
     info!("Loading configuration and checking for updates");
 
     ensure_config_exists(config_path).await?;
     let config = load_config(config_path).await?;
 
-    let mut downloader = Downloader::new(config);
+    let installed_version = log_installed_version(&config).await;
+
+    let update = match ControlClient::connect(&config.control_socket_path).await {
+        Some(client) => {
+            info!("Daemon is running; asking it to check for updates");
+            match client.request(ControlRequest::Check).await? {
+                ControlResponse::Check { update } => update,
+                other => return Err(unexpected_response(other)),
+            }
+        }
+        None => check_standalone(config).await?,
+    };
+
+    match &update {
+        Some(metadata) => {
+            info!("✅ Update available!");
+            info!("  Version: {}", metadata.latest_version);
+            info!("  Size: {} bytes", metadata.file_size);
+            info!("  Released: {}", metadata.release_date);
+            info!("  Description: {}", metadata.description);
+            info!("Run 'ota-client update' to install this update");
+        }
+        None => {
+            info!("✅ No updates available - system is up to date");
+        }
+    }
+
+    Ok(CheckReport {
+        update_available: update.is_some(),
+        installed_version,
+        update,
+    })
+}
+
+/// Discover the server and ask it for an update without the daemon
+async fn check_standalone(config: OtaConfig) -> Result<Option<KernelMetadata>> {
+    let mut downloader = Downloader::new(config)?;
 
     // Discover server
     info!("Discovering OTA server...");
@@ -88,37 +265,28 @@ async fn run_check(config_path: &str) -> Result<()> {
             );
 
             // Check for updates
-            match downloader.check_for_updates().await? {
-                Some(metadata) => {
-                    info!("✅ Update available!");
-                    info!("  Version: {}", metadata.latest_version);
-                    info!("  Size: {} bytes", metadata.file_size);
-                    info!("  Released: {}", metadata.release_date);
-                    info!("  Description: {}", metadata.description);
-                    info!("Run 'ota-client update' to install this update");
-                }
-                None => {
-                    info!("✅ No updates available - system is up to date");
-                }
-            }
+            downloader.check_for_updates().await
         }
         Err(e) => {
             error!("❌ Failed to discover OTA server: {}", e);
-            return Err(e);
+            Err(e)
         }
     }
-
-    Ok(())
 }
 
-/// Force update download and installation
-async fn run_update(config_path: &str) -> Result<()> {
+/// Force update download and installation, or only plan it with `dry_run`
+async fn run_update(config_path: &str, dry_run: Option<DryRun>) -> Result<ActionReport> {
     info!("Loading configuration and forcing update");
 
     ensure_config_exists(config_path).await?;
     let config = load_config(config_path).await?;
 
-    let mut downloader = Downloader::new(config.clone());
+    // Never run a second pipeline next to the daemon's
+    if let Some(client) = ControlClient::connect(&config.control_socket_path).await {
+        return update_via_daemon(client, &config.control_socket_path, dry_run).await;
+    }
+
+    let mut downloader = Downloader::new(config.clone())?;
 
     // Discover server
     info!("Discovering OTA server...");
@@ -139,43 +307,111 @@ async fn run_update(config_path: &str) -> Result<()> {
         }
         None => {
             info!("No updates available - system is already up to date");
-            return Ok(());
+            return Ok(ActionReport::new(ActionResult::UpToDate, None));
         }
     };
 
+    let mut installer = Installer::new(config.clone()).context("Failed to initialize installer")?;
+
+    if let Some(dry_run) = dry_run {
+        return plan_update(&downloader, &installer, &metadata, dry_run).await;
+    }
+
     // Download update
     info!("Downloading kernel update...");
+    let base_image = installer.active_kernel_path().await?;
     let downloaded_path = downloader
-        .download_with_retries(&metadata, None)
+        .download_update(&metadata, &base_image, None)
         .await
         .context("Failed to download kernel")?;
     info!("Download completed: {}", downloaded_path);
 
+    let artifacts = downloader
+        .download_artifacts(&metadata)
+        .await
+        .context("Failed to download artifacts")?;
+
     // Install update
     info!("Installing kernel update...");
-    let mut installer = Installer::new(config).context("Failed to initialize installer")?;
     installer
-        .install_kernel(&downloaded_path, &metadata, None)
+        .install_update(&downloaded_path, &artifacts, &metadata, None)
         .await
         .context("Failed to install kernel")?;
 
-    // Cleanup
-    if let Err(e) = fs::remove_file(&downloaded_path).await {
-        warn!("Failed to cleanup downloaded file: {}", e);
+    // Let the daemon confirm the new kernel after reboot, and reboot into it
+    if let Err(e) = mark_pending_confirmation(&config, &metadata).await {
+        warn!("Failed to write boot confirmation marker: {}", e);
+    }
+    if let Err(e) = mark_pending_reboot(&config, &metadata.latest_version).await {
+        warn!("Failed to write reboot marker: {}", e);
     }
 
+    // Cleanup
+    remove_downloads(Some(&downloaded_path), &artifacts).await;
+
     info!("✅ Update installed successfully!");
     info!("System reboot may be required to activate the new kernel.");
 
-    Ok(())
+    Ok(ActionReport::new(
+        ActionResult::Installed,
+        Some(metadata.latest_version),
+    ))
+}
+
+/// Check that an update could be installed and report what installing it would do
+///
+/// With `download`, the image and artifacts are fetched and verified too, then
+/// removed again. Nothing is written to the history.
+async fn plan_update(
+    downloader: &Downloader,
+    installer: &Installer,
+    metadata: &KernelMetadata,
+    dry_run: DryRun,
+) -> Result<ActionReport> {
+    let (downloaded_path, artifacts) = if dry_run.download {
+        info!("Downloading kernel update to verify it...");
+        let base_image = installer.active_kernel_path().await?;
+        let downloaded_path = downloader
+            .download_update(metadata, &base_image, None)
+            .await
+            .context("Failed to download kernel")?;
+        let artifacts = match downloader.download_artifacts(metadata).await {
+            Ok(artifacts) => artifacts,
+            Err(e) => {
+                remove_downloads(Some(&downloaded_path), &[]).await;
+                return Err(e.context("Failed to download artifacts"));
+            }
+        };
+        (Some(downloaded_path), artifacts)
+    } else {
+        (None, Vec::new())
+    };
+
+    let plan = installer
+        .plan_install(downloaded_path.as_deref(), &artifacts, metadata)
+        .await;
+    remove_downloads(downloaded_path.as_deref(), &artifacts).await;
+    let plan = plan.context("Dry run failed")?;
+
+    log_plan(&plan);
+    Ok(ActionReport::dry_run(plan))
+}
+
+/// Log the operations of a dry-run plan
+fn log_plan(plan: &InstallPlan) {
+    info!("Dry run: version {} can be installed", plan.version);
+    if !plan.image_verified {
+        info!("Dry run: image not downloaded; pass --download to verify it");
+    }
+    for operation in &plan.operations {
+        info!("  would {}", operation);
+    }
 }
 
 /// Show current system status
-async fn run_status(config_path: &str) -> Result<()> {
+async fn run_status(config_path: &str) -> Result<StatusReport> {
     ensure_config_exists(config_path).await?;
 
-    // Try to get daemon status if it's running
-    // For now, we'll show basic configuration info
     let config = load_config(config_path).await?;
 
     info!("=== OTA Client Status ===");
@@ -185,73 +421,506 @@ async fn run_status(config_path: &str) -> Result<()> {
     info!("Kernel path: {}", config.kernel_path);
     info!("Backup path: {}", config.backup_path);
     info!("Download timeout: {} seconds", config.download_timeout_secs);
+    let installed_version = log_installed_version(&config).await;
+    let backups = log_backups(&config).await;
+    let pending_reboot = log_pending_reboot(&config).await;
+    info!("Channel: {}", config.channel.name());
+    let pin = log_pin(&config).await;
+    log_client_certificate(&config);
+
+    let daemon = match ControlClient::connect(&config.control_socket_path).await {
+        Some(client) => match client.request(ControlRequest::Status).await? {
+            ControlResponse::Status { status } => {
+                log_daemon_status(&status);
+                Some(status)
+            }
+            other => return Err(unexpected_response(other)),
+        },
+        None => {
+            info!("Daemon: not running");
+            None
+        }
+    };
 
     // Check if history file exists
-    let history_path = format!("{}/ota_update_history.json", config.download_path);
-    match fs::read_to_string(&history_path).await {
-        Ok(content) => match serde_json::from_str::<Vec<UpdateRecord>>(&content) {
-            Ok(history) => {
-                info!("Update history: {} records", history.len());
-                if let Some(last_update) = history.last() {
-                    info!(
-                        "Last update: {} ({})",
-                        last_update.version,
-                        last_update.timestamp.format("%Y-%m-%d %H:%M:%S")
-                    );
-                    info!("Status: {:?}", last_update.status);
-                }
-            }
-            Err(_) => {
-                warn!("Failed to parse update history");
+    let history = match load_history(&history_path(&config)).await {
+        Ok(history) if history.is_empty() => {
+            info!("No update history found");
+            history
+        }
+        Ok(history) => {
+            info!("Update history: {} records", history.len());
+            if let Some(last_update) = history.last() {
+                info!(
+                    "Last update: {} ({})",
+                    last_update.version,
+                    last_update.timestamp.format("%Y-%m-%d %H:%M:%S")
+                );
+                info!("Status: {:?}", last_update.status);
             }
-        },
+            history
+        }
         Err(_) => {
-            info!("No update history found");
+            warn!("Failed to parse update history");
+            Vec::new()
         }
-    }
+    };
 
     // Test server connectivity
     info!("Testing server connectivity...");
-    let mut downloader = Downloader::new(config);
-    match downloader.discover_server().await {
+    let summary = ConfigSummary::new(config_path, &config, device_identity(&config));
+    let mut downloader = Downloader::new(config)?;
+    let server = match downloader.discover_server().await {
         Ok(server_info) => {
             info!(
                 "✅ Server reachable: {} at {}",
                 server_info.name, server_info.address
             );
+            Some(format!("{} at {}", server_info.name, server_info.address))
         }
         Err(e) => {
             warn!("❌ Server not reachable: {}", e);
+            None
+        }
+    };
+
+    Ok(StatusReport {
+        config: summary,
+        installed_version,
+        backups,
+        daemon,
+        pending_reboot,
+        pin,
+        update_count: history.len(),
+        last_update: history.last().cloned(),
+        server,
+    })
+}
+
+/// Log a kernel the device still has to reboot into
+async fn log_pending_reboot(config: &OtaConfig) -> Option<PendingReboot> {
+    let marker = match load_pending_reboot(config).await {
+        Ok(marker) => marker?,
+        Err(e) => {
+            warn!("Failed to read reboot marker: {}", e);
+            return None;
         }
+    };
+
+    match marker.reboot_at {
+        Some(reboot_at) => info!(
+            "Reboot pending: version {} at {}",
+            marker.version,
+            reboot_at.format("%Y-%m-%d %H:%M:%S UTC")
+        ),
+        None => info!("Reboot pending: version {}", marker.version),
     }
+    Some(marker)
+}
 
-    Ok(())
+/// Log the pin or hold an operator put on updates
+async fn log_pin(config: &OtaConfig) -> Option<PinRecord> {
+    let record = match load_pin(config).await {
+        Ok(record) => record?,
+        Err(e) => {
+            warn!("Failed to read update pin: {}", e);
+            return None;
+        }
+    };
+
+    info!(
+        "Updates {} (since {})",
+        record.pin,
+        record.set_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    Some(record)
 }
 
-/// Perform rollback to previous kernel
-async fn run_rollback(config_path: &str) -> Result<()> {
+/// Show the update pin, or replace it when `change` is given (`Some(None)` clears it)
+async fn run_pin(config_path: &str, change: Option<Option<UpdatePin>>) -> Result<PinReport> {
+    ensure_config_exists(config_path).await?;
+    let config = load_config(config_path).await?;
+
+    // The daemon reads the pin before every check, so it needs no notice
+    match change {
+        Some(Some(pin)) => {
+            let record = PinRecord {
+                pin,
+                set_at: chrono::Utc::now(),
+            };
+            save_pin(&config, &record).await?;
+        }
+        Some(None) => {
+            clear_pin(&config).await?;
+            info!("Update pin cleared");
+        }
+        None => {}
+    }
+
+    info!("Channel: {}", config.channel.name());
+    let pin = log_pin(&config).await;
+    if pin.is_none() {
+        info!("Updates follow the {} channel", config.channel.name());
+    }
+
+    Ok(PinReport {
+        channel: config.channel,
+        pin,
+    })
+}
+
+/// Perform rollback to previous kernel, or to `target` from the backup store
+async fn run_rollback(config_path: &str, target: Option<&str>) -> Result<ActionReport> {
     info!("Loading configuration and performing rollback");
 
     ensure_config_exists(config_path).await?;
     let config = load_config(config_path).await?;
 
-    let installer = Installer::new(config).context("Failed to initialize installer")?;
+    if let Some(client) = ControlClient::connect(&config.control_socket_path).await {
+        info!("Daemon is running; asking it to roll back");
+        let request = ControlRequest::Rollback {
+            version: target.map(str::to_string),
+        };
+        return match client.request(request).await? {
+            ControlResponse::RolledBack => {
+                info!("✅ Rollback completed successfully!");
+                info!("System reboot may be required to activate the previous kernel.");
+                Ok(ActionReport::new(
+                    ActionResult::RolledBack,
+                    target.map(str::to_string),
+                ))
+            }
+            other => Err(unexpected_response(other)),
+        };
+    }
 
-    info!("Rolling back to previous kernel...");
-    installer
-        .rollback()
-        .await
-        .context("Failed to perform rollback")?;
+    let mut installer = Installer::new(config.clone()).context("Failed to initialize installer")?;
+
+    match target {
+        Some(version) => {
+            info!("Rolling back to version {}...", version);
+            installer
+                .rollback_to(version)
+                .await
+                .context("Failed to perform rollback")?;
+        }
+        None => {
+            info!("Rolling back to previous kernel...");
+            installer
+                .rollback(true)
+                .await
+                .context("Failed to perform rollback")?;
+        }
+    }
 
     info!("✅ Rollback completed successfully!");
     info!("System reboot may be required to activate the previous kernel.");
 
+    // Installer rollbacks record the restored kernel as the installed one
+    let version = load_installed_version(&config)
+        .await
+        .ok()
+        .flatten()
+        .map(|installed| installed.version);
+    Ok(ActionReport::new(ActionResult::RolledBack, version))
+}
+
+/// Install a kernel from local files, without discovering a server
+async fn run_install(config_path: &str, source: LocalSource) -> Result<ActionReport> {
+    ensure_config_exists(config_path).await?;
+    let config = load_config(config_path).await?;
+
+    // Never run a second install next to the daemon's
+    if let Some(client) = ControlClient::connect(&config.control_socket_path).await {
+        info!("Daemon is running; asking it to install the update");
+        let source = absolute_source(source)?;
+        return match client.request(ControlRequest::Install { source }).await? {
+            ControlResponse::Installed { version } => {
+                info!("✅ Version {} installed successfully!", version);
+                info!("System reboot may be required to activate the new kernel.");
+                Ok(ActionReport::new(ActionResult::Installed, Some(version)))
+            }
+            other => Err(unexpected_response(other)),
+        };
+    }
+
+    let update = open_local_update(&config, &source).await?;
+    info!(
+        "Installing version {} from {}",
+        update.metadata.latest_version,
+        update.kernel_path.display()
+    );
+
+    let start_time = std::time::Instant::now();
+    let mut installer = Installer::new(config.clone()).context("Failed to initialize installer")?;
+    let result = installer
+        .install_update(
+            &update.kernel_path.to_string_lossy(),
+            &update.artifacts,
+            &update.metadata,
+            None,
+        )
+        .await
+        .context("Failed to install kernel");
+    update.cleanup().await;
+
+    let record = UpdateRecord {
+        timestamp: chrono::Utc::now(),
+        version: update.metadata.latest_version.clone(),
+        status: if result.is_ok() {
+            UpdateStatus::Success
+        } else {
+            UpdateStatus::Failed
+        },
+        error_message: result.as_ref().err().map(|e| e.to_string()),
+        duration_seconds: start_time.elapsed().as_secs(),
+        device_id: device_identity(&config),
+    };
+    if let Err(e) = append_history(&history_path(&config), record).await {
+        warn!("Failed to record update history: {}", e);
+    }
+    result?;
+
+    // Let the daemon confirm the new kernel after reboot, and reboot into it
+    if let Err(e) = mark_pending_confirmation(&config, &update.metadata).await {
+        warn!("Failed to write boot confirmation marker: {}", e);
+    }
+    if let Err(e) = mark_pending_reboot(&config, &update.metadata.latest_version).await {
+        warn!("Failed to write reboot marker: {}", e);
+    }
+
+    info!("✅ Update installed successfully!");
+    info!("System reboot may be required to activate the new kernel.");
+
+    Ok(ActionReport::new(
+        ActionResult::Installed,
+        Some(update.metadata.latest_version),
+    ))
+}
+
+/// Resolve relative paths, since the daemon has its own working directory
+fn absolute_source(source: LocalSource) -> Result<LocalSource> {
+    let absolute = |path: String| -> Result<String> {
+        let path = std::path::absolute(&path)
+            .with_context(|| format!("Failed to resolve path: {}", path))?;
+        Ok(path.to_string_lossy().to_string())
+    };
+
+    Ok(match source {
+        LocalSource::Bundle { path } => LocalSource::Bundle {
+            path: absolute(path)?,
+        },
+        LocalSource::Files { image, metadata } => LocalSource::Files {
+            image: absolute(image)?,
+            metadata: absolute(metadata)?,
+        },
+    })
+}
+
+/// Print or export the selected update records, then optionally clear the history
+async fn run_history(
+    config_path: &str,
+    filter: &HistoryFilter,
+    format: HistoryFormat,
+    output: OutputFormat,
+    export: Option<&str>,
+    clear: bool,
+) -> Result<()> {
+    ensure_config_exists(config_path).await?;
+    let config = load_config(config_path).await?;
+
+    let path = history_path(&config);
+    let history = load_history(&path).await?;
+    let selected = filter.apply(&history);
+    // JSON output always means JSON lines, whatever --format says
+    let rendered = match (format, output) {
+        (HistoryFormat::Jsonl, _) | (_, OutputFormat::Json) => format_json_lines(&selected)?,
+        (HistoryFormat::Table, OutputFormat::Text) => format_table(&selected),
+    };
+
+    match export {
+        Some(export_path) => {
+            fs::write(export_path, rendered)
+                .await
+                .with_context(|| format!("Failed to export update history to {}", export_path))?;
+            info!(
+                "Exported {} update records to {}",
+                selected.len(),
+                export_path
+            );
+        }
+        None => print!("{}", rendered),
+    }
+
+    if !clear {
+        return Ok(());
+    }
+
+    // The daemon keeps the history in memory, so it must do the clearing
+    let removed = match ControlClient::connect(&config.control_socket_path).await {
+        Some(client) => match client.request(ControlRequest::ClearHistory).await? {
+            ControlResponse::HistoryCleared { removed } => removed,
+            other => return Err(unexpected_response(other)),
+        },
+        None => {
+            save_history(&path, &[]).await?;
+            history.len()
+        }
+    };
+    info!("Cleared {} update records from history", removed);
+
     Ok(())
 }
 
+/// Ask the running daemon to update, following its progress until it finishes
+async fn update_via_daemon(
+    client: ControlClient,
+    socket_path: &str,
+    dry_run: Option<DryRun>,
+) -> Result<ActionReport> {
+    info!("Daemon is running; asking it to perform the update");
+
+    let request = tokio::spawn(client.request(ControlRequest::Update { dry_run }));
+    let mut last_state = None;
+
+    while !request.is_finished() {
+        if let Some(status_client) = ControlClient::connect(socket_path).await
+            && let Ok(ControlResponse::Status { status }) =
+                status_client.request(ControlRequest::Status).await
+            && last_state.as_ref() != Some(&status.current_state)
+        {
+            info!("Daemon: {}", describe_state(&status.current_state));
+            last_state = Some(status.current_state);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    match request.await.context("Update request task failed")?? {
+        ControlResponse::Update { installed: true } => {
+            info!("✅ Update installed successfully!");
+            info!("System reboot may be required to activate the new kernel.");
+            Ok(ActionReport::new(ActionResult::Installed, None))
+        }
+        ControlResponse::Update { installed: false } => {
+            info!("No updates available - system is already up to date");
+            Ok(ActionReport::new(ActionResult::UpToDate, None))
+        }
+        ControlResponse::DryRun { plan } => {
+            log_plan(&plan);
+            Ok(ActionReport::dry_run(plan))
+        }
+        other => Err(unexpected_response(other)),
+    }
+}
+
+/// Log the live status reported by the daemon
+fn log_daemon_status(status: &DaemonStatus) {
+    info!("Daemon: running for {}s", status.uptime.as_secs());
+    info!("Daemon state: {}", describe_state(&status.current_state));
+    match status.last_check {
+        Some(last_check) => info!("Last check: {}", last_check.format("%Y-%m-%d %H:%M:%S")),
+        None => info!("Last check: never"),
+    }
+    info!("Next check in: {}s", status.next_check_in.as_secs());
+}
+
+/// Human readable daemon state
+fn describe_state(state: &DaemonState) -> String {
+    match state {
+        DaemonState::Downloading(progress) => format!(
+            "Downloading ({:.1}%, {}/{} bytes)",
+            progress.percentage, progress.downloaded, progress.total
+        ),
+        DaemonState::Installing(status) => format!("Installing ({:?})", status),
+        DaemonState::Error(message) => format!("Error: {}", message),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Turn a control response the command did not expect into an error
+fn unexpected_response(response: ControlResponse) -> anyhow::Error {
+    match response {
+        ControlResponse::Error { message } => anyhow::anyhow!("Daemon reported: {}", message),
+        other => anyhow::anyhow!("Unexpected response from daemon: {:?}", other),
+    }
+}
+
+/// Log the currently installed kernel version, returning its record
+async fn log_installed_version(config: &OtaConfig) -> Option<InstalledVersion> {
+    match load_installed_version(config).await {
+        Ok(Some(installed)) => {
+            info!(
+                "Installed version: {} (installed {})",
+                installed.version,
+                installed.installed_at.format("%Y-%m-%d %H:%M:%S")
+            );
+            Some(installed)
+        }
+        Ok(None) => {
+            info!("Installed version: unknown");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read installed version: {}", e);
+            None
+        }
+    }
+}
+
+/// Log the kernels kept in the backup store, returning them newest first
+async fn log_backups(config: &OtaConfig) -> Vec<BackupEntry> {
+    let mut entries = match load_backup_index(config).await {
+        Ok(index) => index.entries,
+        Err(e) => {
+            warn!("Failed to read backup index: {}", e);
+            return Vec::new();
+        }
+    };
+    entries.reverse();
+
+    if entries.is_empty() {
+        info!("Kernel backups: none");
+    } else {
+        info!("Kernel backups: {}", entries.len());
+    }
+    for entry in &entries {
+        info!(
+            "  {} ({}, {} bytes): {}",
+            entry.version.as_deref().unwrap_or("unknown version"),
+            entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+            entry.size,
+            entry.file
+        );
+    }
+    entries
+}
+
+/// Log the device identity and when the mutual TLS certificate expires
+fn log_client_certificate(config: &OtaConfig) {
+    if let Some(device_id) = device_identity(config) {
+        info!("Device identity: {}", device_id);
+    }
+
+    match client_certificate(&config.tls) {
+        Ok(Some(cert)) => {
+            let now = chrono::Utc::now();
+            info!(
+                "Client certificate: expires {} ({} days)",
+                cert.not_after.format("%Y-%m-%d %H:%M:%S"),
+                cert.days_until_expiry(now)
+            );
+            if let Some(warning) = cert.expiry_warning(config.tls.cert_expiry_warning_days, now) {
+                warn!("{}", warning);
+            }
+        }
+        Ok(None) => info!("Client certificate: none"),
+        Err(e) => warn!("Failed to read client certificate: {:#}", e),
+    }
+}
+
 /// Ensure configuration file exists, create default if not
 async fn ensure_config_exists(config_path: &str) -> Result<()> {
-    if !fs::metadata(config_path).await.is_ok() {
+    if fs::metadata(config_path).await.is_err() {
         info!(
             "Configuration file not found, creating default: {}",
             config_path
@@ -0,0 +1,464 @@
+use crate::daemon::OtaDaemon;
+use crate::types::{DaemonState, DaemonStatus, UpdateRecord, UpdateStatus};
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+/// Everything the metrics page is rendered from
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub status: DaemonStatus,
+    pub history: Vec<UpdateRecord>,
+    pub installed_version: Option<String>,
+    pub bytes_downloaded: u64,
+}
+
+/// Label values for every daemon state, so inactive states report 0
+const STATE_LABELS: [&str; 9] = [
+    "starting",
+    "idle",
+    "discovering",
+    "checking_updates",
+    "downloading",
+    "installing",
+    "rebooting",
+    "error",
+    "shutdown",
+];
+
+/// Largest request head we are willing to read
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// Time a client gets to send its request, and again to take the response
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Scrapes served at once; further connections are closed right away
+const MAX_CONNECTIONS: usize = 16;
+
+/// Prometheus label value for a daemon state
+pub fn state_label(state: &DaemonState) -> &'static str {
+    match state {
+        DaemonState::Starting => "starting",
+        DaemonState::Idle => "idle",
+        DaemonState::Discovering => "discovering",
+        DaemonState::CheckingUpdates => "checking_updates",
+        DaemonState::Downloading(_) => "downloading",
+        DaemonState::Installing(_) => "installing",
+        DaemonState::Rebooting => "rebooting",
+        DaemonState::Error(_) => "error",
+        DaemonState::Shutdown => "shutdown",
+    }
+}
+
+fn status_label(status: &UpdateStatus) -> &'static str {
+    match status {
+        UpdateStatus::Success => "success",
+        UpdateStatus::Failed => "failed",
+        UpdateStatus::RolledBack => "rolled_back",
+        UpdateStatus::DryRun => "dry_run",
+    }
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Render a snapshot in the Prometheus text exposition format
+pub fn render_metrics(snapshot: &MetricsSnapshot) -> String {
+    let mut out = String::new();
+    let status = &snapshot.status;
+
+    write_header(
+        &mut out,
+        "ota_daemon_state",
+        "gauge",
+        "Current daemon state (1 for the active state).",
+    );
+    let active = state_label(&status.current_state);
+    for label in STATE_LABELS {
+        let value = u8::from(label == active);
+        let _ = writeln!(out, "ota_daemon_state{{state=\"{}\"}} {}", label, value);
+    }
+
+    write_header(
+        &mut out,
+        "ota_download_progress_ratio",
+        "gauge",
+        "Progress of the download in flight (0 when not downloading).",
+    );
+    let progress = match &status.current_state {
+        DaemonState::Downloading(progress) => progress.percentage / 100.0,
+        _ => 0.0,
+    };
+    let _ = writeln!(out, "ota_download_progress_ratio {}", progress);
+
+    write_header(
+        &mut out,
+        "ota_last_check_timestamp_seconds",
+        "gauge",
+        "Unix time of the last completed update check (0 if none yet).",
+    );
+    let last_check = status.last_check.map(|t| t.timestamp()).unwrap_or(0);
+    let _ = writeln!(out, "ota_last_check_timestamp_seconds {}", last_check);
+
+    write_header(
+        &mut out,
+        "ota_downloaded_bytes_total",
+        "counter",
+        "Bytes received from the update server since the daemon started.",
+    );
+    let _ = writeln!(
+        out,
+        "ota_downloaded_bytes_total {}",
+        snapshot.bytes_downloaded
+    );
+
+    write_header(
+        &mut out,
+        "ota_updates",
+        "gauge",
+        "Update records in the retained history, by outcome.",
+    );
+    for status_value in [
+        UpdateStatus::Success,
+        UpdateStatus::Failed,
+        UpdateStatus::RolledBack,
+        UpdateStatus::DryRun,
+    ] {
+        let count = snapshot
+            .history
+            .iter()
+            .filter(|record| record.status == status_value)
+            .count();
+        let _ = writeln!(
+            out,
+            "ota_updates{{status=\"{}\"}} {}",
+            status_label(&status_value),
+            count
+        );
+    }
+
+    write_header(
+        &mut out,
+        "ota_update_duration_seconds",
+        "summary",
+        "Duration of update cycles in the retained history, by outcome.",
+    );
+    for status_value in [
+        UpdateStatus::Success,
+        UpdateStatus::Failed,
+        UpdateStatus::RolledBack,
+        UpdateStatus::DryRun,
+    ] {
+        let durations: Vec<u64> = snapshot
+            .history
+            .iter()
+            .filter(|record| record.status == status_value)
+            .map(|record| record.duration_seconds)
+            .collect();
+        let label = status_label(&status_value);
+        let _ = writeln!(
+            out,
+            "ota_update_duration_seconds_sum{{status=\"{}\"}} {}",
+            label,
+            durations.iter().sum::<u64>()
+        );
+        let _ = writeln!(
+            out,
+            "ota_update_duration_seconds_count{{status=\"{}\"}} {}",
+            label,
+            durations.len()
+        );
+    }
+
+    write_header(
+        &mut out,
+        "ota_last_update_duration_seconds",
+        "gauge",
+        "Duration of the most recent update cycle.",
+    );
+    let last_duration = status
+        .last_update
+        .as_ref()
+        .map(|record| record.duration_seconds)
+        .unwrap_or(0);
+    let _ = writeln!(out, "ota_last_update_duration_seconds {}", last_duration);
+
+    write_header(
+        &mut out,
+        "ota_installed_version_info",
+        "gauge",
+        "Installed kernel version as a label.",
+    );
+    let version = snapshot.installed_version.as_deref().unwrap_or("unknown");
+    let _ = writeln!(
+        out,
+        "ota_installed_version_info{{version=\"{}\"}} 1",
+        escape_label(version)
+    );
+
+    write_header(
+        &mut out,
+        "ota_uptime_seconds",
+        "gauge",
+        "Seconds since the daemon started.",
+    );
+    let _ = writeln!(out, "ota_uptime_seconds {}", status.uptime.as_secs());
+
+    out
+}
+
+/// Serve `GET /metrics` on the given address
+pub async fn serve(daemon: Arc<OtaDaemon>, listen_address: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen_address)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener: {}", listen_address))?;
+
+    info!("Metrics available on http://{}/metrics", listen_address);
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .context("Failed to accept metrics connection")?;
+
+        let Ok(permit) = Arc::clone(&connections).try_acquire_owned() else {
+            debug!("Too many metrics connections, dropping {}", peer);
+            continue;
+        };
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(daemon, stream, CONNECTION_TIMEOUT).await {
+                debug!("Metrics request from {} failed: {}", peer, e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+/// Read up to the end of the request head; the body is never needed
+///
+/// Returns `None` when the head grows past `MAX_REQUEST_BYTES`.
+async fn read_request_head(stream: &mut TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if request.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+    }
+    Ok(Some(request))
+}
+
+async fn handle_connection(
+    daemon: Arc<OtaDaemon>,
+    mut stream: TcpStream,
+    io_timeout: Duration,
+) -> Result<()> {
+    // Idle clients must not hold a task and a socket forever
+    let Ok(request) = timeout(io_timeout, read_request_head(&mut stream)).await else {
+        debug!("Metrics client sent no request in time, dropping connection");
+        return Ok(());
+    };
+    let Some(request) = request? else {
+        warn!("Metrics request head too large, dropping connection");
+        return Ok(());
+    };
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render_metrics(&daemon.metrics_snapshot().await),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "Not Found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method Not Allowed\n".to_string(),
+        ),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    timeout(io_timeout, async {
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await
+    .context("Timed out sending metrics response")??;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DownloadProgress;
+    use chrono::{TimeZone, Utc};
+
+    fn create_test_record(status: UpdateStatus, duration_seconds: u64) -> UpdateRecord {
+        UpdateRecord {
+            timestamp: Utc::now(),
+            version: "1.0.0".to_string(),
+            status,
+            error_message: None,
+            duration_seconds,
+            device_id: None,
+        }
+    }
+
+    fn create_test_snapshot() -> MetricsSnapshot {
+        let history = vec![
+            create_test_record(UpdateStatus::Success, 100),
+            create_test_record(UpdateStatus::Failed, 20),
+            create_test_record(UpdateStatus::Success, 50),
+        ];
+
+        MetricsSnapshot {
+            status: DaemonStatus {
+                current_state: DaemonState::Downloading(DownloadProgress {
+                    downloaded: 512,
+                    total: 1024,
+                    percentage: 50.0,
+                }),
+                last_check: Some(Utc.timestamp_opt(1_750_000_000, 0).unwrap()),
+                last_update: history.last().cloned(),
+                update_count: history.len(),
+                uptime: Duration::from_secs(3600),
+                next_check_in: Duration::from_secs(60),
+            },
+            history,
+            installed_version: Some("6.1.21-\"v8\"".to_string()),
+            bytes_downloaded: 4096,
+        }
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let output = render_metrics(&create_test_snapshot());
+
+        assert!(output.contains("ota_daemon_state{state=\"downloading\"} 1"));
+        assert!(output.contains("ota_daemon_state{state=\"idle\"} 0"));
+        assert!(output.contains("ota_download_progress_ratio 0.5"));
+        assert!(output.contains("ota_last_check_timestamp_seconds 1750000000"));
+        assert!(output.contains("ota_downloaded_bytes_total 4096"));
+        assert!(output.contains("ota_updates{status=\"success\"} 2"));
+        assert!(output.contains("ota_updates{status=\"failed\"} 1"));
+        assert!(output.contains("ota_updates{status=\"rolled_back\"} 0"));
+        assert!(output.contains("ota_update_duration_seconds_sum{status=\"success\"} 150"));
+        assert!(output.contains("ota_update_duration_seconds_count{status=\"success\"} 2"));
+        assert!(output.contains("ota_last_update_duration_seconds 50"));
+        assert!(output.contains("ota_installed_version_info{version=\"6.1.21-\\\"v8\\\"\"} 1"));
+    }
+
+    #[test]
+    fn test_every_state_has_a_label() {
+        for state in [
+            DaemonState::Starting,
+            DaemonState::Idle,
+            DaemonState::Error("boom".to_string()),
+            DaemonState::Shutdown,
+        ] {
+            assert!(STATE_LABELS.contains(&state_label(&state)));
+        }
+    }
+
+    async fn create_test_daemon(temp_dir: &tempfile::TempDir) -> Arc<OtaDaemon> {
+        let config = crate::types::OtaConfig {
+            download_path: temp_dir.path().to_string_lossy().to_string(),
+            state_path: temp_dir.path().join("state").to_string_lossy().to_string(),
+            ..crate::types::OtaConfig::default()
+        };
+        let config_path = temp_dir.path().join("config.toml");
+        std::fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+        Arc::new(OtaDaemon::new(config_path.to_str().unwrap()).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_times_out() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let daemon = create_test_daemon(&temp_dir).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        // The client never sends a request; the server gives up and closes
+        handle_connection(daemon, stream, Duration::from_millis(50))
+            .await
+            .unwrap();
+        let mut buffer = Vec::new();
+        assert_eq!(client.read_to_end(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let daemon = create_test_daemon(&temp_dir).await;
+
+        // Grab a free port, then hand it to the server
+        let address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let server_address = address.clone();
+        tokio::spawn(async move { serve(daemon, &server_address).await });
+
+        let client = reqwest::Client::new();
+        let mut response = None;
+        for _ in 0..50 {
+            if let Ok(r) = client
+                .get(format!("http://{}/metrics", address))
+                .send()
+                .await
+            {
+                response = Some(r);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let body = response.unwrap().text().await.unwrap();
+        assert!(body.contains("ota_daemon_state{state=\"starting\"} 1"));
+        assert!(body.contains("ota_installed_version_info{version=\"unknown\"} 1"));
+    }
+}
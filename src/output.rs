@@ -0,0 +1,220 @@
+use crate::backup_store::BackupEntry;
+use crate::channel::PinRecord;
+use crate::installer::InstallPlan;
+use crate::reboot::PendingReboot;
+use crate::types::{DaemonStatus, KernelMetadata, OtaConfig, UpdateChannel, UpdateRecord};
+use crate::version::InstalledVersion;
+use serde::Serialize;
+
+/// Exit code: the command succeeded; `check` and `update` found nothing newer to install
+pub const EXIT_SUCCESS: u8 = 0;
+/// Exit code: the command failed
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code: the command line was invalid (reported by the argument parser)
+pub const EXIT_USAGE: u8 = 2;
+/// Exit code: `check` or a dry-run `update` found an update that is not installed yet
+pub const EXIT_UPDATE_AVAILABLE: u8 = 100;
+/// Exit code: a rollback was performed
+pub const EXIT_ROLLED_BACK: u8 = 101;
+
+/// How a successful command ended, which decides the exit code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandOutcome {
+    Success,
+    UpdateAvailable,
+    RolledBack,
+}
+
+impl CommandOutcome {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            CommandOutcome::Success => EXIT_SUCCESS,
+            CommandOutcome::UpdateAvailable => EXIT_UPDATE_AVAILABLE,
+            CommandOutcome::RolledBack => EXIT_ROLLED_BACK,
+        }
+    }
+}
+
+/// `check` result
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub update_available: bool,
+    pub installed_version: Option<InstalledVersion>,
+    pub update: Option<KernelMetadata>,
+}
+
+/// What `update`, `install` or `rollback` did
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionResult {
+    Installed,
+    UpToDate,
+    RolledBack,
+    /// A dry run planned an install without performing it
+    DryRun,
+}
+
+/// `update`, `install` and `rollback` result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActionReport {
+    pub result: ActionResult,
+    /// Version installed or restored, when known
+    pub version: Option<String>,
+    /// Operations a dry run would have performed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<InstallPlan>,
+}
+
+impl ActionReport {
+    pub fn new(result: ActionResult, version: Option<String>) -> Self {
+        Self {
+            result,
+            version,
+            plan: None,
+        }
+    }
+
+    /// Report for a dry run that planned installing `plan`
+    pub fn dry_run(plan: InstallPlan) -> Self {
+        Self {
+            result: ActionResult::DryRun,
+            version: Some(plan.version.clone()),
+            plan: Some(plan),
+        }
+    }
+
+    pub fn outcome(&self) -> CommandOutcome {
+        match self.result {
+            ActionResult::RolledBack => CommandOutcome::RolledBack,
+            ActionResult::DryRun => CommandOutcome::UpdateAvailable,
+            ActionResult::Installed | ActionResult::UpToDate => CommandOutcome::Success,
+        }
+    }
+}
+
+/// Configuration values shown by `status`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigSummary {
+    pub config_path: String,
+    pub check_interval_minutes: u64,
+    pub download_path: String,
+    pub kernel_path: String,
+    pub backup_path: String,
+    pub download_timeout_secs: u64,
+    pub device_id: Option<String>,
+    pub channel: UpdateChannel,
+}
+
+impl ConfigSummary {
+    pub fn new(config_path: &str, config: &OtaConfig, device_id: Option<String>) -> Self {
+        Self {
+            config_path: config_path.to_string(),
+            check_interval_minutes: config.check_interval_minutes,
+            download_path: config.download_path.clone(),
+            kernel_path: config.kernel_path.clone(),
+            backup_path: config.backup_path.clone(),
+            download_timeout_secs: config.download_timeout_secs,
+            device_id,
+            channel: config.channel,
+        }
+    }
+}
+
+/// `status` result
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusReport {
+    pub config: ConfigSummary,
+    pub installed_version: Option<InstalledVersion>,
+    /// Backup store entries, newest first
+    pub backups: Vec<BackupEntry>,
+    /// Live daemon status; absent when no daemon is running
+    pub daemon: Option<DaemonStatus>,
+    /// Installed kernel the device has not rebooted into yet
+    pub pending_reboot: Option<PendingReboot>,
+    /// Operator pin or hold overriding the channel
+    pub pin: Option<PinRecord>,
+    pub update_count: usize,
+    pub last_update: Option<UpdateRecord>,
+    /// `name at address` of the discovered server; absent when unreachable
+    pub server: Option<String>,
+}
+
+/// `pin` result
+#[derive(Debug, Clone, Serialize)]
+pub struct PinReport {
+    pub channel: UpdateChannel,
+    /// Pin in effect after the command; absent when the channel decides
+    pub pin: Option<PinRecord>,
+}
+
+/// Failure of any command
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub result: &'static str,
+    pub error: String,
+}
+
+impl ErrorReport {
+    pub fn new(error: &anyhow::Error) -> Self {
+        Self {
+            result: "error",
+            error: format!("{:#}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let codes = [
+            EXIT_SUCCESS,
+            EXIT_FAILURE,
+            EXIT_USAGE,
+            EXIT_UPDATE_AVAILABLE,
+            EXIT_ROLLED_BACK,
+        ];
+        for (i, code) in codes.iter().enumerate() {
+            assert!(!codes[i + 1..].contains(code));
+        }
+
+        assert_eq!(CommandOutcome::UpdateAvailable.exit_code(), 100);
+        let rollback = ActionReport::new(ActionResult::RolledBack, None);
+        assert_eq!(rollback.outcome().exit_code(), EXIT_ROLLED_BACK);
+        let up_to_date = ActionReport::new(ActionResult::UpToDate, None);
+        assert_eq!(up_to_date.outcome().exit_code(), EXIT_SUCCESS);
+        let dry_run = ActionReport::dry_run(InstallPlan {
+            version: "1.1.0".to_string(),
+            image_verified: false,
+            operations: Vec::new(),
+        });
+        assert_eq!(dry_run.outcome().exit_code(), EXIT_UPDATE_AVAILABLE);
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = ActionReport::new(ActionResult::Installed, Some("1.1.0".to_string()));
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"result":"installed","version":"1.1.0"}"#
+        );
+
+        let error = ErrorReport::new(&anyhow::anyhow!("disk full").context("Install failed"));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"result":"error","error":"Install failed: disk full"}"#
+        );
+
+        let check = CheckReport {
+            update_available: false,
+            installed_version: None,
+            update: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&check).unwrap(),
+            r#"{"update_available":false,"installed_version":null,"update":null}"#
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use crate::boot::current_boot_id;
+use crate::durable::{read_json, sync_parent, write_json};
+use crate::schedule::{delay_until_window, local_now};
+use crate::types::{OtaConfig, RebootConfig, RebootPolicy, ScheduleConfig};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Marker for an installed kernel the device has not rebooted into yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingReboot {
+    pub version: String,
+    pub requested_at: DateTime<Utc>,
+    /// When the daemon reboots; `None` once the reboot was issued or when it is left to an operator
+    pub reboot_at: Option<DateTime<Utc>>,
+    /// Boot the install happened in; a different one means the reboot took place
+    pub boot_id: Option<String>,
+}
+
+/// Path of the pending reboot marker for a configuration
+pub fn pending_reboot_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("pending_reboot.json")
+}
+
+/// Load the pending reboot marker, if present
+pub async fn load_pending_reboot(config: &OtaConfig) -> Result<Option<PendingReboot>> {
+    read_json(&pending_reboot_path(config), "reboot marker").await
+}
+
+/// Persist the pending reboot marker, synced before returning
+pub async fn save_pending_reboot(config: &OtaConfig, marker: &PendingReboot) -> Result<()> {
+    write_json(&pending_reboot_path(config), marker, "reboot marker").await?;
+
+    debug!("Saved reboot marker for {}", marker.version);
+    Ok(())
+}
+
+/// Write the marker for a kernel just installed, due when the reboot policy says
+pub async fn mark_pending_reboot(config: &OtaConfig, version: &str) -> Result<PendingReboot> {
+    let now = Utc::now();
+    let marker = PendingReboot {
+        version: version.to_string(),
+        requested_at: now,
+        reboot_at: reboot_due_at(&config.reboot, &config.schedule, now, local_now()),
+        boot_id: current_boot_id().await,
+    };
+    save_pending_reboot(config, &marker).await?;
+    Ok(marker)
+}
+
+/// Remove the pending reboot marker
+pub async fn clear_pending_reboot(config: &OtaConfig) -> Result<()> {
+    let path = pending_reboot_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove reboot marker: {}", path.display()))?;
+        sync_parent(&path).await?;
+    }
+    Ok(())
+}
+
+/// Clear the marker when `boot_id` shows the device has rebooted since the install
+///
+/// Returns the marker that was cleared.
+pub async fn settle_pending_reboot(
+    config: &OtaConfig,
+    boot_id: Option<&str>,
+) -> Result<Option<PendingReboot>> {
+    let Some(marker) = load_pending_reboot(config).await? else {
+        return Ok(None);
+    };
+
+    let rebooted =
+        matches!((marker.boot_id.as_deref(), boot_id), (Some(then), Some(now)) if then != now);
+    if !rebooted {
+        return Ok(None);
+    }
+
+    clear_pending_reboot(config).await?;
+    info!("Rebooted after installing version {}", marker.version);
+    Ok(Some(marker))
+}
+
+/// When a kernel installed at `now` should be rebooted into; `None` for the `never` policy
+///
+/// `local_now` is the same instant in local time, which the maintenance windows use.
+pub fn reboot_due_at(
+    settings: &RebootConfig,
+    schedule: &ScheduleConfig,
+    now: DateTime<Utc>,
+    local_now: NaiveDateTime,
+) -> Option<DateTime<Utc>> {
+    let delay = match settings.policy {
+        RebootPolicy::Never => return None,
+        RebootPolicy::Immediate => Duration::ZERO,
+        RebootPolicy::NextWindow => delay_until_window(schedule, local_now).unwrap_or_default(),
+        RebootPolicy::Delayed => Duration::from_secs(settings.delay_secs),
+    };
+    Some(now + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX))
+}
+
+/// Something holding back a reboot
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inhibitor {
+    LockFile(String),
+    /// A check command exited non-zero or could not be run
+    Command {
+        command: String,
+        reason: String,
+    },
+    /// A systemd inhibitor lock blocking shutdown, as listed by `systemd-inhibit`
+    Systemd(String),
+}
+
+impl fmt::Display for Inhibitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inhibitor::LockFile(path) => write!(f, "lock file {} exists", path),
+            Inhibitor::Command { command, reason } => write!(f, "`{}` {}", command, reason),
+            Inhibitor::Systemd(lock) => write!(f, "systemd inhibitor lock: {}", lock),
+        }
+    }
+}
+
+/// The first condition that currently holds back a reboot
+pub async fn active_inhibitor(settings: &RebootConfig) -> Option<Inhibitor> {
+    if let Some(path) = settings
+        .lock_files
+        .iter()
+        .find(|path| Path::new(path).exists())
+    {
+        return Some(Inhibitor::LockFile(path.clone()));
+    }
+
+    // A hung check must not hold the reboot back forever
+    let timeout = Duration::from_secs(settings.check_timeout_secs);
+    for command in &settings.check_commands {
+        let status = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .kill_on_drop(true)
+            .status();
+
+        let reason = match tokio::time::timeout(timeout, status).await {
+            Ok(Ok(status)) if status.success() => continue,
+            Ok(Ok(status)) => format!("exited with {}", status),
+            Ok(Err(e)) => format!("could not be run: {}", e),
+            Err(_) => format!("timed out after {} seconds", timeout.as_secs()),
+        };
+        return Some(Inhibitor::Command {
+            command: command.clone(),
+            reason,
+        });
+    }
+
+    if settings.systemd_inhibitors {
+        return systemd_shutdown_inhibitors()
+            .await
+            .into_iter()
+            .next()
+            .map(Inhibitor::Systemd);
+    }
+    None
+}
+
+/// Blocking shutdown locks held through logind; empty without `systemd-inhibit`
+async fn systemd_shutdown_inhibitors() -> Vec<String> {
+    let output = match tokio::process::Command::new("systemd-inhibit")
+        .args(["--list", "--no-legend", "--no-pager"])
+        .kill_on_drop(true)
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!("systemd-inhibit --list exited with {}", output.status);
+            return Vec::new();
+        }
+        Err(e) => {
+            debug!("Cannot list systemd inhibitors: {}", e);
+            return Vec::new();
+        }
+    };
+
+    blocking_shutdown_locks(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Lines of a `systemd-inhibit --list` listing that block shutdown
+///
+/// Each line ends with the mode, and one column lists what is inhibited as
+/// colon-separated operations; the free-text columns may contain spaces.
+fn blocking_shutdown_locks(listing: &str) -> Vec<String> {
+    const OPERATIONS: &[&str] = &[
+        "shutdown",
+        "sleep",
+        "idle",
+        "handle-power-key",
+        "handle-suspend-key",
+        "handle-hibernate-key",
+        "handle-lid-switch",
+    ];
+
+    listing
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            let columns: Vec<_> = line.split_whitespace().collect();
+            columns.last() == Some(&"block")
+                && columns.iter().any(|column| {
+                    let what: Vec<_> = column.split(':').collect();
+                    what.contains(&"shutdown") && what.iter().all(|op| OPERATIONS.contains(op))
+                })
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Carries out reboots for the daemon, so tests can observe them instead
+pub trait RebootExecutor: Send + Sync {
+    /// Show a message to logged-in users
+    fn broadcast<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<()>>;
+
+    /// Reboot the system
+    fn reboot(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Reboots the machine through systemd and warns users with `wall`
+#[derive(Debug, Default)]
+pub struct SystemReboot;
+
+impl RebootExecutor for SystemReboot {
+    fn broadcast<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let status = tokio::process::Command::new("wall")
+                .arg(message)
+                .status()
+                .await
+                .context("Failed to run wall")?;
+            if !status.success() {
+                anyhow::bail!("wall exited with {}", status);
+            }
+            Ok(())
+        })
+    }
+
+    fn reboot(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let status = tokio::process::Command::new("systemctl")
+                .arg("reboot")
+                .status()
+                .await
+                .context("Failed to run systemctl reboot")?;
+            if !status.success() {
+                anyhow::bail!("systemctl reboot exited with {}", status);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// What a `RecordingReboot` was asked to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebootAction {
+    Broadcast(String),
+    Reboot,
+}
+
+/// Executor that records requests instead of acting on them
+#[derive(Debug, Default)]
+pub struct RecordingReboot {
+    actions: Mutex<Vec<RebootAction>>,
+}
+
+impl RecordingReboot {
+    /// Requests received so far, oldest first
+    pub fn actions(&self) -> Vec<RebootAction> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    fn record(&self, action: RebootAction) {
+        self.actions.lock().unwrap().push(action);
+    }
+}
+
+impl RebootExecutor for RecordingReboot {
+    fn broadcast<'a>(&'a self, message: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.record(RebootAction::Broadcast(message.to_string()));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn reboot(&self) -> BoxFuture<'_, Result<()>> {
+        self.record(RebootAction::Reboot);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MaintenanceWindow;
+    use chrono::{NaiveDate, TimeZone};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_reboot_due_at_each_policy() {
+        let now = Utc.with_ymd_and_hms(2025, 6, 16, 1, 0, 0).unwrap();
+        let local = NaiveDate::from_ymd_opt(2025, 6, 16)
+            .unwrap()
+            .and_hms_opt(1, 0, 0)
+            .unwrap();
+        let schedule = ScheduleConfig {
+            jitter_secs: 0,
+            maintenance_windows: vec![MaintenanceWindow {
+                days: Vec::new(),
+                start: "02:00".to_string(),
+                end: "03:00".to_string(),
+            }],
+        };
+        let due = |policy| {
+            let settings = RebootConfig {
+                policy,
+                delay_secs: 600,
+                ..RebootConfig::default()
+            };
+            reboot_due_at(&settings, &schedule, now, local)
+        };
+
+        assert_eq!(due(RebootPolicy::Never), None);
+        assert_eq!(due(RebootPolicy::Immediate), Some(now));
+        assert_eq!(
+            due(RebootPolicy::Delayed),
+            Some(now + chrono::Duration::seconds(600))
+        );
+        assert_eq!(
+            due(RebootPolicy::NextWindow),
+            Some(now + chrono::Duration::hours(1))
+        );
+
+        // Without windows the next window is now
+        let settings = RebootConfig {
+            policy: RebootPolicy::NextWindow,
+            ..RebootConfig::default()
+        };
+        assert_eq!(
+            reboot_due_at(&settings, &ScheduleConfig::default(), now, local),
+            Some(now)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inhibitors() {
+        let temp_dir = TempDir::new().unwrap();
+        let lock_file = temp_dir.path().join("maintenance.lock");
+        let mut settings = RebootConfig {
+            lock_files: vec![lock_file.to_string_lossy().to_string()],
+            check_commands: vec!["true".to_string()],
+            systemd_inhibitors: false,
+            ..RebootConfig::default()
+        };
+        assert_eq!(active_inhibitor(&settings).await, None);
+
+        std::fs::write(&lock_file, "").unwrap();
+        assert!(matches!(
+            active_inhibitor(&settings).await,
+            Some(Inhibitor::LockFile(_))
+        ));
+
+        std::fs::remove_file(&lock_file).unwrap();
+        settings.check_commands.push("exit 3".to_string());
+        let inhibitor = active_inhibitor(&settings).await.unwrap();
+        assert!(matches!(&inhibitor, Inhibitor::Command { command, .. } if command == "exit 3"));
+
+        // A hung check is given up on and keeps inhibiting until it passes
+        settings.check_commands = vec!["sleep 30".to_string()];
+        settings.check_timeout_secs = 1;
+        let inhibitor = tokio::time::timeout(Duration::from_secs(10), active_inhibitor(&settings))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            matches!(&inhibitor, Inhibitor::Command { reason, .. } if reason.contains("timed out"))
+        );
+    }
+
+    #[test]
+    fn test_blocking_shutdown_locks() {
+        let listing = "\
+ModemManager 0 root 712 ModemManager sleep ModemManager needs to reset devices delay
+backup job 0 root 900 rsync shutdown:sleep Backup in progress block
+NetworkManager 0 root 640 NetworkManager sleep NetworkManager needs to turn off networks delay
+gdm 120 gdm 1201 gdm-wayland-ses handle-power-key GNOME handles these keys block
+";
+        assert_eq!(
+            blocking_shutdown_locks(listing),
+            ["backup job 0 root 900 rsync shutdown:sleep Backup in progress block"]
+        );
+        assert!(blocking_shutdown_locks("").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_marker_cleared_after_reboot() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+        let marker = PendingReboot {
+            version: "1.1.0".to_string(),
+            requested_at: Utc::now(),
+            reboot_at: None,
+            boot_id: Some("boot-1".to_string()),
+        };
+        save_pending_reboot(&config, &marker).await.unwrap();
+
+        // Same boot: the reboot is still pending
+        assert!(
+            settle_pending_reboot(&config, Some("boot-1"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(load_pending_reboot(&config).await.unwrap(), Some(marker));
+
+        let settled = settle_pending_reboot(&config, Some("boot-2"))
+            .await
+            .unwrap();
+        assert_eq!(settled.unwrap().version, "1.1.0");
+        assert!(load_pending_reboot(&config).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mark_pending_reboot() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+
+        // Left to an operator, but still recorded for `status`
+        let marker = mark_pending_reboot(&config, "1.1.0").await.unwrap();
+        assert_eq!(marker.reboot_at, None);
+        assert_eq!(load_pending_reboot(&config).await.unwrap(), Some(marker));
+
+        config.reboot.policy = RebootPolicy::Immediate;
+        let marker = mark_pending_reboot(&config, "1.2.0").await.unwrap();
+        assert_eq!(marker.reboot_at, Some(marker.requested_at));
+        assert_eq!(load_pending_reboot(&config).await.unwrap(), Some(marker));
+    }
+}
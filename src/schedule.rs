@@ -0,0 +1,244 @@
+use crate::types::{MaintenanceWindow, ScheduleConfig};
+use anyhow::{Context, Result};
+use chrono::{Datelike, Days, NaiveDateTime, NaiveTime, Weekday};
+use rand::Rng;
+use std::time::Duration;
+
+/// A concrete occurrence of a maintenance window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowSpan {
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time {:?}, expected HH:MM", value))
+}
+
+fn parse_days(days: &[String]) -> Result<Vec<Weekday>> {
+    days.iter()
+        .map(|day| {
+            day.trim()
+                .parse::<Weekday>()
+                .map_err(|_| anyhow::anyhow!("Invalid weekday {:?}", day))
+        })
+        .collect()
+}
+
+/// Check that every window has valid days and times
+pub fn validate_windows(windows: &[MaintenanceWindow]) -> Result<()> {
+    for window in windows {
+        parse_days(&window.days)?;
+        let start = parse_time(&window.start)?;
+        let end = parse_time(&window.end)?;
+        if start == end {
+            anyhow::bail!(
+                "Maintenance window {}-{} is empty",
+                window.start,
+                window.end
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Occurrences of the windows that overlap the week starting the day before `now`
+fn window_spans(windows: &[MaintenanceWindow], now: NaiveDateTime) -> Vec<WindowSpan> {
+    let mut spans = Vec::new();
+
+    // Start a day early so a window that crosses midnight is still seen
+    let Some(first_day) = now.date().checked_sub_days(Days::new(1)) else {
+        return spans;
+    };
+
+    for window in windows {
+        let (Ok(days), Ok(start), Ok(end)) = (
+            parse_days(&window.days),
+            parse_time(&window.start),
+            parse_time(&window.end),
+        ) else {
+            continue;
+        };
+
+        for offset in 0..=8 {
+            let Some(date) = first_day.checked_add_days(Days::new(offset)) else {
+                continue;
+            };
+
+            if !days.is_empty() && !days.contains(&date.weekday()) {
+                continue;
+            }
+
+            let span_start = date.and_time(start);
+            let mut span_end = date.and_time(end);
+            if end <= start {
+                span_end += chrono::Duration::days(1);
+            }
+            spans.push(WindowSpan {
+                start: span_start,
+                end: span_end,
+            });
+        }
+    }
+
+    spans
+}
+
+/// The window open at `now`, or else the next one to open
+///
+/// Returns `None` when no windows are configured.
+pub fn current_or_next_window(
+    windows: &[MaintenanceWindow],
+    now: NaiveDateTime,
+) -> Option<WindowSpan> {
+    let spans = window_spans(windows, now);
+
+    if let Some(open) = spans
+        .iter()
+        .filter(|span| span.start <= now && now < span.end)
+        .max_by_key(|span| span.end)
+    {
+        return Some(*open);
+    }
+
+    spans
+        .into_iter()
+        .filter(|span| span.start > now)
+        .min_by_key(|span| span.start)
+}
+
+/// Whether updates may be applied at `now`
+pub fn in_maintenance_window(windows: &[MaintenanceWindow], now: NaiveDateTime) -> bool {
+    if windows.is_empty() {
+        return true;
+    }
+
+    current_or_next_window(windows, now).is_some_and(|span| span.start <= now)
+}
+
+/// Random delay between zero and `max_secs`
+pub fn jitter(max_secs: u64) -> Duration {
+    if max_secs == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs(rand::rng().random_range(0..=max_secs))
+}
+
+/// Delay before the next periodic check
+pub fn next_check_delay(check_interval: Duration, schedule: &ScheduleConfig) -> Duration {
+    check_interval + jitter(schedule.jitter_secs)
+}
+
+/// Delay until updates may next be applied, spread across the start of the window
+///
+/// Jitter is capped to the window length so the action still lands inside it.
+pub fn delay_until_window(schedule: &ScheduleConfig, now: NaiveDateTime) -> Option<Duration> {
+    let span = current_or_next_window(&schedule.maintenance_windows, now)?;
+    if span.start <= now {
+        return Some(Duration::ZERO);
+    }
+
+    let until_start = (span.start - now).to_std().ok()?;
+    let window_length = (span.end - span.start).to_std().ok()?;
+    let max_jitter = schedule
+        .jitter_secs
+        .min(window_length.as_secs().saturating_sub(1));
+    Some(until_start + jitter(max_jitter))
+}
+
+/// Local wall-clock time used to evaluate the windows
+pub fn local_now() -> NaiveDateTime {
+    chrono::Local::now().naive_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // June 2025: the 16th is a Monday
+        NaiveDate::from_ymd_opt(2025, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn window(days: &[&str], start: &str, end: &str) -> MaintenanceWindow {
+        MaintenanceWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            start: start.to_string(),
+            end: end.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_no_windows_means_any_time() {
+        assert!(in_maintenance_window(&[], at(16, 12, 0)));
+        assert!(current_or_next_window(&[], at(16, 12, 0)).is_none());
+    }
+
+    #[test]
+    fn test_window_on_weekday() {
+        let windows = vec![window(&["mon", "wed"], "02:00", "04:00")];
+
+        assert!(in_maintenance_window(&windows, at(16, 2, 30)));
+        assert!(!in_maintenance_window(&windows, at(16, 4, 0)));
+        assert!(!in_maintenance_window(&windows, at(17, 2, 30)));
+
+        // Tuesday noon: next opening is Wednesday 02:00
+        let next = current_or_next_window(&windows, at(17, 12, 0)).unwrap();
+        assert_eq!(next.start, at(18, 2, 0));
+    }
+
+    #[test]
+    fn test_window_across_midnight() {
+        let windows = vec![window(&["fri"], "23:00", "01:30")];
+
+        // Friday the 20th 23:30 and Saturday 01:00 are both inside
+        assert!(in_maintenance_window(&windows, at(20, 23, 30)));
+        assert!(in_maintenance_window(&windows, at(21, 1, 0)));
+        assert!(!in_maintenance_window(&windows, at(21, 2, 0)));
+    }
+
+    #[test]
+    fn test_delay_until_window() {
+        let schedule = ScheduleConfig {
+            jitter_secs: 0,
+            maintenance_windows: vec![window(&[], "02:00", "03:00")],
+        };
+
+        assert_eq!(
+            delay_until_window(&schedule, at(16, 1, 0)),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            delay_until_window(&schedule, at(16, 2, 30)),
+            Some(Duration::ZERO)
+        );
+
+        let jittered = ScheduleConfig {
+            jitter_secs: 86400,
+            ..schedule
+        };
+        let delay = delay_until_window(&jittered, at(16, 1, 0)).unwrap();
+        assert!(delay >= Duration::from_secs(3600) && delay < Duration::from_secs(7200));
+    }
+
+    #[test]
+    fn test_validate_windows() {
+        assert!(validate_windows(&[window(&["Mon", "sunday"], "22:00", "06:00")]).is_ok());
+        assert!(validate_windows(&[window(&["funday"], "02:00", "04:00")]).is_err());
+        assert!(validate_windows(&[window(&[], "25:00", "04:00")]).is_err());
+        assert!(validate_windows(&[window(&[], "02:00", "02:00")]).is_err());
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        assert_eq!(jitter(0), Duration::ZERO);
+        for _ in 0..100 {
+            assert!(jitter(10) <= Duration::from_secs(10));
+        }
+    }
+}
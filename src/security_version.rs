@@ -0,0 +1,321 @@
+use crate::durable::{read_json, write_json};
+use crate::types::OtaConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// An image older than the anti-rollback counter allows
+#[derive(Debug, Clone, PartialEq)]
+pub struct AntiRollbackError {
+    pub version: String,
+    pub security_version: u64,
+    pub minimum: u64,
+}
+
+impl fmt::Display for AntiRollbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "image {} has security version {}, below the minimum {}",
+            self.version, self.security_version, self.minimum
+        )
+    }
+}
+
+impl std::error::Error for AntiRollbackError {}
+
+/// Persisted anti-rollback counter
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SecurityVersionState {
+    /// Highest security version ever installed; nothing older may be installed
+    pub highest: u64,
+    /// Security version of the running kernel
+    pub installed: u64,
+    /// Security version of the kernel a local rollback would restore
+    pub previous: Option<u64>,
+}
+
+/// Path of the anti-rollback counter for a configuration
+pub fn security_version_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("security_version.json")
+}
+
+/// Load the anti-rollback counter, if one has been written
+pub async fn load_security_version(config: &OtaConfig) -> Result<Option<SecurityVersionState>> {
+    let Some(state): Option<SecurityVersionState> =
+        read_json(&security_version_path(config), "security version").await?
+    else {
+        return Ok(None);
+    };
+
+    debug!("Loaded security version counter: {}", state.highest);
+    Ok(Some(state))
+}
+
+/// Persist the anti-rollback counter
+pub async fn save_security_version(config: &OtaConfig, state: &SecurityVersionState) -> Result<()> {
+    write_json(&security_version_path(config), state, "security version").await
+}
+
+/// Lowest security version that may be installed
+pub async fn minimum_security_version(config: &OtaConfig) -> Result<u64> {
+    Ok(load_security_version(config)
+        .await?
+        .map(|state| state.highest)
+        .unwrap_or(0))
+}
+
+/// Refuse images whose security version is below the counter
+pub async fn check_security_version(
+    config: &OtaConfig,
+    version: &str,
+    security_version: u64,
+) -> Result<()> {
+    let minimum = minimum_security_version(config).await?;
+    if security_version < minimum {
+        return Err(AntiRollbackError {
+            version: version.to_string(),
+            security_version,
+            minimum,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Refuse a rollback target below the counter, other than the one step allowed
+pub async fn check_rollback_security_version(
+    config: &OtaConfig,
+    version: &str,
+    security_version: u64,
+) -> Result<()> {
+    let Some(state) = load_security_version(config).await? else {
+        return Ok(());
+    };
+
+    if security_version >= state.highest || state.previous == Some(security_version) {
+        return Ok(());
+    }
+
+    Err(AntiRollbackError {
+        version: version.to_string(),
+        security_version,
+        minimum: state.previous.unwrap_or(state.highest).min(state.highest),
+    }
+    .into())
+}
+
+/// Refuse a plain rollback that would go below the counter unless it was asked for
+///
+/// Only an explicit local rollback, from the CLI or the control socket, or the
+/// revert of a boot that was never confirmed may step below the counter; other
+/// automatic rollbacks must stay at or above it.
+pub async fn check_plain_rollback(
+    config: &OtaConfig,
+    version: &str,
+    allow_below_counter: bool,
+) -> Result<()> {
+    if allow_below_counter {
+        return Ok(());
+    }
+    let Some(state) = load_security_version(config).await? else {
+        return Ok(());
+    };
+
+    match state.previous {
+        Some(previous) if previous < state.highest => Err(AntiRollbackError {
+            version: version.to_string(),
+            security_version: previous,
+            minimum: state.highest,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Record a newly installed image, raising the counter if needed
+pub async fn record_installed_security_version(
+    config: &OtaConfig,
+    security_version: u64,
+) -> Result<()> {
+    let state = load_security_version(config).await?.unwrap_or_default();
+
+    let updated = SecurityVersionState {
+        highest: state.highest.max(security_version),
+        installed: security_version,
+        previous: Some(state.installed),
+    };
+    if updated.highest > state.highest {
+        info!(
+            "Anti-rollback counter raised from {} to {}",
+            state.highest, updated.highest
+        );
+    }
+
+    save_security_version(config, &updated)
+        .await
+        .context("Failed to record security version")
+}
+
+/// Record a plain rollback to the previous image
+///
+/// Only an explicit or unconfirmed-boot rollback gets here below the counter, see
+/// `check_plain_rollback`; it goes at most one step below it.
+/// The counter itself is kept, so updates still have to reach it again. With A/B
+/// slots the version that was left stays available for a rollback back to it.
+pub async fn record_rollback_security_version(config: &OtaConfig, swaps_slots: bool) -> Result<()> {
+    let Some(state) = load_security_version(config).await? else {
+        return Ok(());
+    };
+    let Some(previous) = state.previous else {
+        debug!("No previous security version recorded, counter unchanged");
+        return Ok(());
+    };
+
+    if previous < state.highest {
+        warn!(
+            "Anti-rollback override: local rollback to security version {} (counter stays at {})",
+            previous, state.highest
+        );
+    }
+
+    let updated = SecurityVersionState {
+        highest: state.highest,
+        installed: previous,
+        previous: swaps_slots.then_some(state.installed),
+    };
+    save_security_version(config, &updated)
+        .await
+        .context("Failed to record security version after rollback")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn create_test_config(temp_dir: &TempDir) -> OtaConfig {
+        OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        }
+    }
+
+    fn is_anti_rollback(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| cause.is::<AntiRollbackError>())
+    }
+
+    #[tokio::test]
+    async fn test_counter_refuses_older_images() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        // No counter yet: anything goes
+        check_security_version(&config, "1.0.0", 0).await.unwrap();
+
+        record_installed_security_version(&config, 5).await.unwrap();
+        check_security_version(&config, "2.0.0", 5).await.unwrap();
+        check_security_version(&config, "3.0.0", 6).await.unwrap();
+
+        let error = check_security_version(&config, "0.9.0", 4)
+            .await
+            .unwrap_err();
+        assert!(is_anti_rollback(&error));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_goes_back_one_step() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        record_installed_security_version(&config, 3).await.unwrap();
+        record_installed_security_version(&config, 4).await.unwrap();
+
+        record_rollback_security_version(&config, false)
+            .await
+            .unwrap();
+        let state = load_security_version(&config).await.unwrap().unwrap();
+        assert_eq!(
+            state,
+            SecurityVersionState {
+                highest: 4,
+                installed: 3,
+                previous: None,
+            }
+        );
+
+        // A second rollback can't go further back
+        record_rollback_security_version(&config, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            load_security_version(&config).await.unwrap().unwrap(),
+            state
+        );
+
+        // The counter still holds after rolling back
+        assert!(check_security_version(&config, "1.0.0", 3).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_automatic_rollback_stays_at_counter() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        check_plain_rollback(&config, "1.0.0", false).await.unwrap();
+
+        record_installed_security_version(&config, 3).await.unwrap();
+        record_installed_security_version(&config, 3).await.unwrap();
+        check_plain_rollback(&config, "1.0.0", false).await.unwrap();
+
+        record_installed_security_version(&config, 4).await.unwrap();
+        let error = check_plain_rollback(&config, "1.0.0", false)
+            .await
+            .unwrap_err();
+        assert!(is_anti_rollback(&error));
+        check_plain_rollback(&config, "1.0.0", true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rollback_target_limited_to_one_step() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        check_rollback_security_version(&config, "0.1.0", 0)
+            .await
+            .unwrap();
+
+        record_installed_security_version(&config, 2).await.unwrap();
+        record_installed_security_version(&config, 4).await.unwrap();
+
+        check_rollback_security_version(&config, "1.1.0", 4)
+            .await
+            .unwrap();
+        check_rollback_security_version(&config, "1.0.0", 2)
+            .await
+            .unwrap();
+        let error = check_rollback_security_version(&config, "0.9.0", 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AntiRollbackError>().unwrap().minimum,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_counter_never_decreases_on_install() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = create_test_config(&temp_dir);
+
+        record_installed_security_version(&config, 7).await.unwrap();
+        record_installed_security_version(&config, 7).await.unwrap();
+
+        let state = load_security_version(&config).await.unwrap().unwrap();
+        assert_eq!(state.highest, 7);
+        assert_eq!(state.previous, Some(7));
+    }
+}
@@ -0,0 +1,345 @@
+use crate::types::{KernelMetadata, OtaConfig};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::signature::{ED25519, UnparsedPublicKey};
+use std::fmt;
+use tracing::{debug, info, warn};
+
+/// Reasons a kernel image signature can be rejected
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureError {
+    /// Metadata carries no signature and unsigned images are not allowed
+    Missing,
+    /// Signature is not valid base64 or has the wrong length
+    Malformed(String),
+    /// No trusted public keys are configured
+    NoTrustedKeys,
+    /// Signature does not verify against any trusted public key
+    Invalid,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "image is unsigned and allow_unsigned is off"),
+            SignatureError::Malformed(reason) => write!(f, "malformed signature: {}", reason),
+            SignatureError::NoTrustedKeys => write!(f, "no trusted public keys configured"),
+            SignatureError::Invalid => {
+                write!(f, "signature does not match any trusted public key")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Message covered by the signature: the image version, its digest and file name
+///
+/// The file name follows as a `kernel_file <name>` line. A non-zero security version adds a `security_version <n>` line so it can't
+/// be raised. Each artifact adds an `artifact <json>` line and a delta a
+/// `delta <json>` line, holding every field in declaration order, so a manifest
+/// can't be extended, redirected or given other modes without invalidating it.
+pub fn signed_message(metadata: &KernelMetadata) -> Vec<u8> {
+    let mut message = format!(
+        "{}\n{}\nkernel_file {}",
+        metadata.latest_version, metadata.checksum, metadata.kernel_file
+    );
+    if metadata.security_version > 0 {
+        message.push_str(&format!("\nsecurity_version {}", metadata.security_version));
+    }
+    for artifact in &metadata.artifacts {
+        message.push_str("\nartifact ");
+        message.push_str(&serde_json::to_string(artifact).expect("artifact serializes"));
+    }
+    if let Some(delta) = &metadata.delta {
+        message.push_str("\ndelta ");
+        message.push_str(&serde_json::to_string(delta).expect("delta serializes"));
+    }
+    message.into_bytes()
+}
+
+/// Verify the detached Ed25519 signature in the metadata against the pinned keys
+pub fn verify_metadata_signature(
+    config: &OtaConfig,
+    metadata: &KernelMetadata,
+) -> Result<(), SignatureError> {
+    let Some(signature) = metadata.signature.as_deref() else {
+        if config.allow_unsigned {
+            warn!(
+                "Accepting unsigned image {} (allow_unsigned is enabled)",
+                metadata.latest_version
+            );
+            return Ok(());
+        }
+        return Err(SignatureError::Missing);
+    };
+
+    let signature = BASE64
+        .decode(signature.trim())
+        .map_err(|e| SignatureError::Malformed(e.to_string()))?;
+    if signature.len() != 64 {
+        return Err(SignatureError::Malformed(format!(
+            "expected 64 bytes, got {}",
+            signature.len()
+        )));
+    }
+
+    if config.trusted_public_keys.is_empty() {
+        return Err(SignatureError::NoTrustedKeys);
+    }
+
+    let message = signed_message(metadata);
+    for (index, key) in config.trusted_public_keys.iter().enumerate() {
+        let key_bytes = match BASE64.decode(key.trim()) {
+            Ok(bytes) if bytes.len() == 32 => bytes,
+            _ => {
+                warn!("Ignoring malformed trusted public key #{}", index);
+                continue;
+            }
+        };
+
+        let public_key = UnparsedPublicKey::new(&ED25519, key_bytes);
+        if public_key.verify(&message, &signature).is_ok() {
+            info!(
+                "Signature for version {} verified with trusted key #{}",
+                metadata.latest_version, index
+            );
+            return Ok(());
+        }
+        debug!("Signature did not verify with trusted key #{}", index);
+    }
+
+    Err(SignatureError::Invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn generate_key_pair() -> Ed25519KeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    fn create_test_metadata() -> KernelMetadata {
+        KernelMetadata {
+            latest_version: "1.0.0".to_string(),
+            kernel_file: "kernel-v1.0.0.img".to_string(),
+            file_size: 1024,
+            checksum: "sha256:abc123def456".to_string(),
+            release_date: "2025-06-16T10:30:00Z".to_string(),
+            description: "Test kernel".to_string(),
+            download_url: "/kernels/kernel-v1.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
+        }
+    }
+
+    fn sign(key_pair: &Ed25519KeyPair, metadata: &KernelMetadata) -> String {
+        BASE64.encode(key_pair.sign(&signed_message(metadata)).as_ref())
+    }
+
+    fn config_with_key(key_pair: &Ed25519KeyPair) -> OtaConfig {
+        OtaConfig {
+            trusted_public_keys: vec![BASE64.encode(key_pair.public_key().as_ref())],
+            ..OtaConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_signature() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.signature = Some(sign(&key_pair, &metadata));
+
+        let config = config_with_key(&key_pair);
+        assert!(verify_metadata_signature(&config, &metadata).is_ok());
+    }
+
+    #[test]
+    fn test_signature_from_untrusted_key() {
+        let signing_key = generate_key_pair();
+        let trusted_key = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.signature = Some(sign(&signing_key, &metadata));
+
+        let config = config_with_key(&trusted_key);
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_tampered_checksum() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.signature = Some(sign(&key_pair, &metadata));
+        metadata.checksum = "sha256:0000".to_string();
+
+        let config = config_with_key(&key_pair);
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_kernel_file_is_covered() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.signature = Some(sign(&key_pair, &metadata));
+        metadata.kernel_file = "../../boot/kernel8.img".to_string();
+
+        let config = config_with_key(&key_pair);
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_artifacts_are_covered() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.artifacts.push(crate::types::Artifact {
+            name: "bcm2711-rpi-4-b.dtb".to_string(),
+            download_url: "/kernels/bcm2711-rpi-4-b.dtb".to_string(),
+            destination: "/boot/bcm2711-rpi-4-b.dtb".to_string(),
+            file_size: 512,
+            checksum: "sha256:dtb".to_string(),
+            mode: None,
+            install_type: crate::types::ArtifactInstallType::File,
+        });
+        metadata.signature = Some(sign(&key_pair, &metadata));
+
+        let config = config_with_key(&key_pair);
+        assert!(verify_metadata_signature(&config, &metadata).is_ok());
+
+        metadata.artifacts[0].destination = "/etc/passwd".to_string();
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_artifact_mode_and_install_type_are_covered() {
+        let key_pair = generate_key_pair();
+        let config = config_with_key(&key_pair);
+        let mut metadata = create_test_metadata();
+        metadata.artifacts.push(crate::types::Artifact {
+            name: "overlays.tar".to_string(),
+            download_url: "/kernels/overlays.tar".to_string(),
+            destination: "/boot/overlays/custom.dtbo".to_string(),
+            file_size: 512,
+            checksum: "sha256:dtbo".to_string(),
+            mode: Some("0644".to_string()),
+            install_type: crate::types::ArtifactInstallType::File,
+        });
+        metadata.signature = Some(sign(&key_pair, &metadata));
+        assert!(verify_metadata_signature(&config, &metadata).is_ok());
+
+        let mut setuid = metadata.clone();
+        setuid.artifacts[0].mode = Some("4755".to_string());
+        assert_eq!(
+            verify_metadata_signature(&config, &setuid),
+            Err(SignatureError::Invalid)
+        );
+
+        let mut no_mode = metadata.clone();
+        no_mode.artifacts[0].mode = None;
+        assert_eq!(
+            verify_metadata_signature(&config, &no_mode),
+            Err(SignatureError::Invalid)
+        );
+
+        let mut extracted = metadata.clone();
+        extracted.artifacts[0].install_type = crate::types::ArtifactInstallType::Archive;
+        assert_eq!(
+            verify_metadata_signature(&config, &extracted),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_delta_is_covered() {
+        let key_pair = generate_key_pair();
+        let config = config_with_key(&key_pair);
+        let mut metadata = create_test_metadata();
+        metadata.delta = Some(crate::types::DeltaPatch {
+            format: "zstd".to_string(),
+            base_version: "0.9.0".to_string(),
+            base_checksum: "sha256:base".to_string(),
+            download_url: "/deltas/kernel-v1.0.0.patch".to_string(),
+            file_size: 128,
+            checksum: "sha256:patch".to_string(),
+        });
+        metadata.signature = Some(sign(&key_pair, &metadata));
+        assert!(verify_metadata_signature(&config, &metadata).is_ok());
+
+        metadata.delta.as_mut().unwrap().file_size = u64::MAX;
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+
+        metadata.delta = None;
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_security_version_is_covered() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.security_version = 3;
+        metadata.signature = Some(sign(&key_pair, &metadata));
+
+        let config = config_with_key(&key_pair);
+        assert!(verify_metadata_signature(&config, &metadata).is_ok());
+
+        metadata.security_version = 9;
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_unsigned_image() {
+        let metadata = create_test_metadata();
+
+        let config = OtaConfig::default();
+        assert_eq!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Missing)
+        );
+
+        let insecure_config = OtaConfig {
+            allow_unsigned: true,
+            ..OtaConfig::default()
+        };
+        assert!(verify_metadata_signature(&insecure_config, &metadata).is_ok());
+    }
+
+    #[test]
+    fn test_malformed_signature() {
+        let key_pair = generate_key_pair();
+        let mut metadata = create_test_metadata();
+        metadata.signature = Some("not base64!".to_string());
+
+        let config = config_with_key(&key_pair);
+        assert!(matches!(
+            verify_metadata_signature(&config, &metadata),
+            Err(SignatureError::Malformed(_))
+        ));
+    }
+}
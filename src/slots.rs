@@ -0,0 +1,244 @@
+use crate::durable::{durable_rename, read_json, sync_file, write_json};
+use crate::types::{AbSlotConfig, OtaConfig};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Persisted A/B slot state
+///
+/// Slots are stored as the value of the boot config `kernel=` line, so a
+/// kernel that was booting before A/B mode was enabled can still be restored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlotState {
+    pub active: String,
+    pub previous: Option<String>,
+}
+
+/// Path of the slot state record for a configuration
+pub fn slot_state_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("slot_state.json")
+}
+
+/// Load the slot state record, if one has been written
+pub async fn load_slot_state(config: &OtaConfig) -> Result<Option<SlotState>> {
+    read_json(&slot_state_path(config), "slot state").await
+}
+
+/// Persist the slot state record
+pub async fn save_slot_state(config: &OtaConfig, state: &SlotState) -> Result<()> {
+    write_json(&slot_state_path(config), state, "slot state").await?;
+
+    debug!("Saved slot state: active={}", state.active);
+    Ok(())
+}
+
+/// Value of the `kernel=` line that boots a slot image
+///
+/// The boot loader resolves `kernel=` relative to the boot partition, which
+/// is where the boot config lives.
+pub fn slot_kernel_value(slots: &AbSlotConfig, slot_path: &str) -> String {
+    let boot_dir = Path::new(&slots.boot_config_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("/"));
+
+    match Path::new(slot_path).strip_prefix(boot_dir) {
+        Ok(relative) => relative.to_string_lossy().to_string(),
+        Err(_) => slot_path.to_string(),
+    }
+}
+
+/// Resolve a `kernel=` value to a file path
+pub fn kernel_value_path(slots: &AbSlotConfig, kernel_value: &str) -> PathBuf {
+    let boot_dir = Path::new(&slots.boot_config_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("/"));
+    boot_dir.join(kernel_value)
+}
+
+/// Find the `kernel=` value in a config.txt-style boot config
+///
+/// Only unconditional lines count; entries inside a `[section]` filter other
+/// than `[all]` are ignored.
+pub fn parse_kernel_value(boot_config: &str) -> Option<String> {
+    let mut in_all_section = true;
+    let mut kernel = None;
+
+    for line in boot_config.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_all_section = trimmed.eq_ignore_ascii_case("[all]");
+            continue;
+        }
+        if !in_all_section {
+            continue;
+        }
+        if let Some(value) = trimmed.strip_prefix("kernel=") {
+            kernel = Some(value.trim().to_string());
+        }
+    }
+
+    kernel
+}
+
+/// Return the boot config with its unconditional `kernel=` line set to `kernel_value`
+///
+/// The boot loader applies the last matching `kernel=`, so the line must come
+/// after any conditional one; otherwise it moves into an `[all]` block at the end.
+pub fn set_kernel_value(boot_config: &str, kernel_value: &str) -> String {
+    let mut in_all_section = true;
+    let mut last_unconditional = None;
+    let mut last_conditional = None;
+
+    for (index, line) in boot_config.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_all_section = trimmed.eq_ignore_ascii_case("[all]");
+        } else if trimmed.starts_with("kernel=") {
+            if in_all_section {
+                last_unconditional = Some(index);
+            } else {
+                last_conditional = Some(index);
+            }
+        }
+    }
+    let keep = last_unconditional.filter(|&index| last_conditional.is_none_or(|c| index > c));
+
+    in_all_section = true;
+    let mut lines = Vec::new();
+    for (index, line) in boot_config.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_all_section = trimmed.eq_ignore_ascii_case("[all]");
+        } else if in_all_section && trimmed.starts_with("kernel=") {
+            // Drop the others so only one unconditional kernel= line is left
+            if keep == Some(index) {
+                lines.push(format!("kernel={}", kernel_value));
+            }
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+
+    match (keep, last_conditional) {
+        (Some(_), _) => {}
+        // Prepend so the line sits before any conditional section
+        (None, None) => lines.insert(0, format!("kernel={}", kernel_value)),
+        (None, Some(_)) => {
+            if !in_all_section {
+                lines.push("[all]".to_string());
+            }
+            lines.push(format!("kernel={}", kernel_value));
+        }
+    }
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Read the `kernel=` value currently in the boot config
+pub async fn read_active_kernel_value(slots: &AbSlotConfig, default: &str) -> Result<String> {
+    let content = tokio::fs::read_to_string(&slots.boot_config_path)
+        .await
+        .with_context(|| format!("Failed to read boot config: {}", slots.boot_config_path))?;
+
+    Ok(parse_kernel_value(&content).unwrap_or_else(|| default.to_string()))
+}
+
+/// Atomically point the boot config `kernel=` line at a new value
+pub async fn switch_boot_kernel(slots: &AbSlotConfig, kernel_value: &str) -> Result<()> {
+    let boot_config = Path::new(&slots.boot_config_path);
+    let content = tokio::fs::read_to_string(boot_config)
+        .await
+        .with_context(|| format!("Failed to read boot config: {}", boot_config.display()))?;
+
+    let updated = set_kernel_value(&content, kernel_value);
+
+    // Write next to the original and rename, so the boot loader never sees a partial file
+    let temp_path = boot_config.with_extension("txt.ota_tmp");
+    tokio::fs::write(&temp_path, updated)
+        .await
+        .with_context(|| format!("Failed to write boot config: {}", temp_path.display()))?;
+    sync_file(&temp_path).await?;
+    durable_rename(&temp_path, boot_config)
+        .await
+        .with_context(|| format!("Failed to replace boot config: {}", boot_config.display()))?;
+
+    info!("Boot config now selects kernel={}", kernel_value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_slots() -> AbSlotConfig {
+        AbSlotConfig {
+            slot_a_path: "/boot/kernel_a.img".to_string(),
+            slot_b_path: "/boot/kernel_b.img".to_string(),
+            boot_config_path: "/boot/config.txt".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_slot_kernel_value() {
+        let slots = create_test_slots();
+        assert_eq!(
+            slot_kernel_value(&slots, &slots.slot_a_path),
+            "kernel_a.img"
+        );
+        assert_eq!(
+            kernel_value_path(&slots, "kernel_b.img"),
+            PathBuf::from("/boot/kernel_b.img")
+        );
+    }
+
+    #[test]
+    fn test_parse_kernel_value() {
+        let config = "arm_64bit=1\n[pi4]\nkernel=pi4.img\n[all]\nkernel=kernel_a.img\n";
+        assert_eq!(parse_kernel_value(config), Some("kernel_a.img".to_string()));
+
+        assert_eq!(parse_kernel_value("arm_64bit=1\n"), None);
+    }
+
+    #[test]
+    fn test_set_kernel_value_replaces_line() {
+        let config = "# boot\nkernel=kernel_a.img\narm_64bit=1\n[all]\nkernel=kernel_a.img\n";
+        let updated = set_kernel_value(config, "kernel_b.img");
+
+        assert_eq!(updated, "# boot\narm_64bit=1\n[all]\nkernel=kernel_b.img\n");
+    }
+
+    #[test]
+    fn test_set_kernel_value_overrides_conditional_line() {
+        let config = "# boot\nkernel=kernel_a.img\narm_64bit=1\n[pi4]\nkernel=pi4.img\n";
+        let updated = set_kernel_value(config, "kernel_b.img");
+
+        // The slot switch must come last or the [pi4] line wins on a Pi 4
+        assert_eq!(
+            updated,
+            "# boot\narm_64bit=1\n[pi4]\nkernel=pi4.img\n[all]\nkernel=kernel_b.img\n"
+        );
+
+        // Switching again rewrites the appended line in place
+        assert_eq!(
+            set_kernel_value(&updated, "kernel_a.img"),
+            "# boot\narm_64bit=1\n[pi4]\nkernel=pi4.img\n[all]\nkernel=kernel_a.img\n"
+        );
+        assert_eq!(
+            set_kernel_value("[pi5]\nkernel=pi5.img\n[all]\n", "kernel_a.img"),
+            "[pi5]\nkernel=pi5.img\n[all]\nkernel=kernel_a.img\n"
+        );
+    }
+
+    #[test]
+    fn test_set_kernel_value_inserts_line() {
+        let updated = set_kernel_value("arm_64bit=1\n", "kernel_a.img");
+        assert_eq!(updated, "kernel=kernel_a.img\narm_64bit=1\n");
+        assert_eq!(
+            parse_kernel_value(&updated),
+            Some("kernel_a.img".to_string())
+        );
+    }
+}
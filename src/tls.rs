@@ -0,0 +1,429 @@
+use crate::types::{OtaConfig, TlsConfig};
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, Utc};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Plain HTTP scheme
+pub const HTTP_SCHEME: &str = "http";
+
+/// HTTPS scheme
+pub const HTTPS_SCHEME: &str = "https";
+
+/// Prefix of an SPKI pin, as in `sha256/<base64 digest>`
+const PIN_PREFIX: &str = "sha256/";
+
+/// Details of the client certificate presented for mutual TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    pub subject_cn: Option<String>,
+    pub not_after: DateTime<Utc>,
+}
+
+impl ClientCertificate {
+    /// Whole days left before expiry; negative once expired
+    pub fn days_until_expiry(&self, now: DateTime<Utc>) -> i64 {
+        (self.not_after - now).num_days()
+    }
+
+    /// Warning to log when the certificate expires within `warning_days`
+    pub fn expiry_warning(&self, warning_days: u64, now: DateTime<Utc>) -> Option<String> {
+        let days = self.days_until_expiry(now);
+        let expiry = self.not_after.format("%Y-%m-%d %H:%M:%S UTC");
+        if self.not_after <= now {
+            Some(format!("Client certificate expired on {}", expiry))
+        } else if days.unsigned_abs() <= warning_days {
+            Some(format!(
+                "Client certificate expires in {} days ({})",
+                days, expiry
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Check that a URL scheme is one the client speaks
+pub fn validate_scheme(scheme: &str) -> Result<()> {
+    match scheme {
+        HTTP_SCHEME | HTTPS_SCHEME => Ok(()),
+        other => anyhow::bail!("Unsupported URL scheme {:?}, expected http or https", other),
+    }
+}
+
+/// Scheme to talk to a server with, given the configured and advertised ones
+///
+/// A server may upgrade to HTTPS but never downgrade a client configured for it.
+pub fn select_scheme(configured: &str, advertised: Option<&str>) -> Result<String> {
+    validate_scheme(configured)?;
+
+    let Some(advertised) = advertised else {
+        return Ok(configured.to_string());
+    };
+    let advertised = advertised.trim().to_ascii_lowercase();
+    validate_scheme(&advertised)?;
+
+    if configured == HTTPS_SCHEME && advertised == HTTP_SCHEME {
+        anyhow::bail!("Server only offers plain HTTP but HTTPS is required");
+    }
+    Ok(advertised)
+}
+
+/// Decode a `sha256/<base64>` SubjectPublicKeyInfo pin
+pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let encoded = pin
+        .trim()
+        .strip_prefix(PIN_PREFIX)
+        .with_context(|| format!("SPKI pin must start with {:?}", PIN_PREFIX))?;
+    let digest = BASE64
+        .decode(encoded)
+        .context("SPKI pin is not valid base64")?;
+    digest
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("SPKI pin must be a 32-byte SHA-256 digest"))
+}
+
+/// SHA-256 of a certificate's SubjectPublicKeyInfo
+pub fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32]> {
+    let (_, parsed) =
+        x509_parser::parse_x509_certificate(cert.as_ref()).context("Malformed certificate")?;
+    Ok(Sha256::digest(parsed.tbs_certificate.subject_pki.raw).into())
+}
+
+/// Read every certificate in a PEM bundle into a root store
+fn load_ca_bundle(path: &str) -> Result<RootCertStore> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .with_context(|| format!("Failed to read CA bundle: {}", path))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse CA bundle: {}", path))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("CA bundle contains no certificates: {}", path);
+    }
+
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid certificate in CA bundle: {}", path))?;
+    }
+    Ok(roots)
+}
+
+/// Read the client certificate chain and its private key
+fn load_client_identity(
+    cert_path: &str,
+    key_path: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .with_context(|| format!("Failed to read client certificate: {}", cert_path))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse client certificate: {}", cert_path))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("Client certificate file is empty: {}", cert_path);
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read client key: {}", key_path))?;
+
+    Ok((certs, key))
+}
+
+/// Client certificate and key paths, which must be configured together
+fn client_identity_paths(tls: &TlsConfig) -> Result<Option<(&str, &str)>> {
+    match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (None, None) => Ok(None),
+        _ => anyhow::bail!("client_cert_path and client_key_path must be set together"),
+    }
+}
+
+/// Check the client identity settings without loading the key
+pub fn validate_client_identity(tls: &TlsConfig) -> Result<()> {
+    client_identity_paths(tls).map(|_| ())
+}
+
+/// Parse the leaf of the configured client certificate chain
+pub fn client_certificate(tls: &TlsConfig) -> Result<Option<ClientCertificate>> {
+    let Some(path) = &tls.client_cert_path else {
+        return Ok(None);
+    };
+
+    let cert = CertificateDer::from_pem_file(path)
+        .with_context(|| format!("Failed to read client certificate: {}", path))?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert.as_ref())
+        .context("Malformed client certificate")?;
+
+    let subject_cn = parsed
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .map(str::to_string);
+    let not_after = DateTime::from_timestamp(parsed.validity().not_after.timestamp(), 0)
+        .context("Client certificate expiry is out of range")?;
+
+    Ok(Some(ClientCertificate {
+        subject_cn,
+        not_after,
+    }))
+}
+
+/// Identity recorded with updates: the configured device ID, else the certificate CN
+pub fn device_identity(config: &OtaConfig) -> Option<String> {
+    config.device_id.clone().or_else(|| {
+        client_certificate(&config.tls)
+            .ok()
+            .flatten()
+            .and_then(|cert| cert.subject_cn)
+    })
+}
+
+/// rustls configuration for the HTTP client, or `None` to keep reqwest's defaults
+///
+/// A CA bundle replaces the built-in roots rather than adding to them.
+pub fn client_tls_config(tls: &TlsConfig) -> Result<Option<ClientConfig>> {
+    let identity = match client_identity_paths(tls)? {
+        Some((cert_path, key_path)) => Some(load_client_identity(cert_path, key_path)?),
+        None => None,
+    };
+
+    if tls.ca_bundle_path.is_none() && tls.spki_pins.is_empty() && identity.is_none() {
+        return Ok(None);
+    }
+
+    let roots = match &tls.ca_bundle_path {
+        Some(path) => load_ca_bundle(path)?,
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        },
+    };
+    let pins = tls
+        .spki_pins
+        .iter()
+        .map(|pin| parse_pin(pin))
+        .collect::<Result<Vec<_>>>()?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS protocol versions")?;
+
+    let builder = if pins.is_empty() {
+        builder.with_root_certificates(roots)
+    } else {
+        let verifier = PinnedCertVerifier::new(roots, pins, provider)?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+    };
+
+    let config = match identity {
+        Some((certs, key)) => builder
+            .with_client_auth_cert(certs, key)
+            .context("Client certificate does not match its key")?,
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Some(config))
+}
+
+/// Normal chain verification plus a match of the server key against the pins
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl PinnedCertVerifier {
+    fn new(
+        roots: RootCertStore,
+        pins: Vec<[u8; 32]>,
+        provider: Arc<CryptoProvider>,
+    ) -> Result<Self> {
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .context("Failed to build certificate verifier")?;
+        Ok(Self { inner, pins })
+    }
+
+    /// Whether the server's own certificate carries a pinned key
+    ///
+    /// Intermediates are not considered: the peer may send extra certificates
+    /// that play no part in the validated chain.
+    fn matches_pin(&self, end_entity: &CertificateDer<'_>) -> bool {
+        spki_sha256(end_entity).is_ok_and(|digest| self.pins.contains(&digest))
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        if self.matches_pin(end_entity) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate does not match any pinned public key".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_scheme() {
+        assert_eq!(select_scheme("http", None).unwrap(), "http");
+        assert_eq!(select_scheme("http", Some("HTTPS")).unwrap(), "https");
+        assert_eq!(select_scheme("https", Some("https")).unwrap(), "https");
+        assert!(select_scheme("https", Some("http")).is_err());
+        assert!(select_scheme("ftp", None).is_err());
+        assert!(select_scheme("http", Some("gopher")).is_err());
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let digest = [7u8; 32];
+        let pin = format!("sha256/{}", BASE64.encode(digest));
+        assert_eq!(parse_pin(&pin).unwrap(), digest);
+
+        assert!(parse_pin(&BASE64.encode(digest)).is_err());
+        assert!(parse_pin("sha256/not base64!").is_err());
+        assert!(parse_pin(&format!("sha256/{}", BASE64.encode([7u8; 16]))).is_err());
+    }
+
+    #[test]
+    fn test_spki_digest_of_generated_cert() {
+        use rcgen::PublicKeyData;
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["ota.local".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+
+        let expected: [u8; 32] = Sha256::digest(key.subject_public_key_info()).into();
+        assert_eq!(spki_sha256(cert.der()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_pin_matches_end_entity_only() {
+        let generate = || {
+            let key = rcgen::KeyPair::generate().unwrap();
+            rcgen::CertificateParams::new(vec!["ota.local".to_string()])
+                .unwrap()
+                .self_signed(&key)
+                .unwrap()
+        };
+        let server = generate();
+        let impostor = generate();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(server.der().clone()).unwrap();
+        let pins = vec![spki_sha256(server.der()).unwrap()];
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = PinnedCertVerifier::new(roots, pins, provider).unwrap();
+
+        assert!(verifier.matches_pin(server.der()));
+        // Sending the real server certificate as an unused extra doesn't help
+        assert!(!verifier.matches_pin(impostor.der()));
+    }
+
+    #[test]
+    fn test_client_certificate_identity_and_expiry() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec!["pi-0042".to_string()]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "pi-0042");
+        params.not_after = rcgen::date_time_ymd(2030, 1, 1);
+        let cert = params.self_signed(&key).unwrap();
+
+        let cert_path = temp_dir.path().join("client.pem");
+        let key_path = temp_dir.path().join("client.key");
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key.serialize_pem()).unwrap();
+
+        let mut config = OtaConfig::default();
+        config.tls.client_cert_path = Some(cert_path.to_string_lossy().to_string());
+        config.tls.client_key_path = Some(key_path.to_string_lossy().to_string());
+
+        let info = client_certificate(&config.tls).unwrap().unwrap();
+        assert_eq!(info.subject_cn.as_deref(), Some("pi-0042"));
+        assert_eq!(info.not_after.format("%Y-%m-%d").to_string(), "2030-01-01");
+        assert!(client_tls_config(&config.tls).unwrap().is_some());
+
+        assert_eq!(device_identity(&config).as_deref(), Some("pi-0042"));
+        config.device_id = Some("kitchen-display".to_string());
+        assert_eq!(device_identity(&config).as_deref(), Some("kitchen-display"));
+
+        let soon = info.not_after - chrono::Duration::days(10);
+        assert!(info.expiry_warning(30, soon).unwrap().contains("10 days"));
+        let later = info.not_after - chrono::Duration::days(90);
+        assert!(info.expiry_warning(30, later).is_none());
+        let after = info.not_after + chrono::Duration::days(1);
+        assert!(info.expiry_warning(30, after).unwrap().contains("expired"));
+
+        config.tls.client_key_path = None;
+        assert!(validate_client_identity(&config.tls).is_err());
+    }
+
+    #[test]
+    fn test_default_config_keeps_reqwest_tls() {
+        assert!(client_tls_config(&TlsConfig::default()).unwrap().is_none());
+
+        let missing_bundle = TlsConfig {
+            ca_bundle_path: Some("/nonexistent/ca.pem".to_string()),
+            ..TlsConfig::default()
+        };
+        assert!(client_tls_config(&missing_bundle).is_err());
+    }
+}
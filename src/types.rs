@@ -27,6 +27,375 @@ pub struct OtaConfig {
 
     /// Download timeout in seconds
     pub download_timeout_secs: u64,
+
+    /// Directory for persistent client state (installed version, etc.)
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+
+    /// Base64 Ed25519 public keys trusted to sign kernel images
+    #[serde(default)]
+    pub trusted_public_keys: Vec<String>,
+
+    /// Accept images without a signature (insecure, for development only)
+    #[serde(default)]
+    pub allow_unsigned: bool,
+
+    /// A/B slot installation; when unset the kernel is replaced in place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ab_slots: Option<AbSlotConfig>,
+
+    /// Confirmation of a new kernel after it boots
+    #[serde(default)]
+    pub boot_confirmation: BootConfirmationConfig,
+
+    /// Unix socket the daemon serves the control API on
+    #[serde(default = "default_control_socket_path")]
+    pub control_socket_path: String,
+
+    /// Prometheus metrics endpoint
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
+    /// When checks run and when updates may be installed
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+
+    /// URL scheme for the OTA server ("http" or "https"); mDNS may upgrade it
+    #[serde(default = "default_server_scheme")]
+    pub server_scheme: String,
+
+    /// Trust settings for HTTPS servers
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    /// Device identity for update records; defaults to the client certificate CN
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+
+    /// Release channel asked for when checking for updates
+    #[serde(default)]
+    pub channel: UpdateChannel,
+
+    /// Board model sent to the server; read from the device tree when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_model: Option<String>,
+
+    /// Board revision sent to the server; read from `/proc/cpuinfo` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware_revision: Option<String>,
+
+    /// Versioned kernel backups kept for rollback
+    #[serde(default)]
+    pub backups: BackupConfig,
+
+    /// Automatic installs from bundles on removable media
+    #[serde(default)]
+    pub bundle_watch: BundleWatchConfig,
+
+    /// When the daemon reboots into a newly installed kernel
+    #[serde(default)]
+    pub reboot: RebootConfig,
+
+    /// Commands run at stages of an install or rollback
+    #[serde(default)]
+    pub hooks: HooksConfig,
+}
+
+/// Release channel a device takes updates from
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+    Dev,
+}
+
+impl UpdateChannel {
+    /// Name sent to the server and used in the config file
+    pub fn name(self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+            UpdateChannel::Dev => "dev",
+        }
+    }
+}
+
+/// Shell commands run at stages of an install or rollback
+///
+/// Each command gets the version, paths and checksum in `OTA_*` environment
+/// variables. Only `before_backup` can stop an install; failures of the other
+/// hooks are logged.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Before anything is changed; a non-zero exit aborts the install
+    pub before_backup: Vec<String>,
+
+    /// Once the new kernel is written, before it is verified
+    pub after_install: Vec<String>,
+
+    /// Once the new kernel is verified and selected for the next boot
+    pub after_verification: Vec<String>,
+
+    /// When an install fails or is aborted, after it has been undone
+    pub on_failure: Vec<String>,
+
+    /// After a rollback restored an earlier kernel
+    pub on_rollback: Vec<String>,
+
+    /// Seconds each command may run before it is killed and counted as failed
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            before_backup: Vec::new(),
+            after_install: Vec::new(),
+            after_verification: Vec::new(),
+            on_failure: Vec::new(),
+            on_rollback: Vec::new(),
+            timeout_secs: 300,
+        }
+    }
+}
+
+/// When to reboot after the daemon installs a kernel
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RebootPolicy {
+    /// Leave the reboot to an operator
+    #[default]
+    Never,
+    /// Reboot as soon as the install finishes
+    Immediate,
+    /// Reboot when the next maintenance window opens
+    NextWindow,
+    /// Warn logged-in users, then reboot after `delay_secs`
+    Delayed,
+}
+
+/// Reboot policy and the conditions that hold a reboot back
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct RebootConfig {
+    pub policy: RebootPolicy,
+
+    /// Seconds between the warning broadcast and the reboot with the `delayed` policy
+    pub delay_secs: u64,
+
+    /// The reboot waits while any of these files exists
+    pub lock_files: Vec<String>,
+
+    /// Shell commands that must all exit 0 before rebooting
+    pub check_commands: Vec<String>,
+
+    /// Seconds a check command may run before it counts as blocking the reboot
+    pub check_timeout_secs: u64,
+
+    /// Wait while a systemd inhibitor lock blocks shutdown
+    pub systemd_inhibitors: bool,
+
+    /// Seconds between attempts while the reboot is inhibited
+    pub inhibitor_retry_secs: u64,
+}
+
+impl Default for RebootConfig {
+    fn default() -> Self {
+        Self {
+            policy: RebootPolicy::Never,
+            delay_secs: 300,
+            lock_files: Vec::new(),
+            check_commands: Vec::new(),
+            check_timeout_secs: 60,
+            systemd_inhibitors: true,
+            inhibitor_retry_secs: 60,
+        }
+    }
+}
+
+/// Mount point watched by the daemon for signed update bundles
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct BundleWatchConfig {
+    /// Install bundles found at `path` without a server
+    pub enabled: bool,
+
+    /// Directory holding `metadata.json`, the kernel and its artifacts once mounted
+    pub path: String,
+
+    /// Seconds between looks at `path`
+    pub scan_interval_secs: u64,
+}
+
+impl Default for BundleWatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "/media/ota".to_string(),
+            scan_interval_secs: 30,
+        }
+    }
+}
+
+/// Versioned backup store settings
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Directory for versioned backups; defaults to `kernel_backups` next to `backup_path`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<String>,
+
+    /// Number of backups kept, newest first
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            keep: 3,
+        }
+    }
+}
+
+/// TLS trust and client identity settings
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM bundle of private CAs trusted instead of the built-in roots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle_path: Option<String>,
+
+    /// `sha256/<base64>` SubjectPublicKeyInfo pins; one must match the server certificate
+    pub spki_pins: Vec<String>,
+
+    /// PEM client certificate chain presented to the server (mutual TLS)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert_path: Option<String>,
+
+    /// PEM private key for the client certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key_path: Option<String>,
+
+    /// Warn when the client certificate expires within this many days
+    pub cert_expiry_warning_days: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_bundle_path: None,
+            spki_pins: Vec::new(),
+            client_cert_path: None,
+            client_key_path: None,
+            cert_expiry_warning_days: 30,
+        }
+    }
+}
+
+/// Update scheduling settings
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Random delay of up to this many seconds added to every scheduled check
+    pub jitter_secs: u64,
+
+    /// Windows in which downloads and installs may run; empty means any time
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+}
+
+/// Recurring local-time window in which updates may be applied
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct MaintenanceWindow {
+    /// Weekdays the window opens on ("mon", "tue", ...); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+
+    /// Local opening time, "HH:MM"
+    pub start: String,
+
+    /// Local closing time, "HH:MM"; earlier than `start` means the next day
+    pub end: String,
+}
+
+/// Prometheus metrics endpoint settings
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Serve metrics over HTTP
+    pub enabled: bool,
+
+    /// Address the metrics listener binds to
+    pub listen_address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "0.0.0.0:9110".to_string(),
+        }
+    }
+}
+
+/// Post-reboot boot confirmation settings
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct BootConfirmationConfig {
+    /// Write a confirmation marker at install time and check it on startup
+    pub enabled: bool,
+
+    /// Boots allowed without confirmation before reverting
+    pub max_boot_attempts: u32,
+
+    /// Shell commands that must all exit 0 for the boot to be confirmed
+    pub health_checks: Vec<String>,
+
+    /// Time allowed for the health checks to pass after startup
+    pub health_check_deadline_secs: u64,
+
+    /// Require `uname -r` to contain the installed version
+    pub verify_kernel_release: bool,
+}
+
+impl Default for BootConfirmationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_boot_attempts: 3,
+            health_checks: Vec::new(),
+            health_check_deadline_secs: 300,
+            verify_kernel_release: false,
+        }
+    }
+}
+
+/// A/B kernel slot configuration
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct AbSlotConfig {
+    /// Kernel image for slot A
+    pub slot_a_path: String,
+
+    /// Kernel image for slot B
+    pub slot_b_path: String,
+
+    /// Boot config whose `kernel=` line selects the active slot
+    pub boot_config_path: String,
+}
+
+fn default_state_path() -> String {
+    "/var/lib/ota-client".to_string()
+}
+
+fn default_control_socket_path() -> String {
+    "/run/ota-client/control.sock".to_string()
+}
+
+fn default_server_scheme() -> String {
+    "http".to_string()
 }
 
 impl Default for OtaConfig {
@@ -40,6 +409,24 @@ impl Default for OtaConfig {
             mdns_service: "_ota._tcp.local".to_string(),
             fallback_server: None,
             download_timeout_secs: 90, // 90 seconds
+            state_path: default_state_path(),
+            trusted_public_keys: Vec::new(),
+            allow_unsigned: false,
+            ab_slots: None,
+            boot_confirmation: BootConfirmationConfig::default(),
+            control_socket_path: default_control_socket_path(),
+            metrics: MetricsConfig::default(),
+            schedule: ScheduleConfig::default(),
+            server_scheme: default_server_scheme(),
+            tls: TlsConfig::default(),
+            device_id: None,
+            channel: UpdateChannel::default(),
+            device_model: None,
+            hardware_revision: None,
+            backups: BackupConfig::default(),
+            bundle_watch: BundleWatchConfig::default(),
+            reboot: RebootConfig::default(),
+            hooks: HooksConfig::default(),
         }
     }
 }
@@ -49,6 +436,20 @@ impl Default for OtaConfig {
 pub struct ServerInfo {
     pub address: SocketAddr,
     pub name: String,
+    /// URL scheme to reach the server with
+    pub scheme: String,
+    /// Host name put in URLs so TLS can use SNI; the IP is used when unset
+    pub hostname: Option<String>,
+}
+
+impl ServerInfo {
+    /// `scheme://host:port` prefix for requests to this server
+    pub fn base_url(&self) -> String {
+        match &self.hostname {
+            Some(hostname) => format!("{}://{}:{}", self.scheme, hostname, self.address.port()),
+            None => format!("{}://{}", self.scheme, self.address),
+        }
+    }
 }
 
 /// Kernel metadata from server
@@ -61,10 +462,65 @@ pub struct KernelMetadata {
     pub release_date: String,
     pub description: String,
     pub download_url: String,
+    /// Base64 Ed25519 signature over the version and checksum
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Patch that rebuilds this image from the installed one, if the server has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<DeltaPatch>,
+    /// Files installed together with the kernel (DTBs, overlays, modules)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<Artifact>,
+    /// Monotonic anti-rollback version; images below the device's counter are refused
+    #[serde(default)]
+    pub security_version: u64,
+}
+
+/// Additional file or directory tree shipped with a kernel
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Artifact {
+    /// Unique name, also used for the download file name
+    pub name: String,
+    pub download_url: String,
+    /// Where the file or extracted tree ends up on the device
+    pub destination: String,
+    pub file_size: u64,
+    pub checksum: String,
+    /// Octal permission bits, e.g. "0644"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub install_type: ArtifactInstallType,
+}
+
+/// How an artifact is placed at its destination
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactInstallType {
+    /// Copied to the destination as-is
+    #[default]
+    File,
+    /// Tar archive (optionally zstd-compressed) extracted to a destination directory
+    Archive,
+}
+
+/// Binary delta from an installed image to the advertised one
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct DeltaPatch {
+    /// Patch format; only "zstd" (`zstd --patch-from`) is supported
+    pub format: String,
+    /// Version the patch applies to
+    pub base_version: String,
+    /// Checksum of the image the patch applies to
+    pub base_checksum: String,
+    pub download_url: String,
+    pub file_size: u64,
+    /// Checksum of the patch file itself
+    pub checksum: String,
 }
 
 /// Download progress information
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DownloadProgress {
     pub downloaded: u64,
     pub total: u64,
@@ -75,14 +531,23 @@ pub struct DownloadProgress {
 #[derive(Debug, PartialEq)]
 pub enum OtaResult {
     NoUpdate,
-    UpdateAvailable(KernelMetadata),
+    UpdateAvailable(Box<KernelMetadata>),
     UpdateDownloaded(String), // file path
     UpdateInstalled,
+    /// A dry run found an update and planned its install
+    DryRun(Box<crate::installer::InstallPlan>),
     Error(String),
 }
 
+/// Dry run settings: check for an update and plan its install without installing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct DryRun {
+    /// Also download the image and verify it against the metadata
+    pub download: bool,
+}
+
 /// Daemon state for monitoring and control
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DaemonState {
     Starting,
     Idle,
@@ -103,18 +568,23 @@ pub struct UpdateRecord {
     pub status: UpdateStatus,
     pub error_message: Option<String>,
     pub duration_seconds: u64,
+    /// Device the update ran on (configured ID or client certificate CN)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
 }
 
 /// Update operation status
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
 pub enum UpdateStatus {
     Success,
     Failed,
     RolledBack,
+    /// A dry run planned the install; nothing was installed
+    DryRun,
 }
 
 /// Daemon status for external monitoring
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DaemonStatus {
     pub current_state: DaemonState,
     pub last_check: Option<chrono::DateTime<chrono::Utc>>,
@@ -129,16 +599,35 @@ pub struct DaemonStatus {
 #[command(name = "ota-client")]
 #[command(about = "OTA Client for Raspberry Pi kernel updates")]
 pub struct Cli {
+    /// Result format on standard output; logs always go to standard error
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+/// Format of command results
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable log lines
+    Text,
+    /// One JSON document on standard output
+    Json,
+}
+
 #[derive(Debug, clap::Subcommand)]
 pub enum Commands {
     /// Run as daemon (background service)
     Daemon {
         #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
         config: String,
+        /// Check and plan updates without installing them
+        #[arg(long)]
+        dry_run: bool,
+        /// With --dry-run, also download and verify each image
+        #[arg(long, requires = "dry_run")]
+        download: bool,
     },
     /// Check for updates once
     Check {
@@ -149,6 +638,12 @@ pub enum Commands {
     Update {
         #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
         config: String,
+        /// Check and plan the update without installing it
+        #[arg(long)]
+        dry_run: bool,
+        /// With --dry-run, also download and verify the image
+        #[arg(long, requires = "dry_run")]
+        download: bool,
     },
     /// Show current status
     Status {
@@ -159,9 +654,79 @@ pub enum Commands {
     Rollback {
         #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
         config: String,
+        /// Version to restore from the backup store instead of the previous one
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Install a kernel from a local bundle or image, without a server
+    Install {
+        #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
+        config: String,
+        /// Bundle directory or tar archive with metadata.json, the kernel and its artifacts
+        #[arg(required_unless_present = "image", conflicts_with = "image")]
+        bundle: Option<String>,
+        /// Kernel image to install
+        #[arg(long, requires = "metadata")]
+        image: Option<String>,
+        /// Metadata for the image; artifacts are looked up next to it
+        #[arg(long, requires = "image")]
+        metadata: Option<String>,
+    },
+    /// Show, set or clear the update pin kept in the state directory
+    Pin {
+        #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
+        config: String,
+        /// Only install this exact version
+        #[arg(conflicts_with_all = ["hold", "clear"])]
+        version: Option<String>,
+        /// Install no updates until the pin is cleared
+        #[arg(long, conflicts_with = "clear")]
+        hold: bool,
+        /// Take updates from the configured channel again
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Show, export or clear the update history
+    History {
+        #[arg(short, long, default_value = "/etc/ota-client/config.toml")]
+        config: String,
+        /// Only updates that ended with this status
+        #[arg(long, value_enum)]
+        status: Option<UpdateStatus>,
+        /// Only updates to this version
+        #[arg(long)]
+        version: Option<String>,
+        /// Only updates at or after this time (RFC 3339 or YYYY-MM-DD)
+        #[arg(long, value_parser = crate::history::parse_since)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        /// Only updates at or before this time (RFC 3339 or YYYY-MM-DD, whole day)
+        #[arg(long, value_parser = crate::history::parse_until)]
+        until: Option<chrono::DateTime<chrono::Utc>>,
+        /// Show at most this many of the most recent updates
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+        format: HistoryFormat,
+        /// Write the selected records to this file instead of standard output
+        #[arg(long)]
+        export: Option<String>,
+        /// Delete the whole history once it has been shown or exported; not
+        /// allowed with a filter, which would suggest only the selection goes
+        #[arg(long, conflicts_with_all = ["status", "version", "since", "until", "limit"])]
+        clear: bool,
     },
 }
 
+/// Output format of `ota-client history`
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum HistoryFormat {
+    /// Aligned columns for reading
+    Table,
+    /// One JSON record per line
+    Jsonl,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,6 +743,18 @@ mod tests {
         assert_eq!(config.mdns_service, "_ota._tcp.local");
         assert_eq!(config.download_timeout_secs, 90);
         assert!(config.fallback_server.is_none());
+        assert_eq!(config.state_path, "/var/lib/ota-client");
+        assert!(config.trusted_public_keys.is_empty());
+        assert!(!config.allow_unsigned);
+        assert_eq!(config.control_socket_path, "/run/ota-client/control.sock");
+        assert!(!config.metrics.enabled);
+        assert!(config.schedule.maintenance_windows.is_empty());
+        assert_eq!(config.server_scheme, "http");
+        assert!(config.tls.ca_bundle_path.is_none());
+        assert!(config.tls.spki_pins.is_empty());
+        assert!(config.tls.client_cert_path.is_none());
+        assert_eq!(config.tls.cert_expiry_warning_days, 30);
+        assert!(config.device_id.is_none());
     }
 
     #[test]
@@ -195,12 +772,19 @@ mod tests {
     #[test]
     fn test_server_info_creation() {
         let addr = std::net::SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 100)), 8080);
-        let server = ServerInfo {
+        let mut server = ServerInfo {
             address: addr,
             name: "test-server".to_string(),
+            scheme: "http".to_string(),
+            hostname: None,
         };
         assert_eq!(server.address.port(), 8080);
         assert_eq!(server.name, "test-server");
+        assert_eq!(server.base_url(), "http://192.168.1.100:8080");
+
+        server.scheme = "https".to_string();
+        server.hostname = Some("ota.local".to_string());
+        assert_eq!(server.base_url(), "https://ota.local:8080");
     }
 
     #[test]
@@ -213,6 +797,10 @@ mod tests {
             release_date: "2025-06-16".to_string(),
             description: "Test kernel".to_string(),
             download_url: "/kernels/kernel-v1.0.0.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
         };
 
         assert_eq!(metadata.latest_version, "1.0.0");
@@ -267,6 +855,10 @@ mod tests {
             release_date: "2025-06-16T10:30:00Z".to_string(),
             description: "Updated kernel with fixes".to_string(),
             download_url: "/kernels/kernel-v1.0.1.img".to_string(),
+            signature: None,
+            delta: None,
+            artifacts: Vec::new(),
+            security_version: 0,
         };
 
         let json = serde_json::to_string(&metadata).unwrap();
@@ -279,12 +871,13 @@ mod tests {
 
     #[test]
     fn test_config_validation_bounds() {
-        let mut config = OtaConfig::default();
-
         // Test minimum values
-        config.check_interval_minutes = 1;
-        config.max_retries = 1;
-        config.download_timeout_secs = 1;
+        let config = OtaConfig {
+            check_interval_minutes: 1,
+            max_retries: 1,
+            download_timeout_secs: 1,
+            ..OtaConfig::default()
+        };
 
         assert!(config.check_interval_minutes > 0);
         assert!(config.max_retries > 0);
@@ -299,4 +892,23 @@ mod tests {
         // We can add more specific CLI parsing tests when implementing main.rs
         assert_eq!(std::mem::size_of::<Cli>(), std::mem::size_of::<Cli>());
     }
+
+    #[test]
+    fn test_history_clear_rejects_filters() {
+        use clap::Parser;
+
+        assert!(Cli::try_parse_from(["ota-client", "history", "--clear"]).is_ok());
+        for filter in [
+            &["--status", "failed"][..],
+            &["--version", "1.0.0"],
+            &["--since", "2025-01-01"],
+            &["--until", "2025-01-01"],
+            &["--limit", "5"],
+        ] {
+            let args = ["ota-client", "history", "--clear"]
+                .into_iter()
+                .chain(filter.iter().copied());
+            assert!(Cli::try_parse_from(args).is_err(), "{:?}", filter);
+        }
+    }
 }
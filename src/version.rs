@@ -0,0 +1,183 @@
+use crate::durable::{read_json, write_json};
+use crate::types::OtaConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Persisted record of the kernel currently installed on the device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledVersion {
+    pub version: String,
+    pub checksum: String,
+    pub installed_at: chrono::DateTime<chrono::Utc>,
+    /// Version held in the backup slot, restored by rollback
+    pub previous_version: Option<String>,
+}
+
+/// Path of the installed version record for a configuration
+pub fn installed_version_path(config: &OtaConfig) -> PathBuf {
+    Path::new(&config.state_path).join("installed_version.json")
+}
+
+/// Load the installed version record, if one has been written
+pub async fn load_installed_version(config: &OtaConfig) -> Result<Option<InstalledVersion>> {
+    let Some(record): Option<InstalledVersion> =
+        read_json(&installed_version_path(config), "installed version").await?
+    else {
+        return Ok(None);
+    };
+
+    debug!("Loaded installed version: {}", record.version);
+    Ok(Some(record))
+}
+
+/// Persist the installed version record
+pub async fn save_installed_version(config: &OtaConfig, record: &InstalledVersion) -> Result<()> {
+    write_json(&installed_version_path(config), record, "installed version").await?;
+
+    info!("Recorded installed version: {}", record.version);
+    Ok(())
+}
+
+/// Remove the installed version record
+pub async fn clear_installed_version(config: &OtaConfig) -> Result<()> {
+    let path = installed_version_path(config);
+    if path.exists() {
+        tokio::fs::remove_file(&path)
+            .await
+            .with_context(|| format!("Failed to remove installed version: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Compare two version strings.
+///
+/// Both sides are parsed as semver first (an optional leading `v` is ignored).
+/// If either side is not valid semver, a natural ordering is used instead:
+/// numeric runs compare by value and everything else compares lexically.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    match (parse_semver(a), parse_semver(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => natural_cmp(a.trim(), b.trim()),
+    }
+}
+
+/// Whether `candidate` is strictly newer than `current`
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    compare_versions(candidate, current) == Ordering::Greater
+}
+
+fn parse_semver(version: &str) -> Option<semver::Version> {
+    let trimmed = version.trim();
+    let trimmed = trimmed
+        .strip_prefix('v')
+        .or_else(|| trimmed.strip_prefix('V'))
+        .unwrap_or(trimmed);
+    semver::Version::parse(trimmed).ok()
+}
+
+/// Split a version string into alternating numeric and non-numeric segments
+fn segments(version: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let bytes = version.as_bytes();
+
+    for i in 1..=bytes.len() {
+        if i == bytes.len() || bytes[i].is_ascii_digit() != bytes[start].is_ascii_digit() {
+            segments.push(&version[start..i]);
+            start = i;
+        }
+    }
+
+    segments
+}
+
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let a_segments = segments(a);
+    let b_segments = segments(b);
+
+    for (a_seg, b_seg) in a_segments.iter().zip(b_segments.iter()) {
+        let a_numeric = a_seg.as_bytes()[0].is_ascii_digit();
+        let b_numeric = b_seg.as_bytes()[0].is_ascii_digit();
+
+        let ordering = match (a_numeric, b_numeric) {
+            (true, true) => {
+                let a_trimmed = a_seg.trim_start_matches('0');
+                let b_trimmed = b_seg.trim_start_matches('0');
+                a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+            }
+            // Numbers sort before text so "1.0" < "1.a"
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => a_seg.cmp(b_seg),
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    a_segments.len().cmp(&b_segments.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_semver_comparison() {
+        assert_eq!(compare_versions("1.0.0", "1.0.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.10.0", "1.9.0"), Ordering::Greater);
+        assert_eq!(compare_versions("v2.0.0", "2.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0.0-rc.1", "1.0.0"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_fallback_comparison() {
+        assert_eq!(compare_versions("6.1.21-v8", "6.1.9-v8"), Ordering::Greater);
+        assert_eq!(
+            compare_versions("build-20250616", "build-20250701"),
+            Ordering::Less
+        );
+        assert_eq!(compare_versions("1.2", "1.2.1"), Ordering::Less);
+        assert_eq!(compare_versions("1.02", "1.2"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("1.0.1", "1.0.0"));
+        assert!(!is_newer("1.0.0", "1.0.0"));
+        assert!(!is_newer("0.9.0", "1.0.0"));
+    }
+
+    #[tokio::test]
+    async fn test_installed_version_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = OtaConfig {
+            state_path: temp_dir.path().to_string_lossy().to_string(),
+            ..OtaConfig::default()
+        };
+
+        assert!(load_installed_version(&config).await.unwrap().is_none());
+
+        let record = InstalledVersion {
+            version: "1.0.0".to_string(),
+            checksum: "sha256:abc123".to_string(),
+            installed_at: chrono::Utc::now(),
+            previous_version: None,
+        };
+        save_installed_version(&config, &record).await.unwrap();
+
+        let loaded = load_installed_version(&config).await.unwrap().unwrap();
+        assert_eq!(loaded, record);
+
+        clear_installed_version(&config).await.unwrap();
+        assert!(load_installed_version(&config).await.unwrap().is_none());
+    }
+}